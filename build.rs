@@ -0,0 +1,82 @@
+//! Bakes build information into the binary for `commands::version`
+//!
+//! Sets `GC_GIT_COMMIT` to the short hash of the commit being built, when
+//! building from a git checkout with git installed, and `GC_BUILD_DATE` to
+//! the day of the build. Either is left unset if it can't be found, and
+//! the version is reported without it.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    if let Some(commit) = git_commit() {
+        println!("cargo:rustc-env=GC_GIT_COMMIT={}", commit);
+    }
+    if let Some(date) = build_date() {
+        println!("cargo:rustc-env=GC_BUILD_DATE={}", date);
+    }
+
+    // Pick up new commits, but don't rebuild every time outside a checkout.
+    // A commit moves the branch HEAD points to, not HEAD itself.
+    println!("cargo:rerun-if-changed=build.rs");
+    let branch = head_ref().map(|head| format!(".git/{}", head));
+    for path in [".git/HEAD", ".git/packed-refs"]
+        .iter()
+        .map(|&path| path.to_owned())
+        .chain(branch)
+    {
+        if Path::new(&path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
+
+/// The ref HEAD points to, like `refs/heads/master`, unless HEAD is
+/// detached or this isn't a git checkout
+fn head_ref() -> Option<String> {
+    let head = fs::read_to_string(".git/HEAD").ok()?;
+    head.trim().splitn(2, "ref: ").nth(1).map(str::to_owned)
+}
+
+/// Short hash of the commit being built, if this is a git checkout
+fn git_commit() -> Option<String> {
+    let output = Command::new("git")
+        .args(&["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let commit = String::from_utf8(output.stdout).ok()?;
+    let commit = commit.trim();
+    if commit.is_empty() {
+        None
+    } else {
+        Some(commit.to_owned())
+    }
+}
+
+/// Today's date in UTC, like `2019-03-01`
+fn build_date() -> Option<String> {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let (year, month, day) = civil_date((secs / 86_400) as i64);
+    Some(format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+/// The year, month and day `days` days after 1970-01-01
+///
+/// From Howard Hinnant's `civil_from_days`.
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,309 @@
+//! Bookkeeping for the wings assigned to each player
+//!
+//! `Server` spawns and recalls the wingmen, but the counting is done
+//! here, without a connection, so the limits can be tested on their own.
+
+use crate::wing::{Flag, Order};
+
+use airmash_protocol::Player;
+
+use std::collections::HashMap;
+use std::sync::mpsc;
+
+/// A wingman's control flag, and the sender that changes its orders
+/// while it's in the air
+pub type Wing = (Flag, mpsc::Sender<Order>);
+
+/// The wingmen assigned to each player, and the server-wide limits on them
+pub struct Assignments {
+    /// Each player's wingmen, oldest first
+    wings: HashMap<Player, Vec<Wing>>,
+    /// Maximum number of wingmen across all players
+    max_total: usize,
+    /// Maximum number of wingmen across all players on one team, if limited
+    max_team: Option<usize>,
+}
+
+impl Assignments {
+    /// Create an empty set of assignments with the given limits
+    pub fn new(max_total: usize, max_team: Option<usize>) -> Self {
+        Assignments {
+            wings: HashMap::new(),
+            max_total,
+            max_team,
+        }
+    }
+
+    /// Number of wingmen assigned to the player
+    pub fn count(&self, id: Player) -> usize {
+        self.wings.get(&id).map(Vec::len).unwrap_or(0)
+    }
+
+    /// Number of wingmen assigned across all players
+    pub fn total(&self) -> usize {
+        self.wings.values().map(Vec::len).sum()
+    }
+
+    /// Number of players with wingmen
+    pub fn players(&self) -> usize {
+        self.wings.len()
+    }
+
+    /// True if the player has any wingmen
+    pub fn contains(&self, id: Player) -> bool {
+        self.wings.contains_key(&id)
+    }
+
+    /// Each player with wingmen, and how many they have
+    pub fn iter(&self) -> impl Iterator<Item = (Player, usize)> + '_ {
+        self.wings.iter().map(|(&id, flags)| (id, flags.len()))
+    }
+
+    /// True if `wings` more wingmen for player `id` would stay within the total
+    /// and per-team limits
+    ///
+    /// `team` looks up a player's team; players whose team is unknown don't
+    /// count against any team's limit.
+    pub fn has_capacity<T, F>(&self, id: Player, wings: u8, team: F) -> bool
+    where
+        T: PartialEq,
+        F: Fn(Player) -> Option<T>,
+    {
+        if self.total() + usize::from(wings) > self.max_total {
+            return false;
+        }
+
+        let max_team = match self.max_team {
+            Some(max_team) => max_team,
+            None => return true,
+        };
+        let own_team = match team(id) {
+            Some(own_team) => own_team,
+            None => return true,
+        };
+        let team_total: usize = self
+            .wings
+            .iter()
+            .filter(|(&owner, _)| team(owner).as_ref() == Some(&own_team))
+            .map(|(_, flags)| flags.len())
+            .sum();
+        team_total + usize::from(wings) <= max_team
+    }
+
+    /// Assign more wingmen to the player, after any they already have
+    pub fn add(&mut self, id: Player, wings: Vec<Wing>) {
+        if wings.is_empty() {
+            return;
+        }
+        self.wings.entry(id).or_insert_with(Vec::new).extend(wings);
+    }
+
+    /// Send an order to each of the player's wingmen
+    ///
+    /// Wingmen that have shut down no longer listen; `prune` forgets them.
+    pub fn order(&self, id: Player, order: &Order) {
+        for (_, control) in self.wings.get(&id).into_iter().flatten() {
+            let _ = control.send(order.clone());
+        }
+    }
+
+    /// Move every wingman assigned to player `from` over to player `to`,
+    /// after any `to` already has
+    ///
+    /// Used when a player rejoins under a new ID.
+    pub fn transfer(&mut self, from: Player, to: Player) {
+        if let Some(wings) = self.wings.remove(&from) {
+            self.add(to, wings);
+        }
+    }
+
+    /// Recall up to `wings` of the player's wingmen, most recent first
+    ///
+    /// Returns the number recalled.
+    pub fn remove(&mut self, id: Player, wings: u8) -> usize {
+        let (removed, remaining) = match self.wings.get_mut(&id) {
+            None => return 0,
+            Some(flags) => {
+                let keep = flags.len().saturating_sub(usize::from(wings));
+                let removed = flags.len() - keep;
+                // Dropping the flags shuts down the wingmen
+                flags.truncate(keep);
+                (removed, flags.len())
+            }
+        };
+        if remaining == 0 {
+            self.wings.remove(&id);
+        }
+        removed
+    }
+
+    /// Recall all of the player's wingmen
+    ///
+    /// Returns the number recalled.
+    pub fn clear(&mut self, id: Player) -> usize {
+        self.wings.remove(&id).map_or(0, |flags| flags.len())
+    }
+
+    /// Recall every player's wingmen
+    ///
+    /// Returns the number recalled.
+    pub fn clear_all(&mut self) -> usize {
+        let total = self.total();
+        self.wings.clear();
+        total
+    }
+
+    /// Forget wingmen that have shut down on their own
+    pub fn prune(&mut self) {
+        for wings in self.wings.values_mut() {
+            wings.retain(|(flag, _)| !flag.read());
+        }
+        self.wings.retain(|_, wings| !wings.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wingmen that nobody is listening for orders on
+    fn flags(wings: usize) -> Vec<Wing> {
+        (0..wings)
+            .map(|_| (Flag::default(), mpsc::channel().0))
+            .collect()
+    }
+
+    fn no_teams(_: Player) -> Option<u16> {
+        None
+    }
+
+    #[test]
+    fn add_top_up_and_clear() {
+        let xplay = Player(1);
+        let mut assignments = Assignments::new(4, None);
+
+        assert!(assignments.has_capacity(xplay, 3, no_teams));
+        assignments.add(xplay, flags(3));
+        assert_eq!(assignments.count(xplay), 3);
+
+        // Topping up by 2 would go over the total
+        assert!(!assignments.has_capacity(xplay, 2, no_teams));
+        assert!(assignments.has_capacity(xplay, 1, no_teams));
+        assignments.add(xplay, flags(1));
+        assert_eq!(assignments.count(xplay), 4);
+
+        assert_eq!(assignments.clear(xplay), 4);
+        assert_eq!(assignments.count(xplay), 0);
+        assert_eq!(assignments.total(), 0);
+        assert!(!assignments.contains(xplay));
+        assert!(assignments.has_capacity(xplay, 4, no_teams));
+    }
+
+    #[test]
+    fn remove_some_then_the_rest() {
+        let xplay = Player(1);
+        let detect = Player(2);
+        let mut assignments = Assignments::new(40, None);
+        assignments.add(xplay, flags(5));
+        assignments.add(detect, flags(2));
+
+        assert_eq!(assignments.remove(xplay, 3), 3);
+        assert_eq!(assignments.count(xplay), 2);
+        assert_eq!(assignments.total(), 4);
+
+        // Asking for more than remain recalls what's left
+        assert_eq!(assignments.remove(xplay, 5), 2);
+        assert!(!assignments.contains(xplay));
+        assert_eq!(assignments.remove(xplay, 1), 0);
+        assert_eq!(assignments.players(), 1);
+        assert_eq!(assignments.clear_all(), 2);
+        assert_eq!(assignments.players(), 0);
+    }
+
+    #[test]
+    fn team_limit() {
+        let team = |id: Player| Some(if id.0 < 10 { "blue" } else { "red" });
+        let mut assignments = Assignments::new(40, Some(3));
+        assignments.add(Player(1), flags(2));
+
+        assert!(assignments.has_capacity(Player(2), 1, team));
+        assert!(!assignments.has_capacity(Player(2), 2, team));
+        assert!(assignments.has_capacity(Player(11), 3, team));
+    }
+
+    #[test]
+    fn transfer_keeps_wingmen_flying() {
+        let mut assignments = Assignments::new(40, None);
+        let wingmen = flags(2);
+        let copies: Vec<Flag> = wingmen.iter().map(|(flag, _)| flag.clone()).collect();
+        assignments.add(Player(1), wingmen);
+        assignments.add(Player(2), flags(1));
+
+        assignments.transfer(Player(1), Player(2));
+        assert!(!assignments.contains(Player(1)));
+        assert_eq!(assignments.count(Player(2)), 3);
+        assert_eq!(assignments.total(), 3);
+        assert!(copies.iter().all(|flag| !flag.read()));
+
+        // Nothing to move
+        assignments.transfer(Player(1), Player(3));
+        assert!(!assignments.contains(Player(3)));
+    }
+
+    #[test]
+    fn prune_forgets_stopped_wingmen() {
+        let xplay = Player(1);
+        let mut assignments = Assignments::new(40, None);
+        let mut wingmen = flags(2);
+        // The wingman's copy of its flag is dropped as it stops
+        drop(wingmen[0].0.clone());
+        let stopped = wingmen.remove(0);
+        assignments.add(xplay, vec![stopped]);
+        assignments.add(xplay, wingmen);
+
+        assignments.prune();
+        assert_eq!(assignments.count(xplay), 1);
+    }
+
+    #[test]
+    fn orders_reach_wingmen_left_after_pruning_and_removing() {
+        let xplay = Player(1);
+        let mut assignments = Assignments::new(40, None);
+        let (wingmen, receivers): (Vec<Wing>, Vec<_>) = (0..3)
+            .map(|_| {
+                let (control, receiver) = mpsc::channel();
+                ((Flag::default(), control), receiver)
+            })
+            .unzip();
+        // The middle wingman stops on its own
+        drop(wingmen[1].0.clone());
+        assignments.add(xplay, wingmen);
+
+        assignments.prune();
+        assert_eq!(assignments.remove(xplay, 1), 1);
+        let order = Order::Retarget("detect".to_owned());
+        assignments.order(xplay, &order);
+        assert_eq!(receivers[0].try_recv(), Ok(order));
+        assert!(receivers[1].try_recv().is_err());
+        assert!(receivers[2].try_recv().is_err());
+    }
+
+    #[test]
+    fn clear_all_stops_every_wingman() {
+        let mut assignments = Assignments::new(40, None);
+        let xplay = flags(3);
+        let detect = flags(2);
+        // The wingmen keep their own copies of their flags
+        let copies: Vec<Flag> = xplay
+            .iter()
+            .chain(&detect)
+            .map(|(flag, _)| flag.clone())
+            .collect();
+        assignments.add(Player(1), xplay);
+        assignments.add(Player(2), detect);
+
+        assert!(copies.iter().all(|flag| !flag.read()));
+        assert_eq!(assignments.clear_all(), 5);
+        assert!(copies.iter().all(Flag::read));
+    }
+}
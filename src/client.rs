@@ -0,0 +1,272 @@
+//! The parts of a game client that ground control uses
+//!
+//! `Server` talks to the game through `GameClient` rather than using
+//! `ClientBase` directly, so its event loop can be tested against a
+//! scripted `FakeClient` instead of a real connection.
+
+use crate::net;
+use crate::server::{self, Shutdown};
+
+use airmash_client::{ClientBase, ClientEvent};
+use airmash_protocol as protocol;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::time;
+use url::Url;
+
+/// A request to the game server, finished once it's been sent
+pub type ClientFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, String>> + Send + 'a>>;
+
+/// A connection to the game, as ground control sees it
+pub trait GameClient: Sized + Send + 'static {
+    /// Name of the player with ID `id`, if they're in the game
+    fn player_name(&self, id: u16) -> Option<String>;
+
+    /// ID of the player named `name`, if they're in the game
+    fn player_id(&self, name: &str) -> Option<u16>;
+
+    /// Team of the player with ID `id`, if they're in the game
+    fn player_team(&self, id: u16) -> Option<protocol::Team>;
+
+    /// Names of everyone in the game, including ourselves
+    fn player_names(&self) -> Vec<String>;
+
+    /// Our latest ping to the server, in milliseconds
+    fn ping(&self) -> u16;
+
+    /// The next event from the server, or `None` once the connection closes
+    fn next(&mut self) -> ClientFuture<'_, Option<ClientEvent>>;
+
+    /// Say `text` in public chat
+    fn chat(&mut self, text: String) -> ClientFuture<'_, ()>;
+
+    /// Whisper `text` to the player with ID `id`
+    fn whisper(&mut self, id: protocol::Player, text: String) -> ClientFuture<'_, ()>;
+
+    /// Send the server command `com`, like `spectate`, with `data`
+    fn command(&mut self, com: &str, data: String) -> ClientFuture<'_, ()>;
+
+    /// Connect again after losing the connection, making up to `attempts`
+    /// attempts and doubling `backoff` between them, as `limiter` allows
+    ///
+    /// Returns `None` if every attempt failed or shutdown was requested.
+    fn reconnect(
+        url: Url,
+        ctrl_name: String,
+        login: net::LoginOptions,
+        attempts: u32,
+        backoff: time::Duration,
+        limiter: net::ReconnectLimiter,
+        shutdown: Shutdown,
+    ) -> Pin<Box<dyn Future<Output = Option<Self>> + Send>>;
+}
+
+/// Calls into `ClientBase` itself, out of reach of `GameClient`'s methods
+/// of the same names
+mod base {
+    use airmash_client::{ClientBase, ClientEvent};
+    use airmash_protocol as protocol;
+
+    pub async fn next(client: &mut ClientBase) -> Result<Option<ClientEvent>, String> {
+        await!(client.next()).map_err(|err| err.to_string())
+    }
+
+    pub async fn chat(client: &mut ClientBase, text: String) -> Result<(), String> {
+        await!(client.chat(text)).map_err(|err| err.to_string())
+    }
+
+    pub async fn send<P>(client: &mut ClientBase, packet: P) -> Result<(), String>
+    where
+        P: Into<protocol::ClientPacket>,
+    {
+        await!(client.send(packet)).map_err(|err| err.to_string())
+    }
+}
+
+impl GameClient for ClientBase {
+    fn player_name(&self, id: u16) -> Option<String> {
+        self.world
+            .players
+            .get(&id)
+            .map(|player| player.name.clone())
+    }
+
+    fn player_id(&self, name: &str) -> Option<u16> {
+        self.world.names.get(name).cloned()
+    }
+
+    fn player_team(&self, id: u16) -> Option<protocol::Team> {
+        self.world.players.get(&id).map(|player| player.team)
+    }
+
+    fn player_names(&self) -> Vec<String> {
+        self.world
+            .players
+            .values()
+            .map(|player| player.name.clone())
+            .collect()
+    }
+
+    fn ping(&self) -> u16 {
+        self.world.ping
+    }
+
+    fn next(&mut self) -> ClientFuture<'_, Option<ClientEvent>> {
+        Box::pin(base::next(self))
+    }
+
+    fn chat(&mut self, text: String) -> ClientFuture<'_, ()> {
+        Box::pin(base::chat(self, text))
+    }
+
+    fn whisper(&mut self, id: protocol::Player, text: String) -> ClientFuture<'_, ()> {
+        Box::pin(base::send(self, protocol::client::Whisper { id, text }))
+    }
+
+    fn command(&mut self, com: &str, data: String) -> ClientFuture<'_, ()> {
+        let com = com.to_owned();
+        Box::pin(base::send(self, protocol::client::Command { com, data }))
+    }
+
+    fn reconnect(
+        url: Url,
+        ctrl_name: String,
+        login: net::LoginOptions,
+        attempts: u32,
+        backoff: time::Duration,
+        limiter: net::ReconnectLimiter,
+        shutdown: Shutdown,
+    ) -> Pin<Box<dyn Future<Output = Option<Self>> + Send>> {
+        Box::pin(server::connect_with_backoff(
+            url, ctrl_name, login, attempts, backoff, limiter, shutdown,
+        ))
+    }
+}
+
+#[cfg(test)]
+pub use self::fake::{FakeClient, Sent};
+
+#[cfg(test)]
+mod fake {
+    use super::*;
+
+    use std::collections::{BTreeMap, VecDeque};
+    use std::sync::{Arc, Mutex};
+
+    /// Something ground control sent to the game
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Sent {
+        Chat(String),
+        Whisper(u16, String),
+        Command(String, String),
+    }
+
+    /// A scripted game for testing `Server`
+    ///
+    /// The fake plays back its packets in order, then reports the connection
+    /// closed. Players join and leave as `PlayerNew` and `PlayerLeave`
+    /// packets are played, and everything ground control sends is recorded.
+    #[derive(Default)]
+    pub struct FakeClient {
+        /// Players in the game, by ID, with their names and teams
+        players: BTreeMap<u16, (String, protocol::Team)>,
+        /// Packets still to be played back
+        script: VecDeque<protocol::ServerPacket>,
+        /// Everything sent so far, shared with the test
+        sent: Arc<Mutex<Vec<Sent>>>,
+    }
+
+    impl FakeClient {
+        /// Add a player who's already in the game
+        pub fn player(mut self, id: u16, name: &str, team: u16) -> Self {
+            self.players
+                .insert(id, (name.to_owned(), protocol::Team(team)));
+            self
+        }
+
+        /// Play `packet` after any already scripted
+        pub fn packet(mut self, packet: protocol::ServerPacket) -> Self {
+            self.script.push_back(packet);
+            self
+        }
+
+        /// Everything ground control sends, as it's sent
+        pub fn sent(&self) -> Arc<Mutex<Vec<Sent>>> {
+            self.sent.clone()
+        }
+
+        fn record(&mut self, sent: Sent) -> ClientFuture<'_, ()> {
+            self.sent.lock().unwrap().push(sent);
+            Box::pin(async { Ok::<(), String>(()) })
+        }
+    }
+
+    impl GameClient for FakeClient {
+        fn player_name(&self, id: u16) -> Option<String> {
+            self.players.get(&id).map(|(name, _)| name.clone())
+        }
+
+        fn player_id(&self, name: &str) -> Option<u16> {
+            self.players
+                .iter()
+                .find(|(_, (player, _))| player == name)
+                .map(|(&id, _)| id)
+        }
+
+        fn player_team(&self, id: u16) -> Option<protocol::Team> {
+            self.players.get(&id).map(|&(_, team)| team)
+        }
+
+        fn player_names(&self) -> Vec<String> {
+            self.players
+                .values()
+                .map(|(name, _)| name.clone())
+                .collect()
+        }
+
+        fn ping(&self) -> u16 {
+            50
+        }
+
+        fn next(&mut self) -> ClientFuture<'_, Option<ClientEvent>> {
+            let packet = self.script.pop_front();
+            match packet {
+                Some(protocol::ServerPacket::PlayerNew(ref player_new)) => {
+                    self.players
+                        .insert(player_new.id.0, (player_new.name.clone(), player_new.team));
+                }
+                Some(protocol::ServerPacket::PlayerLeave(ref player_leave)) => {
+                    self.players.remove(&player_leave.id.0);
+                }
+                _ => (),
+            }
+            Box::pin(async move { Ok::<_, String>(packet.map(ClientEvent::Packet)) })
+        }
+
+        fn chat(&mut self, text: String) -> ClientFuture<'_, ()> {
+            self.record(Sent::Chat(text))
+        }
+
+        fn whisper(&mut self, id: protocol::Player, text: String) -> ClientFuture<'_, ()> {
+            self.record(Sent::Whisper(id.0, text))
+        }
+
+        fn command(&mut self, com: &str, data: String) -> ClientFuture<'_, ()> {
+            self.record(Sent::Command(com.to_owned(), data))
+        }
+
+        fn reconnect(
+            _url: Url,
+            _ctrl_name: String,
+            _login: net::LoginOptions,
+            _attempts: u32,
+            _backoff: time::Duration,
+            _limiter: net::ReconnectLimiter,
+            _shutdown: Shutdown,
+        ) -> Pin<Box<dyn Future<Output = Option<Self>> + Send>> {
+            // A script can't be picked up again once it's over
+            Box::pin(async { None::<Self> })
+        }
+    }
+}
@@ -3,30 +3,135 @@
 //! Provides command validation based on the required command
 //! state.
 
+use crate::types::{BOUNDARY_X, BOUNDARY_Y};
+
+use airmash_protocol as protocol;
 use clap::crate_version;
+use serde::{Deserialize, Serialize, Serializer};
 use std::fmt;
 
 pub mod command {
     //! Namespace for raw string commands
+    //!
+    //! Each command is appended to the configured prefix, so
+    //! `HELP` becomes `--gc-help` with the default prefix.
+
+    /// Default prefix for all commands
+    pub static DEFAULT_PREFIX: &'static str = "--gc";
+    /// User asks for help
+    pub static HELP: &'static str = "-help";
+    /// User requests wingmen
+    pub static WINGS: &'static str = "-wings";
+    /// User calls of their wingmen
+    pub static CALL_OFF: &'static str = "-call-off";
+    /// Version of this program
+    pub static VERSION: &'static str = "-version";
+    /// User asks how many wings they have
+    pub static STATUS: &'static str = "-status";
+    /// Admin asks for every player's wings
+    pub static LIST: &'static str = "-list";
+    /// User requests wingmen to defend them
+    pub static DEFEND: &'static str = "-defend";
+    /// User asks how busy ground control is
+    pub static STATS: &'static str = "-stats";
+    /// Admin recalls every player's wings
+    pub static RECALL_ALL: &'static str = "-recall-all";
+    /// User sends their wingmen after someone else
+    pub static RETARGET: &'static str = "-retarget";
+    /// User asks who has wingmen attacking them
+    pub static TARGETS: &'static str = "-targets";
+    /// User requests wingmen to guard a point on the map
+    pub static PATROL: &'static str = "-patrol";
+    /// User switches their wingmen between attacking and defending
+    pub static MODE: &'static str = "-mode";
+}
 
+/// The full command strings understood by ground control
+///
+/// Commands are built from a prefix, which is lowercase to
+/// match the lowercased keyword of a user's message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandSet {
     /// Prefix for all commands
-    pub static PREFIX: &'static str = "--gc";
+    pub prefix: String,
     /// User asks for help
-    pub static HELP: &'static str = "--gc-help";
+    pub help: String,
     /// User requests wingmen
-    pub static WINGS: &'static str = "--gc-wings";
+    pub wings: String,
     /// User calls of their wingmen
-    pub static CALL_OFF: &'static str = "--gc-call-off";
+    pub call_off: String,
     /// Version of this program
-    pub static VERSION: &'static str = "--gc-version";
+    pub version: String,
+    /// User asks how many wings they have
+    pub status: String,
+    /// Admin asks for every player's wings
+    pub list: String,
+    /// User requests wingmen to defend them
+    pub defend: String,
+    /// User asks how busy ground control is
+    pub stats: String,
+    /// Admin recalls every player's wings
+    pub recall_all: String,
+    /// User sends their wingmen after someone else
+    pub retarget: String,
+    /// User asks who has wingmen attacking them
+    pub targets: String,
+    /// User requests wingmen to guard a point on the map
+    pub patrol: String,
+    /// User switches their wingmen between attacking and defending
+    pub mode: String,
+}
+
+impl CommandSet {
+    /// Build the commands that start with the provided prefix
+    pub fn new(prefix: &str) -> Self {
+        let prefix = prefix.to_lowercase();
+        let cmd = |suffix: &str| format!("{}{}", prefix, suffix);
+        CommandSet {
+            help: cmd(command::HELP),
+            wings: cmd(command::WINGS),
+            call_off: cmd(command::CALL_OFF),
+            version: cmd(command::VERSION),
+            status: cmd(command::STATUS),
+            list: cmd(command::LIST),
+            defend: cmd(command::DEFEND),
+            stats: cmd(command::STATS),
+            recall_all: cmd(command::RECALL_ALL),
+            retarget: cmd(command::RETARGET),
+            targets: cmd(command::TARGETS),
+            patrol: cmd(command::PATROL),
+            mode: cmd(command::MODE),
+            prefix,
+        }
+    }
+}
+
+impl Default for CommandSet {
+    fn default() -> Self {
+        CommandSet::new(command::DEFAULT_PREFIX)
+    }
+}
+
+/// This program's version, with the commit and date it was built from
+/// when `build.rs` could find them, like `0.3.0 (1a2b3c4, built 2019-03-01)`
+///
+/// Shared by `--version` on the command line and the in-game version command.
+pub fn version() -> String {
+    let build: Vec<String> = option_env!("GC_GIT_COMMIT")
+        .map(str::to_owned)
+        .into_iter()
+        .chain(option_env!("GC_BUILD_DATE").map(|date| format!("built {}", date)))
+        .collect();
+    if build.is_empty() {
+        crate_version!().to_owned()
+    } else {
+        format!("{} ({})", crate_version!(), build.join(", "))
+    }
 }
 
 /// Generate a string containing versioning info for this program
 fn version_message() -> Vec<String> {
-    vec![format!(
-        "AIRMASH Ground Control, version {}",
-        crate_version!()
-    )]
+    vec![format!("AIRMASH Ground Control, version {}", version())]
 }
 
 macro_rules! command_help {
@@ -35,15 +140,214 @@ macro_rules! command_help {
     };
 }
 
-/// Generate the help response for a help command
-fn help_response() -> Vec<String> {
+/// Find the player named with an `@` in a retarget request, if any
+///
+/// Everything after the `@` is the name, so names with spaces work.
+fn target_name(message: &str) -> Option<&str> {
+    message
+        .find(" @")
+        .map(|idx| message[idx + 2..].trim())
+        .filter(|name| !name.is_empty())
+}
+
+/// Find the players named with an `@` each in a wings request, in order
+///
+/// Each name runs up to the next ` @`, so names with spaces work here too.
+fn target_names(message: &str) -> Vec<&str> {
+    message
+        .split(" @")
+        .skip(1)
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Generate the stats response describing how busy ground control is
+///
+/// `wingmen` is the number of wingmen flying for `users` players, and
+/// `ping` is ground control's own ping in milliseconds.
+pub fn stats_message(wingmen: usize, users: usize, ping: u32) -> Vec<String> {
     vec![
-        command_help!(command::WINGS, "request X attacking wingmen"),
-        command_help!(command::CALL_OFF, "remove any requested wingmen"),
-        command_help!(command::VERSION, "program version"),
+        format!("{} wingmen flying for {} players", wingmen, users),
+        format!("Ground control ping: {} ms", ping),
     ]
 }
 
+/// Targets listed on each line of the targets response
+const TARGETS_PER_LINE: usize = 3;
+
+/// Generate the targets response listing who's under attack
+///
+/// `targets` names each player being attacked, with the number of wings
+/// attacking them. A few targets share each line, to keep the lines short
+/// and the number of messages down.
+pub fn targets_message(targets: &[(String, usize)]) -> Vec<String> {
+    if targets.is_empty() {
+        return vec!["Nobody is under attack".to_owned()];
+    }
+    targets
+        .chunks(TARGETS_PER_LINE)
+        .map(|line| {
+            line.iter()
+                .map(|(name, wings)| format!("{}: {} wings", name, wings))
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+        .collect()
+}
+
+/// Generate the greeting for a user who just joined, pointing them at `help`
+///
+/// With `scale`, the number of players online and wingmen flying, the
+/// greeting goes on to say how busy the server is.
+pub fn greeting_message(user: &str, help: &str, scale: Option<(usize, usize)>) -> Vec<String> {
+    let mut msgs = vec![format!(
+        "Ground Control, standing by for {}! Use {} for help.",
+        user, help
+    )];
+    if let Some((players, wingmen)) = scale {
+        msgs.push(format!(
+            "{} players online, {} wingmen flying",
+            players, wingmen
+        ));
+    }
+    msgs
+}
+
+/// Generate the status response describing a user's current wings
+fn status_message(wings: u8) -> Vec<String> {
+    if wings > 0 {
+        vec![format!("You have {} wings assigned", wings)]
+    } else {
+        vec!["You have no wings assigned".to_owned()]
+    }
+}
+
+/// Commands with detailed help, named without the prefix
+const HELP_TOPICS: &str =
+    "wings, defend, patrol, retarget, mode, call-off, status, stats, targets, version";
+
+/// Ship types that can be requested for wingmen, as typed by users
+const SHIP_NAMES: &str = "predator, goliath, mohawk, tornado, prowler";
+
+/// The ship type with the given name, ignoring case
+pub fn ship_type(name: &str) -> Option<protocol::PlaneType> {
+    match name.to_lowercase().as_str() {
+        "predator" => Some(protocol::PlaneType::Predator),
+        "goliath" => Some(protocol::PlaneType::Goliath),
+        "mohawk" => Some(protocol::PlaneType::Mohawk),
+        "tornado" => Some(protocol::PlaneType::Tornado),
+        "prowler" => Some(protocol::PlaneType::Prowler),
+        _ => None,
+    }
+}
+
+/// The name users know a ship type by
+pub fn ship_name(ship: protocol::PlaneType) -> &'static str {
+    match ship {
+        protocol::PlaneType::Predator => "predator",
+        protocol::PlaneType::Goliath => "goliath",
+        protocol::PlaneType::Mohawk => "mohawk",
+        protocol::PlaneType::Tornado => "tornado",
+        protocol::PlaneType::Prowler => "prowler",
+    }
+}
+
+/// Serialize a requested ship by the name users know it by
+fn serialize_ship<S: Serializer>(
+    ship: &Option<protocol::PlaneType>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    ship.map(ship_name).serialize(serializer)
+}
+
+/// Requested firing ranges are clamped to this range, in world units
+const MIN_FIRE_RANGE: u16 = 200;
+const MAX_FIRE_RANGE: u16 = 1200;
+
+/// Optional settings in a wings request
+#[derive(Debug, Default, PartialEq, Eq)]
+struct WingSettings {
+    /// The ship the wings fly
+    ship: Option<protocol::PlaneType>,
+    /// How close the wings get before they open fire
+    range: Option<u16>,
+    /// True if the wings should hunt down whoever threatens the user
+    protect: bool,
+}
+
+/// Word in a wings request asking for wings that protect the user by
+/// hunting down whoever threatens them
+const PROTECT_WORD: &str = "defend-me";
+
+/// Word users give as the wing count to ask for as many wings as they can have
+const MAX_WORD: &str = "max";
+
+/// Parse the optional settings in a wings command
+///
+/// Settings follow the first `skip` words of the command, up to and including
+/// the wing count, as `key=value` words, and come before any `@name` target.
+/// A plain word is taken as `type=word`, so users can name a ship on its own,
+/// except for `defend-me`, which asks for wings that protect the user.
+fn wing_settings(message: &str, skip: usize) -> Result<WingSettings, BadCommand<'_>> {
+    let mut settings = WingSettings::default();
+    let words = message
+        .split_whitespace()
+        .skip(skip)
+        .take_while(|word| !word.starts_with('@'));
+    for word in words {
+        if word.eq_ignore_ascii_case(PROTECT_WORD) {
+            settings.protect = true;
+            continue;
+        }
+        let (key, value) = match word.find('=') {
+            Some(idx) => (&word[..idx], &word[idx + 1..]),
+            None => ("type", word),
+        };
+        match key.to_lowercase().as_str() {
+            "type" => {
+                settings.ship = Some(ship_type(value).ok_or(BadCommand::UnknownShip(value))?);
+            }
+            "range" => {
+                let range = value
+                    .parse::<u16>()
+                    .map_err(|_| BadCommand::BadSetting(word))?;
+                settings.range = Some(range.max(MIN_FIRE_RANGE).min(MAX_FIRE_RANGE));
+            }
+            _ => return Err(BadCommand::BadSetting(word)),
+        }
+    }
+    Ok(settings)
+}
+
+/// Describe a number of wings, with their ship type if one was requested
+fn wings_description(wings: u8, ship: Option<protocol::PlaneType>) -> String {
+    format!("{} {}", wings, wings_noun(ship))
+}
+
+/// Name wings, with their ship type if one was requested
+fn wings_noun(ship: Option<protocol::PlaneType>) -> String {
+    match ship {
+        Some(ship) => format!("{} wings", ship_name(ship)),
+        None => "wings".to_owned(),
+    }
+}
+
+/// What wingmen do with the player they're assigned to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Behavior {
+    /// Chase the player and shoot them down
+    Attack,
+    /// Stay near the player and shoot enemies that come close
+    Defend,
+    /// Stay near the player, and chase down whichever enemy is the biggest
+    /// threat to them
+    Protect,
+    /// Circle a point on the map, given in world units, and shoot enemies
+    /// that come close, whatever the player is doing
+    Patrol { x: i32, y: i32 },
+}
+
 /// A user's command for ground control
 ///
 /// Given the context provided in a command,
@@ -77,7 +381,9 @@ impl<'s> Command<'s> {
 }
 
 /// Possible reasons for a failed command
-#[derive(Debug, PartialEq, Eq)]
+///
+/// These serialize for the event log, as the variant with its fields.
+#[derive(Debug, PartialEq, Eq, Serialize)]
 pub enum BadCommand<'s> {
     /// Unkown command (wrapped in the variant)
     Unknown(&'s str),
@@ -85,8 +391,48 @@ pub enum BadCommand<'s> {
     NoWings(&'s str),
     /// Too many wings assigned to this user
     TooManyWings(&'s str, u8),
-    /// Wings are already assigned to this user
-    AlreadyWinged(&'s str, u8),
+    /// Wings are already assigned to this user (with the call off command)
+    AlreadyWinged(&'s str, u8, &'s str),
+    /// The requested target isn't on the server
+    NoSuchPlayer(&'s str),
+    /// This user requested wings too recently (seconds remaining)
+    CoolingDown(&'s str, u64),
+    /// The requested ship type doesn't exist
+    UnknownShip(&'s str),
+    /// Too many wings are already flying on the server for this user's request
+    ServerBusy(&'s str),
+    /// This user isn't on the list of players allowed to request wings
+    NotAuthorized(&'s str),
+    /// A `key=value` setting in a wings request isn't understood
+    BadSetting(&'s str),
+    /// This user's wings are doing something other than attacking, so they
+    /// can't be retargeted
+    NotAttacking(&'s str, Behavior),
+    /// This patrol point is off the map or inside an obstacle
+    BadPatrolPoint(&'s str),
+    /// The requested target is on the list of players wingmen won't attack
+    ProtectedTarget(&'s str),
+}
+
+impl<'s> BadCommand<'s> {
+    /// A short name for the kind of error, for metrics and logs
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BadCommand::Unknown(_) => "unknown",
+            BadCommand::NoWings(_) => "no_wings",
+            BadCommand::TooManyWings(_, _) => "too_many_wings",
+            BadCommand::AlreadyWinged(_, _, _) => "already_winged",
+            BadCommand::NoSuchPlayer(_) => "no_such_player",
+            BadCommand::CoolingDown(_, _) => "cooling_down",
+            BadCommand::UnknownShip(_) => "unknown_ship",
+            BadCommand::ServerBusy(_) => "server_busy",
+            BadCommand::NotAuthorized(_) => "not_authorized",
+            BadCommand::BadSetting(_) => "bad_setting",
+            BadCommand::NotAttacking(_, _) => "not_attacking",
+            BadCommand::BadPatrolPoint(_) => "bad_patrol_point",
+            BadCommand::ProtectedTarget(_) => "protected_target",
+        }
+    }
 }
 
 impl<'s> fmt::Display for BadCommand<'s> {
@@ -97,24 +443,117 @@ impl<'s> fmt::Display for BadCommand<'s> {
             BadCommand::TooManyWings(user, max) => {
                 write!(f, "too many wings attacking {} (max {} wings)", user, max)
             }
-            BadCommand::AlreadyWinged(user, wings) => write!(
+            BadCommand::AlreadyWinged(user, wings, call_off) => write!(
                 f,
                 "{} already has {} wings; use {} to remove",
-                user,
-                wings,
-                command::CALL_OFF
+                user, wings, call_off
+            ),
+            BadCommand::NoSuchPlayer(name) => write!(f, "no player named {} on this server", name),
+            BadCommand::CoolingDown(user, secs) => write!(
+                f,
+                "{} must wait {} more seconds before requesting wings",
+                user, secs
+            ),
+            BadCommand::UnknownShip(name) => write!(
+                f,
+                "unknown ship type '{}': choose one of {}",
+                name, SHIP_NAMES
+            ),
+            BadCommand::ServerBusy(user) => write!(
+                f,
+                "sorry {}, too many wings are in the air; try again later",
+                user
+            ),
+            BadCommand::NotAuthorized(user) => write!(
+                f,
+                "sorry {}, only approved players may request wings here",
+                user
+            ),
+            BadCommand::BadSetting(setting) => write!(
+                f,
+                "can't understand '{}': use type=ship or range={}-{}",
+                setting, MIN_FIRE_RANGE, MAX_FIRE_RANGE
+            ),
+            BadCommand::NotAttacking(user, behavior) => {
+                let doing = match behavior {
+                    Behavior::Defend => "defending you".to_owned(),
+                    Behavior::Protect => "hunting down your attackers".to_owned(),
+                    Behavior::Patrol { x, y } => format!("on patrol around {} {}", x, y),
+                    Behavior::Attack => "busy".to_owned(),
+                };
+                write!(
+                    f,
+                    "{}, your wings are {}; call them off to send wings after someone",
+                    user, doing
+                )
+            }
+            BadCommand::BadPatrolPoint(point) => write!(
+                f,
+                "can't patrol at {}: pick open sky with x from {} to {} and y from {} to {}",
+                point, -BOUNDARY_X, BOUNDARY_X, -BOUNDARY_Y, BOUNDARY_Y
             ),
+            BadCommand::ProtectedTarget(name) => {
+                write!(f, "sorry, wings won't attack {} on this server", name)
+            }
         }
     }
 }
 
 /// A response generated for a valid command
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+///
+/// Empty wings `targets` mean the wings are assigned to the requesting user,
+/// and several mean the wings attack each in turn. A `ship` of `None` means
+/// the wings fly the default ship, and a `range` of `None` means the wings
+/// use their default firing range.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub enum ResponseKind {
     /// Set wings for the specified user
-    SetWings { wings: u8 },
+    SetWings {
+        wings: u8,
+        targets: Vec<String>,
+        #[serde(serialize_with = "serialize_ship")]
+        ship: Option<protocol::PlaneType>,
+        behavior: Behavior,
+        range: Option<u16>,
+    },
+    /// Add wings to those already assigned to the specified user
+    AddWings {
+        wings: u8,
+        targets: Vec<String>,
+        #[serde(serialize_with = "serialize_ship")]
+        ship: Option<protocol::PlaneType>,
+        behavior: Behavior,
+        range: Option<u16>,
+    },
+    /// Send the specified user's wings after the named target
+    Retarget { target: String },
+    /// Switch the specified user's wings to the behavior, without calling
+    /// them off
+    SetMode { behavior: Behavior },
+    /// Remove some of the wings on the specified user
+    RemoveWings { wings: u8 },
     /// Remove all wings on the specified user
     ClearWings,
+    /// List every user's wings, if the specified user is an admin
+    ///
+    /// Ground control doesn't know who the admins are, so this response
+    /// has no message; the caller replies, or treats the command as unknown.
+    ListWings,
+    /// Recall every user's wings, if the specified user is an admin
+    ///
+    /// Like `ListWings`, this response has no message; the caller replies,
+    /// or treats the command as unknown.
+    RecallAll,
+    /// Report how busy ground control is
+    ///
+    /// Ground control doesn't know about other users' wings, so this response
+    /// has no message; the caller replies with `stats_message`.
+    Stats,
+    /// Report who's under attack, and by how many wings
+    ///
+    /// Like `Stats`, this response has no message; the caller replies with
+    /// `targets_message`.
+    Targets,
 }
 
 /// A ground control response
@@ -124,8 +563,8 @@ pub struct Response {
     ///
     /// These are split up into multiple messages to
     /// circumvent any max character limit per message.
-    /// We don't actually count characters now, but this
-    /// is for any future need.
+    /// Lines still too long for the server are split
+    /// again with `split_for_chat` as they're sent.
     message: Vec<String>,
     /// The kind of action to take on the maintained state
     kind: Option<ResponseKind>,
@@ -144,7 +583,7 @@ impl Response {
 
     /// Returns the kind of response and subsequent action to take
     pub fn kind(&self) -> Option<ResponseKind> {
-        self.kind
+        self.kind.clone()
     }
 
     /// Create a response containing just a message
@@ -156,10 +595,112 @@ impl Response {
     }
 
     /// Create an 'add wings' response with a canned response message
-    fn add_wings(user: &str, wings: u8) -> Self {
+    fn add_wings(
+        user: &str,
+        wings: u8,
+        targets: &[&str],
+        settings: WingSettings,
+        behavior: Behavior,
+    ) -> Self {
+        let desc = wings_description(wings, settings.ship);
+        Response {
+            message: vec![match (behavior, targets) {
+                (Behavior::Defend, _) => format!("OK {}, {} are coming to defend you!", user, desc),
+                (Behavior::Protect, _) => format!(
+                    "OK {}, {} are coming to hunt down your attackers!",
+                    user, desc
+                ),
+                (Behavior::Attack, []) => format!("OK {}, {} are coming!", user, desc),
+                (Behavior::Attack, targets) => format!(
+                    "OK {}, {} are coming for {}!",
+                    user,
+                    desc,
+                    targets.join(", ")
+                ),
+                (Behavior::Patrol { x, y }, _) => format!(
+                    "OK {}, {} are heading out to patrol around {} {}!",
+                    user, desc, x, y
+                ),
+            }],
+            kind: Some(ResponseKind::SetWings {
+                wings,
+                targets: targets.iter().map(|&target| target.to_owned()).collect(),
+                ship: settings.ship,
+                behavior,
+                range: settings.range,
+            }),
+        }
+    }
+
+    /// Create a 'top up wings' response with a canned response message
+    fn top_up_wings(
+        user: &str,
+        wings: u8,
+        targets: &[&str],
+        settings: WingSettings,
+        behavior: Behavior,
+    ) -> Self {
+        let desc = format!("{} more {}", wings, wings_noun(settings.ship));
+        Response {
+            message: vec![match (behavior, targets) {
+                (Behavior::Defend, _) => format!("OK {}, {} are coming to defend you!", user, desc),
+                (Behavior::Protect, _) => format!(
+                    "OK {}, {} are coming to hunt down your attackers!",
+                    user, desc
+                ),
+                (Behavior::Attack, []) => format!("OK {}, {} are coming!", user, desc),
+                (Behavior::Attack, targets) => format!(
+                    "OK {}, {} are coming for {}!",
+                    user,
+                    desc,
+                    targets.join(", ")
+                ),
+                (Behavior::Patrol { x, y }, _) => format!(
+                    "OK {}, {} are heading out to patrol around {} {}!",
+                    user, desc, x, y
+                ),
+            }],
+            kind: Some(ResponseKind::AddWings {
+                wings,
+                targets: targets.iter().map(|&target| target.to_owned()).collect(),
+                ship: settings.ship,
+                behavior,
+                range: settings.range,
+            }),
+        }
+    }
+
+    /// Create a 'retarget wings' response with a canned response message
+    fn retarget_wings(user: &str, target: &str) -> Self {
+        Response {
+            message: vec![format!(
+                "OK {}, your wings are turning on {}!",
+                user, target
+            )],
+            kind: Some(ResponseKind::Retarget {
+                target: target.to_owned(),
+            }),
+        }
+    }
+
+    /// Create a 'set mode' response with a canned response message
+    fn set_mode(user: &str, behavior: Behavior) -> Self {
         Response {
-            message: vec![format!("OK {}, {} wings are coming!", user, wings)],
-            kind: Some(ResponseKind::SetWings { wings }),
+            message: vec![match behavior {
+                Behavior::Defend => {
+                    format!("OK {}, your wings are falling back to defend you!", user)
+                }
+                Behavior::Protect => format!(
+                    "OK {}, your wings are turning to hunt down your attackers!",
+                    user
+                ),
+                Behavior::Attack => format!("OK {}, your wings are going on the attack!", user),
+                Behavior::Patrol { x, y } => format!(
+                    "OK {}, your wings are heading out to patrol around {} {}!",
+                    user, x, y
+                ),
+            }],
+            kind: Some(ResponseKind::SetMode { behavior }),
         }
     }
 
@@ -170,52 +711,433 @@ impl Response {
             kind: Some(ResponseKind::ClearWings),
         }
     }
+
+    /// Create a 'remove wings' response with a canned response message
+    fn remove_wings(user: &str, wings: u8) -> Self {
+        Response {
+            message: vec![format!("Calling off {} wings from {}", wings, user)],
+            kind: Some(ResponseKind::RemoveWings { wings }),
+        }
+    }
+}
+
+/// Split `s` into chat messages of at most `max_len` characters each, or
+/// leave it whole if `max_len` is 0
+///
+/// Messages break between words. A word too long for a message of its own
+/// is broken wherever it has to be.
+pub fn split_for_chat(s: &str, max_len: usize) -> Vec<String> {
+    if max_len == 0 || s.chars().count() <= max_len {
+        return vec![s.to_owned()];
+    }
+
+    let mut pieces = Vec::new();
+    let mut piece = String::new();
+    let mut piece_len = 0;
+    for word in s.split_whitespace() {
+        let word_len = word.chars().count();
+        if piece_len > 0 && piece_len + 1 + word_len > max_len {
+            pieces.push(std::mem::replace(&mut piece, String::new()));
+            piece_len = 0;
+        }
+        if piece_len > 0 {
+            piece.push(' ');
+            piece_len += 1;
+        }
+        for c in word.chars() {
+            if piece_len == max_len {
+                pieces.push(std::mem::replace(&mut piece, String::new()));
+                piece_len = 0;
+            }
+            piece.push(c);
+            piece_len += 1;
+        }
+    }
+    if piece_len > 0 {
+        pieces.push(piece);
+    }
+    pieces
+}
+
+/// The share of users handled by one of several ground controllers on a server
+///
+/// Every controller hashes a user's name the same way, so exactly one
+/// controller in a complete set of shards answers each user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    /// This controller's shard, counting from 0
+    index: u32,
+    /// Number of controllers sharing the server
+    count: u32,
+}
+
+impl Shard {
+    /// Create shard `index` of `count`, or an error if `index` is out of range
+    pub fn new(index: u32, count: u32) -> Result<Self, String> {
+        if index < count {
+            Ok(Shard { index, count })
+        } else {
+            Err(format!(
+                "invalid shard {} of {}: shards count from 0 up to the shard count",
+                index, count
+            ))
+        }
+    }
+
+    /// True if this is the first shard, which speaks for all of them
+    pub fn is_first(&self) -> bool {
+        self.index == 0
+    }
+
+    /// True if this shard handles the named user
+    ///
+    /// Names are hashed with 32-bit FNV-1a, which doesn't change between
+    /// builds or runs.
+    pub fn owns(&self, user: &str) -> bool {
+        let hash = user.bytes().fold(0x811c_9dc5u32, |hash, byte| {
+            (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+        });
+        hash % self.count == self.index
+    }
+}
+
+impl Default for Shard {
+    fn default() -> Self {
+        Shard { index: 0, count: 1 }
+    }
 }
 
 /// A control tower handles user commands and dispatches wings
 pub struct ControlTower {
     /// The maximum number of wings allowed per user
     max_wings: u8,
+    /// The commands we respond to
+    commands: CommandSet,
 }
 
 impl ControlTower {
     /// Create a control tower that will limit the number of wings
-    /// to the provided max
-    pub fn new(max_wings: u8) -> Self {
-        ControlTower { max_wings }
+    /// to the provided max, responding to commands that start with
+    /// `prefix`
+    pub fn new(max_wings: u8, prefix: &str) -> Self {
+        ControlTower {
+            max_wings,
+            commands: CommandSet::new(prefix),
+        }
+    }
+
+    /// The commands this tower responds to
+    pub fn commands(&self) -> &CommandSet {
+        &self.commands
+    }
+
+    /// Generate the help response for a help command
+    ///
+    /// Without a `topic`, this is a short summary of the commands. A topic
+    /// names one command, with or without the prefix.
+    fn help_response(&self, topic: Option<&str>) -> Vec<String> {
+        let commands = &self.commands;
+        let topic = match topic {
+            Some(topic) => topic.to_lowercase(),
+            None => {
+                return vec![
+                    format!(
+                        "Commands: {}, {}, {}, {}, {}, {}, {}, {}, {}, {}",
+                        commands.wings,
+                        commands.defend,
+                        commands.patrol,
+                        commands.retarget,
+                        commands.mode,
+                        commands.call_off,
+                        commands.status,
+                        commands.stats,
+                        commands.targets,
+                        commands.version
+                    ),
+                    format!(
+                        "Up to {} wings each. Say {} followed by one of {} for details",
+                        self.max_wings, commands.help, HELP_TOPICS
+                    ),
+                ];
+            }
+        };
+        let name = topic
+            .trim_start_matches(commands.prefix.as_str())
+            .trim_start_matches('-');
+        match name {
+            "wings" => vec![
+                command_help!(
+                    commands.wings,
+                    "request X attacking wingmen; add a ship type to pick their planes, \
+                     or @name to send them after someone else"
+                ),
+                format!("Example: {} 3 goliath range=800 @name", commands.wings),
+                format!(
+                    "Up to {} wings each, or {} for all you can have; ships are {}; range is {}-{}",
+                    self.max_wings, MAX_WORD, SHIP_NAMES, MIN_FIRE_RANGE, MAX_FIRE_RANGE
+                ),
+            ],
+            "defend" => vec![
+                command_help!(
+                    commands.defend,
+                    "request X wingmen that stay close and fight off enemies near you"
+                ),
+                format!("Example: {} 2 mohawk", commands.defend),
+                format!(
+                    "Defending wings count towards the limit of {} wings each",
+                    self.max_wings
+                ),
+                format!(
+                    "Or say {} 3 {} for wings that hunt down whoever gets close to you",
+                    commands.wings, PROTECT_WORD
+                ),
+            ],
+            "patrol" => vec![
+                command_help!(
+                    commands.patrol,
+                    "request X wingmen that circle the point x y and attack enemies that come near"
+                ),
+                format!("Example: {} -2000 1500 3 mohawk", commands.patrol),
+                format!(
+                    "Points are in world units, with x from {} to {} and y from {} to {}",
+                    -BOUNDARY_X, BOUNDARY_X, -BOUNDARY_Y, BOUNDARY_Y
+                ),
+            ],
+            "retarget" => vec![
+                command_help!(
+                    commands.retarget,
+                    "send your attacking wingmen after @name without calling them off"
+                ),
+                format!("Example: {} @name", commands.retarget),
+            ],
+            "mode" => vec![
+                command_help!(
+                    commands.mode,
+                    "switch your wingmen to attack, defend or defend-me without calling them off"
+                ),
+                format!("Example: {} defend", commands.mode),
+            ],
+            "call-off" => vec![command_help!(
+                commands.call_off,
+                "remove any requested wingmen; add X to remove only X of them"
+            )],
+            "status" => vec![command_help!(commands.status, "show your assigned wingmen")],
+            "stats" => vec![command_help!(
+                commands.stats,
+                "show how many wingmen are flying and ground control's ping"
+            )],
+            "targets" => vec![command_help!(
+                commands.targets,
+                "show who has wingmen attacking them, and how many"
+            )],
+            "version" => vec![command_help!(commands.version, "program version")],
+            _ => vec![format!(
+                "no such help topic '{}'; topics are {}",
+                topic, HELP_TOPICS
+            )],
+        }
+    }
+
+    /// Parse the number of wings requested in `count`, checking it against
+    /// the wings the user already has
+    ///
+    /// `max` asks for as many as the user can still have.
+    fn wing_count<'s>(
+        &'s self,
+        cmd: &Command<'s>,
+        count: Option<&str>,
+    ) -> Result<u8, BadCommand<'s>> {
+        if cmd.wings >= self.max_wings {
+            return Err(BadCommand::AlreadyWinged(
+                cmd.user,
+                cmd.wings,
+                &self.commands.call_off,
+            ));
+        }
+        let count = count.and_then(|count| {
+            if count.eq_ignore_ascii_case(MAX_WORD) {
+                Some(self.max_wings - cmd.wings)
+            } else {
+                count.parse::<u8>().ok()
+            }
+        });
+        match count {
+            None => Err(BadCommand::Unknown(cmd.message)),
+            Some(count) if cmd.wings.saturating_add(count) > self.max_wings => {
+                Err(BadCommand::TooManyWings(cmd.user, self.max_wings))
+            }
+            Some(count) if count == 0 => Err(BadCommand::Unknown(cmd.message)),
+            Some(count) => Ok(count),
+        }
+    }
+
+    /// Respond to a valid request for `count` wings, telling the user how
+    /// close they are to the limit
+    fn wings_response(
+        &self,
+        user: &str,
+        wings: u8,
+        count: u8,
+        targets: &[&str],
+        settings: WingSettings,
+        behavior: Behavior,
+    ) -> Response {
+        let mut response = if wings > 0 {
+            Response::top_up_wings(user, count, targets, settings, behavior)
+        } else {
+            Response::add_wings(user, count, targets, settings, behavior)
+        };
+        if let Some(confirmation) = response.message.first_mut() {
+            confirmation.push_str(&format!(
+                " ({} of {} wings)",
+                wings.saturating_add(count),
+                self.max_wings
+            ));
+        }
+        response
+    }
+
+    /// Parse a request for wings with the given behavior
+    ///
+    /// Defending wings always protect the requesting user, so any `@name`
+    /// targets are only honored for attacking wings, which take each of them
+    /// on in turn. Asking for `defend-me` wings overrides `behavior`.
+    fn request_wings<'s>(
+        &'s self,
+        cmd: Command<'s>,
+        behavior: Behavior,
+    ) -> Result<Response, BadCommand<'s>> {
+        // --gc-wings X
+        let count = self.wing_count(&cmd, cmd.message.split_whitespace().nth(1))?;
+        let settings = wing_settings(cmd.message, 2)?;
+        let behavior = if settings.protect {
+            Behavior::Protect
+        } else {
+            behavior
+        };
+        let targets = match behavior {
+            Behavior::Attack => target_names(cmd.message),
+            _ => Vec::new(),
+        };
+        Ok(self.wings_response(cmd.user, cmd.wings, count, &targets, settings, behavior))
+    }
+
+    /// Parse a request for wings that patrol a point, like `--gc-patrol x y X`
+    ///
+    /// The point is in world units, and must be on the map. Patrolling wings
+    /// guard their point rather than a player, so `@name` targets and
+    /// `defend-me` don't apply.
+    fn request_patrol<'s>(&'s self, cmd: Command<'s>) -> Result<Response, BadCommand<'s>> {
+        let mut words = cmd.message.split_whitespace().skip(1);
+        let (x, y) = match (words.next(), words.next()) {
+            (Some(x), Some(y)) => (x, y),
+            _ => return Err(BadCommand::Unknown(cmd.message)),
+        };
+        let coordinate = |value: &str, bound: f32| {
+            value
+                .parse::<i32>()
+                .ok()
+                .filter(|value| (*value as f32).abs() <= bound)
+        };
+        let point = match (coordinate(x, BOUNDARY_X), coordinate(y, BOUNDARY_Y)) {
+            (Some(x), Some(y)) => Behavior::Patrol { x, y },
+            _ => {
+                // The point as typed, from the start of x to the end of y
+                let start = x.as_ptr() as usize - cmd.message.as_ptr() as usize;
+                let end = y.as_ptr() as usize - cmd.message.as_ptr() as usize + y.len();
+                return Err(BadCommand::BadPatrolPoint(&cmd.message[start..end]));
+            }
+        };
+        let count = self.wing_count(&cmd, words.next())?;
+        let settings = wing_settings(cmd.message, 4)?;
+        if settings.protect {
+            return Err(BadCommand::BadSetting(PROTECT_WORD));
+        }
+        Ok(self.wings_response(cmd.user, cmd.wings, count, &[], settings, point))
     }
 
     /// Command parsing implementation
     ///
     /// If we're in here, we know that the user's message represents some kind of
-    /// command; it's not just a random message to another user.
+    /// command; it's not just a random message to another user. The `keyword` is
+    /// the lowercased first word of the message.
     #[inline]
-    fn parse_command_impl<'s>(&self, cmd: Command<'s>) -> Result<Response, BadCommand<'s>> {
-        if cmd.message == command::HELP {
-            Ok(Response::just_message(help_response()))
-        } else if cmd.message == command::VERSION {
+    fn parse_command_impl<'s>(
+        &'s self,
+        keyword: &str,
+        cmd: Command<'s>,
+    ) -> Result<Response, BadCommand<'s>> {
+        let commands = &self.commands;
+        if keyword == commands.help {
+            let topic = cmd.message.split_whitespace().nth(1);
+            Ok(Response::just_message(self.help_response(topic)))
+        } else if keyword == commands.version {
             Ok(Response::just_message(version_message()))
-        } else if cmd.message.starts_with(command::WINGS) {
-            if cmd.wings > 0 {
-                Err(BadCommand::AlreadyWinged(cmd.user, cmd.wings))
-            } else {
-                // User may have requested wings
-                let mut words = cmd.message.split_whitespace();
-                words.next(); // --gc-wings
-                match words.next().and_then(|count| count.parse().ok()) {
-                    None => Err(BadCommand::Unknown(cmd.message)),
-                    Some(count) if count > self.max_wings => {
-                        Err(BadCommand::TooManyWings(cmd.user, self.max_wings))
-                    }
-                    Some(count) if count == 0 => Err(BadCommand::Unknown(cmd.message)),
-                    Some(count) => Ok(Response::add_wings(cmd.user, count)),
+        } else if keyword == commands.status {
+            Ok(Response::just_message(status_message(cmd.wings)))
+        } else if keyword == commands.stats {
+            Ok(Response {
+                message: Vec::new(),
+                kind: Some(ResponseKind::Stats),
+            })
+        } else if keyword == commands.targets {
+            Ok(Response {
+                message: Vec::new(),
+                kind: Some(ResponseKind::Targets),
+            })
+        } else if keyword == commands.list {
+            Ok(Response {
+                message: Vec::new(),
+                kind: Some(ResponseKind::ListWings),
+            })
+        } else if keyword == commands.recall_all {
+            Ok(Response {
+                message: Vec::new(),
+                kind: Some(ResponseKind::RecallAll),
+            })
+        } else if keyword == commands.wings {
+            self.request_wings(cmd, Behavior::Attack)
+        } else if keyword == commands.defend {
+            self.request_wings(cmd, Behavior::Defend)
+        } else if keyword == commands.patrol {
+            self.request_patrol(cmd)
+        } else if keyword == commands.retarget {
+            if cmd.wings == 0 {
+                return Err(BadCommand::NoWings(cmd.user));
+            }
+            match target_name(cmd.message) {
+                Some(target) => Ok(Response::retarget_wings(cmd.user, target)),
+                None => Err(BadCommand::Unknown(cmd.message)),
+            }
+        } else if keyword == commands.mode {
+            if cmd.wings == 0 {
+                return Err(BadCommand::NoWings(cmd.user));
+            }
+            match cmd.message.split_whitespace().nth(1).map(str::to_lowercase) {
+                Some(ref mode) if mode == "attack" => {
+                    Ok(Response::set_mode(cmd.user, Behavior::Attack))
+                }
+                Some(ref mode) if mode == "defend" => {
+                    Ok(Response::set_mode(cmd.user, Behavior::Defend))
                 }
+                Some(ref mode) if mode == PROTECT_WORD => {
+                    Ok(Response::set_mode(cmd.user, Behavior::Protect))
+                }
+                _ => Err(BadCommand::Unknown(cmd.message)),
             }
-        } else if cmd.message == command::CALL_OFF {
-            if cmd.wings > 0 {
-                Ok(Response::clear_wings(cmd.user))
-            } else {
-                Err(BadCommand::NoWings(cmd.user))
+        } else if keyword == commands.call_off {
+            if cmd.wings == 0 {
+                return Err(BadCommand::NoWings(cmd.user));
+            }
+            // User may only want to remove some wings
+            let mut words = cmd.message.split_whitespace();
+            words.next(); // --gc-call-off
+            match words.next().map(str::parse::<u8>) {
+                None => Ok(Response::clear_wings(cmd.user)),
+                Some(Ok(count)) if count > 0 => {
+                    Ok(Response::remove_wings(cmd.user, count.min(cmd.wings)))
+                }
+                Some(_) => Err(BadCommand::Unknown(cmd.message)),
             }
         } else {
             Err(BadCommand::Unknown(cmd.message))
@@ -228,35 +1150,94 @@ impl ControlTower {
     /// seems like a message was intended from ground control, `Some(result)` is returned.
     /// A `BadCommand` is returned if the command is not understood by ground control. A
     /// `Response`, possibly with a response action, is returned on an appropriate command.
-    pub fn parse_command<'s>(&self, cmd: Command<'s>) -> Option<Result<Response, BadCommand<'s>>> {
-        if !cmd.message.starts_with(command::PREFIX) {
+    ///
+    /// Command keywords are matched case-insensitively; arguments are left untouched.
+    /// A keyword must be the whole first word, so `--gc-wingsXYZ 3` is unknown rather
+    /// than a malformed wings request.
+    pub fn parse_command<'s>(
+        &'s self,
+        cmd: Command<'s>,
+    ) -> Option<Result<Response, BadCommand<'s>>> {
+        let keyword = cmd
+            .message
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .to_lowercase();
+        if !keyword.starts_with(&self.commands.prefix) {
             // Not intended for ground control
             None
         } else {
-            Some(self.parse_command_impl(cmd))
+            Some(self.parse_command_impl(&keyword, cmd))
         }
     }
+
+    /// Rebuild a wings `response` to `user`, who already has `assigned`
+    /// wings, for just the named `targets`, like when some of those asked
+    /// for aren't in the game
+    ///
+    /// Other responses are returned unchanged.
+    pub fn with_targets(
+        &self,
+        response: Response,
+        user: &str,
+        assigned: u8,
+        targets: &[String],
+    ) -> Response {
+        let (count, ship, behavior, range) = match response.kind {
+            Some(ResponseKind::SetWings {
+                wings,
+                ship,
+                behavior,
+                range,
+                ..
+            })
+            | Some(ResponseKind::AddWings {
+                wings,
+                ship,
+                behavior,
+                range,
+                ..
+            }) => (wings, ship, behavior, range),
+            _ => return response,
+        };
+        let targets: Vec<&str> = targets.iter().map(String::as_str).collect();
+        let settings = WingSettings {
+            ship,
+            range,
+            ..WingSettings::default()
+        };
+        self.wings_response(user, assigned, count, &targets, settings, behavior)
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
+    use super::greeting_message;
+    use super::protocol;
+    use super::split_for_chat;
+    use super::stats_message;
+    use super::targets_message;
+    use super::version;
     use super::BadCommand;
+    use super::Behavior;
     use super::Command;
     use super::ControlTower;
     use super::ResponseKind;
+    use super::Shard;
 
     #[test]
     fn not_a_command() {
         let cmd = Command::new("--game-stats", "derps", 3);
-        let ctrl = ControlTower::new(5);
+        let ctrl = ControlTower::new(5, "--gc");
         assert!(ctrl.parse_command(cmd).is_none());
     }
 
     #[test]
     fn request_help() {
         let cmd = Command::new("--gc-help", "putin copter", 0);
-        let ctrl = ControlTower::new(5);
+        let ctrl = ControlTower::new(5, "--gc");
         let resp = ctrl.parse_command(cmd).unwrap();
         assert!(resp.is_ok());
         let resp = resp.unwrap();
@@ -266,21 +1247,27 @@ mod tests {
     #[test]
     fn request_wings() {
         let cmd = Command::new("--gc-wings 3", "xplay", 0);
-        let ctrl = ControlTower::new(5);
+        let ctrl = ControlTower::new(5, "--gc");
         let resp = ctrl
             .parse_command(cmd)
             .expect("parsed something")
             .expect("valid command");
         assert_eq!(
             resp.kind.expect("a response kind"),
-            ResponseKind::SetWings { wings: 3 }
+            ResponseKind::SetWings {
+                wings: 3,
+                targets: Vec::new(),
+                ship: None,
+                behavior: Behavior::Attack,
+                range: None,
+            }
         )
     }
 
     #[test]
     fn request_wings_too_many() {
         let cmd = Command::new("--gc-wings 25", "STEAMROLLER", 0);
-        let ctrl = ControlTower::new(5);
+        let ctrl = ControlTower::new(5, "--gc");
         let resp = ctrl
             .parse_command(cmd)
             .expect("parsed something")
@@ -291,7 +1278,7 @@ mod tests {
     #[test]
     fn request_wings_nan() {
         let cmd = Command::new("--gc-wings abc", "Detect", 0);
-        let ctrl = ControlTower::new(5);
+        let ctrl = ControlTower::new(5, "--gc");
         let resp = ctrl
             .parse_command(cmd)
             .expect("parsed something")
@@ -302,7 +1289,7 @@ mod tests {
     #[test]
     fn request_wings_zero() {
         let cmd = Command::new("--gc-wings 0", "putin copter", 0);
-        let ctrl = ControlTower::new(5);
+        let ctrl = ControlTower::new(5, "--gc");
         let resp = ctrl
             .parse_command(cmd)
             .expect("parsed something")
@@ -313,7 +1300,7 @@ mod tests {
     #[test]
     fn call_off() {
         let cmd = Command::new("--gc-call-off", "Friendo", 4);
-        let ctrl = ControlTower::new(5);
+        let ctrl = ControlTower::new(5, "--gc");
         let resp = ctrl
             .parse_command(cmd)
             .expect("parsed something")
@@ -324,11 +1311,891 @@ mod tests {
     #[test]
     fn call_off_no_wings() {
         let cmd = Command::new("--gc-call-off", "xyz", 0);
-        let ctrl = ControlTower::new(5);
+        let ctrl = ControlTower::new(5, "--gc");
         let resp = ctrl
             .parse_command(cmd)
             .expect("parsed something")
             .expect_err("invalid command");
         assert_eq!(resp, BadCommand::NoWings("xyz"));
     }
+
+    #[test]
+    fn request_help_mixed_case() {
+        let cmd = Command::new("--Gc-HeLp", "putin copter", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert!(resp.kind.is_none());
+    }
+
+    #[test]
+    fn request_wings_upper_case() {
+        let cmd = Command::new("--GC-WINGS 3", "xplay", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(
+            resp.kind.expect("a response kind"),
+            ResponseKind::SetWings {
+                wings: 3,
+                targets: Vec::new(),
+                ship: None,
+                behavior: Behavior::Attack,
+                range: None,
+            }
+        )
+    }
+
+    #[test]
+    fn call_off_mixed_case() {
+        let cmd = Command::new("--Gc-Call-Off", "Friendo", 4);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(resp.kind.unwrap(), ResponseKind::ClearWings);
+    }
+
+    #[test]
+    fn unknown_preserves_original_case() {
+        let cmd = Command::new("--GC-WINGS ABC", "Detect", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect_err("invalid command");
+        assert_eq!(resp, BadCommand::Unknown("--GC-WINGS ABC"));
+    }
+
+    #[test]
+    fn keyword_must_be_whole_word() {
+        let ctrl = ControlTower::new(5, "--gc");
+        for message in &[
+            "--gc-wingsXYZ 3",
+            "--gc-wings3",
+            "--gc-defend2",
+            "--gc-call-offs",
+            "--gc-helpme",
+            "--gc-status?",
+        ] {
+            let cmd = Command::new(message, "xplay", 2);
+            let resp = ctrl
+                .parse_command(cmd)
+                .expect("parsed something")
+                .expect_err("invalid command");
+            assert_eq!(resp, BadCommand::Unknown(message));
+        }
+
+        // Any whitespace ends the keyword
+        let cmd = Command::new("--gc-wings\t3", "xplay", 0);
+        assert!(ctrl.parse_command(cmd).expect("parsed something").is_ok());
+    }
+
+    #[test]
+    fn status_no_wings() {
+        let cmd = Command::new("--gc-status", "xyz", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert!(resp.kind.is_none());
+        assert_eq!(resp.msg(), vec!["You have no wings assigned".to_owned()]);
+    }
+
+    #[test]
+    fn status_with_wings() {
+        let cmd = Command::new("--gc-status", "Friendo", 4);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert!(resp.kind.is_none());
+        assert_eq!(resp.msg(), vec!["You have 4 wings assigned".to_owned()]);
+    }
+
+    #[test]
+    fn request_wings_top_up() {
+        let cmd = Command::new("--gc-wings 2", "xplay", 2);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(
+            resp.kind.expect("a response kind"),
+            ResponseKind::AddWings {
+                wings: 2,
+                targets: Vec::new(),
+                ship: None,
+                behavior: Behavior::Attack,
+                range: None,
+            }
+        )
+    }
+
+    #[test]
+    fn request_wings_top_up_over_cap() {
+        let cmd = Command::new("--gc-wings 3", "xplay", 3);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect_err("invalid command");
+        assert_eq!(resp, BadCommand::TooManyWings("xplay", 5));
+    }
+
+    #[test]
+    fn request_wings_max() {
+        let cmd = Command::new("--gc-wings max mohawk", "xplay", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(
+            resp.kind.expect("a response kind"),
+            ResponseKind::SetWings {
+                wings: 5,
+                targets: Vec::new(),
+                ship: Some(protocol::PlaneType::Mohawk),
+                behavior: Behavior::Attack,
+                range: None,
+            }
+        )
+    }
+
+    #[test]
+    fn request_wings_max_top_up() {
+        let cmd = Command::new("--gc-wings MAX", "xplay", 2);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(
+            resp.kind.expect("a response kind"),
+            ResponseKind::AddWings {
+                wings: 3,
+                targets: Vec::new(),
+                ship: None,
+                behavior: Behavior::Attack,
+                range: None,
+            }
+        );
+
+        let cmd = Command::new("--gc-wings max", "xplay", 5);
+        let err = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect_err("invalid command");
+        assert_eq!(err, BadCommand::AlreadyWinged("xplay", 5, "--gc-call-off"));
+    }
+
+    #[test]
+    fn request_wings_at_cap() {
+        let cmd = Command::new("--gc-wings 1", "xplay", 5);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect_err("invalid command");
+        assert_eq!(resp, BadCommand::AlreadyWinged("xplay", 5, "--gc-call-off"));
+    }
+
+    #[test]
+    fn request_wings_for_target() {
+        let cmd = Command::new("--gc-wings 3 @putin copter", "xplay", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(
+            resp.kind.expect("a response kind"),
+            ResponseKind::SetWings {
+                wings: 3,
+                targets: vec!["putin copter".to_owned()],
+                ship: None,
+                behavior: Behavior::Attack,
+                range: None,
+            }
+        )
+    }
+
+    #[test]
+    fn request_wings_for_several_targets() {
+        let cmd = Command::new(
+            "--gc-wings 3 mohawk @alice @putin copter @ @carol",
+            "xplay",
+            0,
+        );
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(
+            resp.kind(),
+            Some(ResponseKind::SetWings {
+                wings: 3,
+                targets: vec![
+                    "alice".to_owned(),
+                    "putin copter".to_owned(),
+                    "carol".to_owned()
+                ],
+                ship: Some(protocol::PlaneType::Mohawk),
+                behavior: Behavior::Attack,
+                range: None,
+            })
+        );
+        assert_eq!(
+            resp.msg(),
+            vec!["OK xplay, 3 mohawk wings are coming for alice, putin copter, carol! (3 of 5 wings)"]
+        );
+    }
+
+    #[test]
+    fn wings_response_with_fewer_targets() {
+        let cmd = Command::new("--gc-wings 3 @alice @bob @carol", "xplay", 2);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        let found = vec!["alice".to_owned(), "carol".to_owned()];
+        let resp = ctrl.with_targets(resp, "xplay", 2, &found);
+        assert_eq!(
+            resp.kind(),
+            Some(ResponseKind::AddWings {
+                wings: 3,
+                targets: found,
+                ship: None,
+                behavior: Behavior::Attack,
+                range: None,
+            })
+        );
+        assert_eq!(
+            resp.msg(),
+            vec!["OK xplay, 3 more wings are coming for alice, carol! (5 of 5 wings)"]
+        );
+    }
+
+    #[test]
+    fn request_wings_empty_target() {
+        let cmd = Command::new("--gc-wings 2 @", "xplay", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(
+            resp.kind.expect("a response kind"),
+            ResponseKind::SetWings {
+                wings: 2,
+                targets: Vec::new(),
+                ship: None,
+                behavior: Behavior::Attack,
+                range: None,
+            }
+        )
+    }
+
+    #[test]
+    fn request_wings_target_without_count() {
+        let cmd = Command::new("--gc-wings @Detect", "xplay", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect_err("invalid command");
+        assert_eq!(resp, BadCommand::Unknown("--gc-wings @Detect"));
+    }
+
+    #[test]
+    fn custom_prefix() {
+        let ctrl = ControlTower::new(5, "--bot");
+        let cmd = Command::new("--gc-wings 3", "xplay", 0);
+        assert!(ctrl.parse_command(cmd).is_none());
+        let cmd = Command::new("--bot-wings 3", "xplay", 0);
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(
+            resp.kind.expect("a response kind"),
+            ResponseKind::SetWings {
+                wings: 3,
+                targets: Vec::new(),
+                ship: None,
+                behavior: Behavior::Attack,
+                range: None,
+            }
+        )
+    }
+
+    #[test]
+    fn help_uses_prefix() {
+        let ctrl = ControlTower::new(5, "--Bot");
+        let cmd = Command::new("--bot-help", "xplay", 0);
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        let msg = resp.msg();
+        assert!(msg.iter().all(|line| line.contains("--bot-")));
+        assert!(msg.iter().all(|line| !line.contains("--gc")));
+    }
+
+    #[test]
+    fn call_off_some() {
+        let cmd = Command::new("--gc-call-off 3", "Friendo", 5);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(resp.kind.unwrap(), ResponseKind::RemoveWings { wings: 3 });
+    }
+
+    #[test]
+    fn call_off_exactly_all() {
+        let cmd = Command::new("--gc-call-off 2", "Friendo", 2);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(resp.kind.unwrap(), ResponseKind::RemoveWings { wings: 2 });
+    }
+
+    #[test]
+    fn call_off_more_than_assigned() {
+        let cmd = Command::new("--gc-call-off 4", "Friendo", 1);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(resp.kind.unwrap(), ResponseKind::RemoveWings { wings: 1 });
+    }
+
+    #[test]
+    fn call_off_zero() {
+        let cmd = Command::new("--gc-call-off 0", "Friendo", 3);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect_err("invalid command");
+        assert_eq!(resp, BadCommand::Unknown("--gc-call-off 0"));
+    }
+
+    #[test]
+    fn request_wings_ship() {
+        let cmd = Command::new("--gc-wings 3 Goliath", "xplay", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(
+            resp.kind.expect("a response kind"),
+            ResponseKind::SetWings {
+                wings: 3,
+                targets: Vec::new(),
+                ship: Some(protocol::PlaneType::Goliath),
+                behavior: Behavior::Attack,
+                range: None,
+            }
+        )
+    }
+
+    #[test]
+    fn request_wings_ship_type_with_target() {
+        let cmd = Command::new("--gc-wings 2 type=mohawk @putin copter", "xplay", 1);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(
+            resp.kind.expect("a response kind"),
+            ResponseKind::AddWings {
+                wings: 2,
+                targets: vec!["putin copter".to_owned()],
+                ship: Some(protocol::PlaneType::Mohawk),
+                behavior: Behavior::Attack,
+                range: None,
+            }
+        )
+    }
+
+    #[test]
+    fn request_wings_unknown_ship() {
+        let cmd = Command::new("--gc-wings 2 type=zeppelin", "xplay", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect_err("invalid command");
+        assert_eq!(resp, BadCommand::UnknownShip("zeppelin"));
+        assert!(format!("{}", resp).contains("goliath"));
+    }
+
+    #[test]
+    fn list_wings() {
+        let cmd = Command::new("--GC-list", "admin", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(resp.kind(), Some(ResponseKind::ListWings));
+        assert!(resp.msg().is_empty());
+    }
+
+    #[test]
+    fn request_defend() {
+        let cmd = Command::new("--gc-defend 2 predator", "xplay", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(
+            resp.kind.expect("a response kind"),
+            ResponseKind::SetWings {
+                wings: 2,
+                targets: Vec::new(),
+                ship: Some(protocol::PlaneType::Predator),
+                behavior: Behavior::Defend,
+                range: None,
+            }
+        )
+    }
+
+    #[test]
+    fn request_defend_me() {
+        let cmd = Command::new("--gc-wings 3 Defend-Me mohawk @Detect", "xplay", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(
+            resp.kind(),
+            Some(ResponseKind::SetWings {
+                wings: 3,
+                targets: Vec::new(),
+                ship: Some(protocol::PlaneType::Mohawk),
+                behavior: Behavior::Protect,
+                range: None,
+            })
+        );
+        assert!(resp.msg()[0].contains("hunt down your attackers"));
+    }
+
+    #[test]
+    fn request_defend_too_many() {
+        let cmd = Command::new("--gc-defend 3", "xplay", 4);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect_err("invalid command");
+        assert_eq!(resp, BadCommand::TooManyWings("xplay", 5));
+    }
+
+    #[test]
+    fn request_patrol() {
+        let cmd = Command::new("--gc-patrol -2000 1500 3 mohawk", "xplay", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(
+            resp.kind(),
+            Some(ResponseKind::SetWings {
+                wings: 3,
+                targets: Vec::new(),
+                ship: Some(protocol::PlaneType::Mohawk),
+                behavior: Behavior::Patrol { x: -2000, y: 1500 },
+                range: None,
+            })
+        );
+        assert!(resp.msg()[0].contains("patrol around -2000 1500"));
+    }
+
+    #[test]
+    fn request_patrol_off_the_map() {
+        let ctrl = ControlTower::new(5, "--gc");
+        let cmd = Command::new("--gc-patrol 100 9000 2", "xplay", 0);
+        let err = ctrl
+            .parse_command(cmd)
+            .unwrap()
+            .expect_err("invalid command");
+        assert_eq!(err, BadCommand::BadPatrolPoint("100 9000"));
+        assert!(format!("{}", err).contains("y from -8192 to 8192"));
+
+        let cmd = Command::new("--gc-patrol north 100 2", "xplay", 0);
+        let err = ctrl
+            .parse_command(cmd)
+            .unwrap()
+            .expect_err("invalid command");
+        assert_eq!(err, BadCommand::BadPatrolPoint("north 100"));
+    }
+
+    #[test]
+    fn retarget_refusal_names_what_wings_are_doing() {
+        let refusal = |behavior| BadCommand::NotAttacking("xplay", behavior).to_string();
+        assert_eq!(
+            refusal(Behavior::Defend),
+            "xplay, your wings are defending you; call them off to send wings after someone"
+        );
+        assert_eq!(
+            refusal(Behavior::Patrol { x: 100, y: -200 }),
+            "xplay, your wings are on patrol around 100 -200; call them off to send wings after someone"
+        );
+    }
+
+    #[test]
+    fn request_patrol_needs_point_and_count() {
+        let ctrl = ControlTower::new(5, "--gc");
+        let cmd = Command::new("--gc-patrol 100 200", "xplay", 0);
+        let err = ctrl
+            .parse_command(cmd)
+            .unwrap()
+            .expect_err("invalid command");
+        assert_eq!(err, BadCommand::Unknown("--gc-patrol 100 200"));
+
+        let cmd = Command::new("--gc-patrol 100 200 2 defend-me", "xplay", 0);
+        let err = ctrl
+            .parse_command(cmd)
+            .unwrap()
+            .expect_err("invalid command");
+        assert_eq!(err, BadCommand::BadSetting("defend-me"));
+    }
+
+    #[test]
+    fn help_summary() {
+        let cmd = Command::new("--gc-help", "xplay", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let msg = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command")
+            .msg();
+        assert_eq!(msg.len(), 2);
+        assert!(msg[0].contains("--gc-wings"));
+        assert!(msg[1].contains("call-off"));
+    }
+
+    #[test]
+    fn help_and_confirmation_mention_the_cap() {
+        let ctrl = ControlTower::new(7, "--gc");
+        let msg = ctrl
+            .parse_command(Command::new("--gc-help", "xplay", 0))
+            .expect("parsed something")
+            .expect("valid command")
+            .msg();
+        assert!(msg[1].contains("Up to 7 wings each"), "{:?}", msg);
+
+        let msg = ctrl
+            .parse_command(Command::new("--gc-wings 2", "xplay", 3))
+            .expect("parsed something")
+            .expect("valid command")
+            .msg();
+        assert_eq!(
+            msg,
+            vec!["OK xplay, 2 more wings are coming! (5 of 7 wings)"]
+        );
+    }
+
+    #[test]
+    fn split_for_chat_breaks_between_words() {
+        assert_eq!(
+            split_for_chat("Up to 4 wings each. Say --gc-help wings", 20),
+            vec!["Up to 4 wings each.", "Say --gc-help wings"]
+        );
+        assert_eq!(
+            split_for_chat("see xxxxxxxxxxxxxxxxxxxxxxxxx now", 10),
+            vec!["see", "xxxxxxxxxx", "xxxxxxxxxx", "xxxxx now"]
+        );
+        for piece in split_for_chat(&"a wing ".repeat(100), 255) {
+            assert!(piece.len() <= 255);
+        }
+    }
+
+    #[test]
+    fn split_for_chat_leaves_short_messages_alone() {
+        assert_eq!(
+            split_for_chat("OK xplay,  wings are coming!", 255),
+            vec!["OK xplay,  wings are coming!"]
+        );
+        assert_eq!(split_for_chat("", 255), vec![""]);
+        assert_eq!(split_for_chat(&"x".repeat(300), 0), vec!["x".repeat(300)]);
+    }
+
+    #[test]
+    fn version_matches_the_command_line() {
+        let ctrl = ControlTower::new(4, "--gc");
+        let msg = ctrl
+            .parse_command(Command::new("--gc-version", "xplay", 0))
+            .expect("parsed something")
+            .expect("valid command")
+            .msg();
+        assert_eq!(msg.len(), 1);
+        let expected = format!(
+            "AIRMASH Ground Control, version {}",
+            env!("CARGO_PKG_VERSION")
+        );
+        assert!(msg[0].starts_with(&expected), "unexpected reply {}", msg[0]);
+        for build in option_env!("GC_GIT_COMMIT")
+            .into_iter()
+            .chain(option_env!("GC_BUILD_DATE"))
+        {
+            assert!(msg[0].contains(build), "{} missing from {}", build, msg[0]);
+        }
+    }
+
+    #[test]
+    fn help_topic() {
+        let cmd = Command::new("--gc-help Wings", "xplay", 0);
+        let ctrl = ControlTower::new(4, "--gc");
+        let msg = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command")
+            .msg();
+        assert!(msg[0].starts_with("--gc-wings:"));
+        assert!(msg.iter().any(|line| line.contains("Up to 4 wings")));
+
+        let cmd = Command::new("--gc-help --gc-call-off", "xplay", 0);
+        let msg = ctrl.parse_command(cmd).unwrap().unwrap().msg();
+        assert!(msg[0].starts_with("--gc-call-off:"));
+    }
+
+    #[test]
+    fn help_unknown_topic() {
+        let cmd = Command::new("--gc-help teleport", "xplay", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert!(resp.kind().is_none());
+        let msg = resp.msg();
+        assert_eq!(msg.len(), 1);
+        assert!(msg[0].contains("no such help topic 'teleport'"));
+        assert!(msg[0].contains("defend"));
+    }
+
+    #[test]
+    fn not_authorized_message() {
+        let err = BadCommand::NotAuthorized("xplay");
+        assert_eq!(err.kind(), "not_authorized");
+        assert!(format!("{}", err).contains("xplay"));
+    }
+
+    #[test]
+    fn stats() {
+        let cmd = Command::new("--gc-stats", "xplay", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(resp.kind(), Some(ResponseKind::Stats));
+        assert!(resp.msg().is_empty());
+    }
+
+    #[test]
+    fn stats_message_format() {
+        assert_eq!(
+            stats_message(7, 3, 42),
+            vec![
+                "7 wingmen flying for 3 players".to_owned(),
+                "Ground control ping: 42 ms".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn targets() {
+        let cmd = Command::new("--gc-targets", "xplay", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(resp.kind(), Some(ResponseKind::Targets));
+        assert!(resp.msg().is_empty());
+    }
+
+    #[test]
+    fn targets_message_format() {
+        assert_eq!(
+            targets_message(&[]),
+            vec!["Nobody is under attack".to_owned()]
+        );
+
+        let targets: Vec<(String, usize)> = (1..=4)
+            .map(|wings| (format!("pilot{}", wings), wings))
+            .collect();
+        assert_eq!(
+            targets_message(&targets),
+            vec![
+                "pilot1: 1 wings, pilot2: 2 wings, pilot3: 3 wings".to_owned(),
+                "pilot4: 4 wings".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn request_wings_settings() {
+        let cmd = Command::new("--gc-wings 2 range=800 TYPE=tornado @Detect", "xplay", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(
+            resp.kind.expect("a response kind"),
+            ResponseKind::SetWings {
+                wings: 2,
+                targets: vec!["Detect".to_owned()],
+                ship: Some(protocol::PlaneType::Tornado),
+                behavior: Behavior::Attack,
+                range: Some(800),
+            }
+        )
+    }
+
+    #[test]
+    fn request_wings_range_clamped() {
+        let cmd = Command::new("--gc-wings 1 range=60000", "xplay", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl.parse_command(cmd).unwrap().unwrap();
+        match resp.kind() {
+            Some(ResponseKind::SetWings { range, .. }) => assert_eq!(range, Some(1200)),
+            kind => panic!("unexpected response {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn request_wings_bad_setting() {
+        let ctrl = ControlTower::new(5, "--gc");
+        let cmd = Command::new("--gc-wings 1 range=far", "xplay", 0);
+        let err = ctrl
+            .parse_command(cmd)
+            .unwrap()
+            .expect_err("invalid command");
+        assert_eq!(err, BadCommand::BadSetting("range=far"));
+
+        let cmd = Command::new("--gc-wings 1 color=red", "xplay", 0);
+        let err = ctrl
+            .parse_command(cmd)
+            .unwrap()
+            .expect_err("invalid command");
+        assert_eq!(err, BadCommand::BadSetting("color=red"));
+    }
+
+    #[test]
+    fn shards_split_users() {
+        let shards: Vec<_> = (0..3).map(|index| Shard::new(index, 3).unwrap()).collect();
+        for user in &["xplay", "putin copter", "Detect", "STEAMROLLER", ""] {
+            let owners = shards.iter().filter(|shard| shard.owns(user)).count();
+            assert_eq!(owners, 1, "{} has {} owners", user, owners);
+        }
+        assert!(Shard::default().owns("xplay"));
+        assert!(Shard::new(3, 3).is_err());
+    }
+
+    #[test]
+    fn greeting() {
+        assert_eq!(
+            greeting_message("xplay", "--gc-help", None),
+            vec!["Ground Control, standing by for xplay! Use --gc-help for help.".to_owned()]
+        );
+        let msg = greeting_message("xplay", "--gc-help", Some((12, 7)));
+        assert_eq!(msg.len(), 2);
+        assert_eq!(msg[1], "12 players online, 7 wingmen flying");
+    }
+
+    #[test]
+    fn recall_all() {
+        let cmd = Command::new("--gc-recall-all", "admin", 0);
+        let ctrl = ControlTower::new(5, "--gc");
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(resp.kind(), Some(ResponseKind::RecallAll));
+        assert!(resp.msg().is_empty());
+    }
+
+    #[test]
+    fn retarget() {
+        let ctrl = ControlTower::new(5, "--gc");
+        let cmd = Command::new("--gc-retarget @putin copter", "xplay", 3);
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(
+            resp.kind(),
+            Some(ResponseKind::Retarget {
+                target: "putin copter".to_owned()
+            })
+        );
+
+        let cmd = Command::new("--gc-retarget @Detect", "xplay", 0);
+        let err = ctrl.parse_command(cmd).unwrap().expect_err("no wings");
+        assert_eq!(err, BadCommand::NoWings("xplay"));
+
+        let cmd = Command::new("--gc-retarget Detect", "xplay", 2);
+        let err = ctrl.parse_command(cmd).unwrap().expect_err("no target");
+        assert_eq!(err, BadCommand::Unknown("--gc-retarget Detect"));
+    }
+
+    #[test]
+    fn mode() {
+        let ctrl = ControlTower::new(5, "--gc");
+        let cmd = Command::new("--gc-mode Defend", "xplay", 3);
+        let resp = ctrl
+            .parse_command(cmd)
+            .expect("parsed something")
+            .expect("valid command");
+        assert_eq!(
+            resp.kind(),
+            Some(ResponseKind::SetMode {
+                behavior: Behavior::Defend
+            })
+        );
+
+        let cmd = Command::new("--gc-mode defend-me", "xplay", 3);
+        let resp = ctrl.parse_command(cmd).unwrap().expect("valid command");
+        assert_eq!(
+            resp.kind(),
+            Some(ResponseKind::SetMode {
+                behavior: Behavior::Protect
+            })
+        );
+
+        let cmd = Command::new("--gc-mode attack", "xplay", 0);
+        let err = ctrl.parse_command(cmd).unwrap().expect_err("no wings");
+        assert_eq!(err, BadCommand::NoWings("xplay"));
+
+        let cmd = Command::new("--gc-mode sideways", "xplay", 2);
+        let err = ctrl.parse_command(cmd).unwrap().expect_err("no such mode");
+        assert_eq!(err, BadCommand::Unknown("--gc-mode sideways"));
+    }
 }
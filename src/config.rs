@@ -0,0 +1,321 @@
+//! Configuration file support
+//!
+//! A TOML configuration file lists servers to connect to, each with
+//! its own settings. Any setting left out falls back to the command
+//! line, then to the built-in default.
+//!
+//! ```toml
+//! [[servers]]
+//! url = "ws://us.airmash.online/ffa1"
+//! max_wingmen = 3
+//!
+//! [[servers]]
+//! url = "ws://eu.airmash.online/ffa1"
+//! announce = false
+//! ctrl_name = "EU-CTRL"
+//! allow = ["trusted", "vip"]
+//! ```
+//!
+//! Servers on the command line can carry a few settings of their own
+//! after a `#`, like `ws://us.airmash.online/ffa1#max=3,announce=false`.
+//! These win over the command line's flags.
+
+use serde::Deserialize;
+
+use std::error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// A problem with the command line or the configuration file
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// The configuration file couldn't be read or parsed
+    File(String),
+    /// A server URL couldn't be parsed, with the reason
+    InvalidUrl(String, String),
+    /// A server URL uses a scheme we can't connect to
+    UnsupportedScheme(String),
+    /// No servers were given anywhere
+    NoServers,
+    /// The maximum number of wingmen per player isn't a number from 0 to 255
+    InvalidMaxWingmen(String),
+    /// The command prefix doesn't look like a flag
+    InvalidPrefix(String),
+    /// The wing TTL isn't a duration
+    InvalidWingTtl(String),
+    /// A numeric option isn't a number, with the option's name, value and the
+    /// reason
+    InvalidNumber(&'static str, String, String),
+    /// The shard index is out of range for the shard count
+    InvalidShard(String),
+    /// The protocol version isn't supported
+    UnsupportedProtocol(String),
+    /// The flag code isn't one the server knows
+    UnknownFlag(String),
+    /// The map file couldn't be loaded
+    InvalidMap(String),
+    /// The wingmen's tick range is empty, or their fixed tick is 0
+    InvalidTick(String),
+    /// A setting given inline with a server URL is unknown or invalid, with
+    /// the server and the reason
+    InvalidServerOption(String, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::File(msg)
+            | ConfigError::UnsupportedScheme(msg)
+            | ConfigError::InvalidShard(msg)
+            | ConfigError::UnsupportedProtocol(msg)
+            | ConfigError::UnknownFlag(msg)
+            | ConfigError::InvalidMap(msg)
+            | ConfigError::InvalidTick(msg) => write!(f, "{}", msg),
+            ConfigError::InvalidUrl(url, reason) => {
+                write!(f, "invalid server URL '{}': {}", url, reason)
+            }
+            ConfigError::NoServers => write!(
+                f,
+                "no servers given on the command line or in the config file"
+            ),
+            ConfigError::InvalidMaxWingmen(value) => write!(
+                f,
+                "invalid max wingmen '{}': must be a number from 0 to 255",
+                value
+            ),
+            ConfigError::InvalidPrefix(prefix) => write!(
+                f,
+                "invalid prefix '{}': must start with '--' followed by a name",
+                prefix
+            ),
+            ConfigError::InvalidWingTtl(msg) => write!(f, "invalid wing TTL: {}", msg),
+            ConfigError::InvalidNumber(name, value, reason) => {
+                write!(f, "invalid {} '{}': {}", name, value, reason)
+            }
+            ConfigError::InvalidServerOption(server, reason) => {
+                write!(f, "invalid setting for server '{}': {}", server, reason)
+            }
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
+
+/// The contents of a configuration file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Servers to connect to
+    #[serde(default)]
+    pub servers: Vec<ServerConfig>,
+}
+
+/// Settings for one server; see `ServerArgs`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    /// The AIRMASH websocket server
+    pub url: String,
+    /// The maximum number of wingmen per player
+    pub max_wingmen: Option<u8>,
+    /// True to announce ourselves to new players
+    pub announce: Option<bool>,
+    /// The ground controller's name
+    pub ctrl_name: Option<String>,
+    /// Names of the only players allowed to request wings
+    pub allow: Option<Vec<String>>,
+}
+
+impl ServerConfig {
+    /// Parse a server given on the command line, with any settings after
+    /// a `#` in its URL
+    ///
+    /// Settings are comma-separated `key=value` pairs: `max` for the
+    /// maximum number of wingmen per player, `announce` as `true` or
+    /// `false`, and `name` for the ground controller's name.
+    pub fn inline(spec: &str) -> Result<ServerConfig, ConfigError> {
+        let mut parts = spec.splitn(2, '#');
+        let url = parts.next().unwrap_or_default();
+        let settings = parts.next().unwrap_or_default();
+        let invalid = |reason: String| ConfigError::InvalidServerOption(url.to_owned(), reason);
+        let mut server = ServerConfig {
+            url: url.to_owned(),
+            max_wingmen: None,
+            announce: None,
+            ctrl_name: None,
+            allow: None,
+        };
+        for setting in settings.split(',').filter(|setting| !setting.is_empty()) {
+            let mut pair = setting.splitn(2, '=');
+            let (key, value) = match (pair.next(), pair.next()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => return Err(invalid(format!("'{}' should be key=value", setting))),
+            };
+            match key {
+                "max" => match value.parse() {
+                    Ok(max) => server.max_wingmen = Some(max),
+                    Err(_) => {
+                        return Err(invalid(format!(
+                            "max '{}' must be a number from 0 to 255",
+                            value
+                        )))
+                    }
+                },
+                "announce" => match value.parse() {
+                    Ok(announce) => server.announce = Some(announce),
+                    Err(_) => {
+                        return Err(invalid(format!(
+                            "announce '{}' must be true or false",
+                            value
+                        )))
+                    }
+                },
+                "name" if !value.is_empty() => server.ctrl_name = Some(value.to_owned()),
+                "name" => return Err(invalid("name can't be empty".to_owned())),
+                _ => {
+                    return Err(invalid(format!(
+                        "unknown setting '{}': use max, announce or name",
+                        key
+                    )))
+                }
+            }
+        }
+        Ok(server)
+    }
+}
+
+/// Split a command line argument naming one or more servers into one
+/// string per server
+///
+/// Servers may be separated by commas as well as given separately. As
+/// inline settings are comma-separated too, a new server starts at each
+/// piece that looks like a URL.
+pub fn split_servers(arg: &str) -> Vec<String> {
+    let mut servers: Vec<String> = Vec::new();
+    for piece in arg.split(',').map(str::trim) {
+        match servers.last_mut() {
+            Some(server) if !piece.contains("://") => {
+                server.push(',');
+                server.push_str(piece);
+            }
+            _ => servers.push(piece.to_owned()),
+        }
+    }
+    servers.retain(|server| !server.is_empty());
+    servers
+}
+
+impl Config {
+    /// Parse a configuration from TOML
+    pub fn parse(text: &str) -> Result<Config, String> {
+        toml::from_str(text).map_err(|err| err.to_string())
+    }
+
+    /// Load a configuration from the file at `path`
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) => {
+                return Err(ConfigError::File(format!(
+                    "error reading config {}: {}",
+                    path.display(),
+                    err
+                )))
+            }
+        };
+        Config::parse(&text)
+            .map_err(|err| ConfigError::File(format!("invalid config {}: {}", path.display(), err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_servers, Config, ConfigError, ServerConfig};
+
+    #[test]
+    fn parse_servers() {
+        let config = Config::parse(
+            r#"
+            [[servers]]
+            url = "ws://localhost:3501"
+            max_wingmen = 3
+
+            [[servers]]
+            url = "ws://localhost:3502"
+            announce = false
+            ctrl_name = "CTRL"
+            allow = ["trusted"]
+            "#,
+        )
+        .expect("valid config");
+
+        assert_eq!(config.servers.len(), 2);
+        assert_eq!(config.servers[0].url, "ws://localhost:3501");
+        assert_eq!(config.servers[0].max_wingmen, Some(3));
+        assert_eq!(config.servers[0].announce, None);
+        assert_eq!(config.servers[1].announce, Some(false));
+        assert_eq!(config.servers[1].ctrl_name, Some("CTRL".to_owned()));
+        assert_eq!(config.servers[0].allow, None);
+        assert_eq!(config.servers[1].allow, Some(vec!["trusted".to_owned()]));
+    }
+
+    #[test]
+    fn parse_rejects_unknown_settings() {
+        let err = Config::parse(
+            r#"
+            [[servers]]
+            url = "ws://localhost:3501"
+            max_wingman = 3
+            "#,
+        )
+        .expect_err("invalid config");
+        assert!(err.contains("max_wingman"));
+    }
+
+    #[test]
+    fn split_comma_separated_servers() {
+        assert_eq!(
+            split_servers("ws://localhost:3501#max=3,announce=false,wss://localhost:3502"),
+            vec![
+                "ws://localhost:3501#max=3,announce=false",
+                "wss://localhost:3502"
+            ]
+        );
+        assert_eq!(
+            split_servers("ws://localhost:3501"),
+            vec!["ws://localhost:3501"]
+        );
+    }
+
+    #[test]
+    fn parse_inline_settings() {
+        let server = ServerConfig::inline("ws://localhost:3501#max=3,announce=false,name=CTRL")
+            .expect("valid settings");
+        assert_eq!(server.url, "ws://localhost:3501");
+        assert_eq!(server.max_wingmen, Some(3));
+        assert_eq!(server.announce, Some(false));
+        assert_eq!(server.ctrl_name, Some("CTRL".to_owned()));
+
+        let server = ServerConfig::inline("ws://localhost:3501").expect("no settings");
+        assert_eq!(server.url, "ws://localhost:3501");
+        assert_eq!(server.max_wingmen, None);
+    }
+
+    #[test]
+    fn parse_inline_rejects_bad_settings() {
+        for spec in &[
+            "ws://localhost:3501#max=lots",
+            "ws://localhost:3501#announce=maybe",
+            "ws://localhost:3501#color=red",
+            "ws://localhost:3501#max",
+        ] {
+            match ServerConfig::inline(spec) {
+                Err(ConfigError::InvalidServerOption(server, _)) => {
+                    assert_eq!(server, "ws://localhost:3501")
+                }
+                other => panic!("unexpected {:?} for {}", other, spec),
+            }
+        }
+    }
+}
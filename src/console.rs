@@ -0,0 +1,140 @@
+//! Admin console over stdin
+//!
+//! With `--console`, the operator can type admin commands into the
+//! terminal ground control runs in, without joining a game. Every
+//! command goes to every server, which handles it within a moment and
+//! logs the outcome.
+
+use std::io::{self, BufRead};
+use std::sync::mpsc;
+use std::thread;
+
+/// What the console understands
+const USAGE: &str = "console commands: list, recall <player>, recall-all, reload, help";
+
+/// An admin command typed at the console
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// List every player's wings
+    List,
+    /// Recall the wings of the named player
+    Recall(String),
+    /// Recall every player's wings
+    RecallAll,
+    /// Re-read who may request wings; handled by the console itself, which
+    /// sends each server its new list
+    Reload,
+    /// Answer wing requests only from the named players, or everyone if
+    /// empty; sent by the console after a reload
+    Allow(Vec<String>),
+    /// Show what the console understands; answered by the console itself
+    Help,
+}
+
+/// Where a server receives the commands typed at the console
+pub type Receiver = mpsc::Receiver<Command>;
+
+/// Parse a line typed at the console, or `None` if it's blank
+///
+/// Player names may contain spaces, so `recall` takes the rest of the line.
+pub fn parse(line: &str) -> Result<Option<Command>, String> {
+    let line = line.trim();
+    let mut words = line.splitn(2, char::is_whitespace);
+    let command = words.next().unwrap_or_default();
+    let rest = words.next().unwrap_or_default().trim();
+    match (command, rest) {
+        ("", _) => Ok(None),
+        ("list", "") => Ok(Some(Command::List)),
+        ("recall-all", "") => Ok(Some(Command::RecallAll)),
+        ("recall", "") => Err("recall needs a player: recall <player>".to_owned()),
+        ("recall", name) => Ok(Some(Command::Recall(name.to_owned()))),
+        ("reload", "") => Ok(Some(Command::Reload)),
+        ("help", "") => Ok(Some(Command::Help)),
+        _ => Err(format!("unknown command '{}'; {}", line, USAGE)),
+    }
+}
+
+/// Read commands from stdin on a thread of its own, passing each one to
+/// every server
+///
+/// `reload` reads the allow list of each server again, in the same order
+/// as `servers`. The thread stops at the end of stdin. Servers that have
+/// stopped are skipped.
+pub fn spawn<F>(servers: Vec<mpsc::Sender<Command>>, reload: F)
+where
+    F: Fn() -> Result<Vec<Vec<String>>, String> + Send + 'static,
+{
+    let spawned = thread::Builder::new()
+        .name("console".to_owned())
+        .spawn(move || {
+            log::info!("Reading admin commands from stdin; {}", USAGE);
+            let stdin = io::stdin();
+            for line in stdin.lock().lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(err) => {
+                        log::error!("error reading the console {}", err);
+                        return;
+                    }
+                };
+                match parse(&line) {
+                    Ok(Some(Command::Help)) => log::info!("{}", USAGE),
+                    Ok(Some(Command::Reload)) => match reload() {
+                        Ok(ref allows) if allows.len() != servers.len() => log::warn!(
+                            "not reloading: the servers have changed; restart to add or remove servers"
+                        ),
+                        Ok(allows) => {
+                            for (server, allow) in servers.iter().zip(allows) {
+                                let _ = server.send(Command::Allow(allow));
+                            }
+                            log::info!("reloaded who may request wings");
+                        }
+                        Err(err) => log::warn!("not reloading: {}", err),
+                    },
+                    Ok(Some(command)) => {
+                        for server in &servers {
+                            // A stopped server has nothing left to recall
+                            let _ = server.send(command.clone());
+                        }
+                    }
+                    Ok(None) => (),
+                    Err(err) => log::warn!("{}", err),
+                }
+            }
+            log::info!("console closed; no more admin commands will be read");
+        });
+    if let Err(err) = spawned {
+        log::error!("error starting the console {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Command};
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(parse("list"), Ok(Some(Command::List)));
+        assert_eq!(parse("  recall-all \n"), Ok(Some(Command::RecallAll)));
+        assert_eq!(
+            parse("recall Mr Bones"),
+            Ok(Some(Command::Recall("Mr Bones".to_owned())))
+        );
+        assert_eq!(parse("reload"), Ok(Some(Command::Reload)));
+        assert_eq!(parse("help"), Ok(Some(Command::Help)));
+        assert_eq!(parse("   "), Ok(None));
+    }
+
+    #[test]
+    fn parse_rejects_bad_commands() {
+        for line in &[
+            "recall",
+            "recall   ",
+            "list everyone",
+            "reload now",
+            "launch",
+        ] {
+            assert!(parse(line).is_err(), "accepted {:?}", line);
+        }
+    }
+}
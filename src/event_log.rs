@@ -0,0 +1,147 @@
+//! Log of every command ground control answers
+//!
+//! With an event log, each command a server parses is appended to the
+//! log file as one line of JSON: who sent it, what they said, what ground
+//! control decided, and how many wings they had afterwards. External tools
+//! can follow the file to watch decisions as they're made, or replay it to
+//! audit them. Every server shares one log.
+
+use crate::commands::{BadCommand, ResponseKind};
+
+use serde::Serialize;
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What ground control decided about a command
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome<'a> {
+    /// The command was carried out, with the action taken, if any
+    Accepted { kind: Option<ResponseKind> },
+    /// The command was turned down, and why
+    Rejected {
+        error: &'a BadCommand<'a>,
+        kind: &'static str,
+        reason: String,
+    },
+}
+
+impl<'a> Outcome<'a> {
+    /// The outcome of a command that was turned down with `error`
+    pub fn rejected(error: &'a BadCommand<'a>) -> Self {
+        Outcome::Rejected {
+            error,
+            kind: error.kind(),
+            reason: error.to_string(),
+        }
+    }
+}
+
+/// One command, as written to the log
+#[derive(Debug, Serialize)]
+pub struct Record<'a> {
+    /// Milliseconds since the Unix epoch when the command was handled
+    pub timestamp: u64,
+    /// URL of the server the command was sent on
+    pub server: &'a str,
+    /// Name of the player who sent the command
+    pub user: &'a str,
+    /// The message as they typed it
+    pub message: &'a str,
+    /// What ground control decided
+    pub outcome: Outcome<'a>,
+    /// Number of wings the player had once the command was handled
+    pub wings: usize,
+}
+
+/// Milliseconds since the Unix epoch
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() * 1000 + u64::from(since.subsec_millis()))
+        .unwrap_or(0)
+}
+
+/// The event log file shared by every server
+#[derive(Debug)]
+pub struct EventLog {
+    file: Mutex<File>,
+}
+
+/// The event log, shared between servers
+pub type Handle = Arc<EventLog>;
+
+impl EventLog {
+    /// Open the log at `path` for appending, creating it if needed
+    pub fn open(path: &Path) -> Result<Handle, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| format!("can't open event log {}: {}", path.display(), err))?;
+        Ok(Arc::new(EventLog {
+            file: Mutex::new(file),
+        }))
+    }
+
+    /// Append a record to the log
+    ///
+    /// The log is only for watching, so errors writing it are logged and
+    /// otherwise ignored.
+    pub fn record(&self, record: &Record) {
+        let mut line = match serde_json::to_string(record) {
+            Ok(line) => line,
+            Err(err) => {
+                log::error!("error serializing event: {}", err);
+                return;
+            }
+        };
+        line.push('\n');
+        if let Ok(mut file) = self.file.lock() {
+            if let Err(err) = file.write_all(line.as_bytes()) {
+                log::warn!("error writing event log: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(outcome: Outcome) -> serde_json::Value {
+        let record = Record {
+            timestamp: 1234,
+            server: "ws://localhost:3501/ffa",
+            user: "xplay",
+            message: "--gc-call-off",
+            outcome,
+            wings: 0,
+        };
+        serde_json::from_str(&serde_json::to_string(&record).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn serializes_accepted_commands() {
+        let json = record(Outcome::Accepted {
+            kind: Some(ResponseKind::ClearWings),
+        });
+        assert_eq!(json["user"], "xplay");
+        assert_eq!(json["timestamp"], 1234);
+        assert_eq!(json["outcome"]["accepted"]["kind"], "ClearWings");
+    }
+
+    #[test]
+    fn serializes_rejected_commands() {
+        let err = BadCommand::NoWings("xplay");
+        let json = record(Outcome::rejected(&err));
+        let rejected = &json["outcome"]["rejected"];
+        assert_eq!(rejected["error"]["NoWings"], "xplay");
+        assert_eq!(rejected["kind"], "no_wings");
+        assert_eq!(rejected["reason"], "no wings assigned to xplay");
+    }
+}
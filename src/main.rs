@@ -3,27 +3,74 @@
 #[macro_use]
 mod logging;
 
+mod assignments;
+mod client;
 mod commands;
+mod config;
+mod console;
+mod event_log;
 mod map;
+mod metrics;
+mod net;
 mod server;
+mod state;
+mod status;
 mod types;
 mod wing;
 
-use airmash_client::Client;
-use airmash_protocol as protocol;
+use crate::config::ConfigError;
 
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use url::Url;
 
 /// Default ground control name
 static DEFAULT_GROUND_CTRL_NAME: &'static str = "GROUND-CTRL";
 
+/// Default start of every wingman's name
+static DEFAULT_WING_NAME_PREFIX: &'static str = "WING-";
+
 /// Maximum number of wingmen per player
 const DEFAULT_MAX_WINGMEN: u8 = 5;
 
+/// Maximum number of wingmen across all players on a server
+const DEFAULT_MAX_TOTAL_WINGMEN: usize = 40;
+
+/// Seconds a player must wait between wing requests
+const DEFAULT_REQUEST_COOLDOWN: u64 = 10;
+
+/// Number of times to try reconnecting to a server
+const DEFAULT_MAX_RECONNECTS: u32 = 5;
+
+/// Milliseconds between chat messages sent by ground control
+const DEFAULT_CHAT_INTERVAL: u64 = 1000;
+
+/// Characters the server allows in one chat message
+const DEFAULT_MAX_MSG_LEN: usize = 255;
+
+/// Seconds during which a repeat of the same error to a player is not sent
+const DEFAULT_ERROR_WINDOW: u64 = 5;
+
+/// Minutes between reminders that ground control is available; 0 disables
+/// the reminders
+const DEFAULT_ANNOUNCE_INTERVAL: u64 = 0;
+
+/// Seconds after greeting a player during which they aren't greeted again
+/// if they rejoin; 0 greets them every time
+const DEFAULT_GREET_WINDOW: u64 = 300;
+
+/// Map cells that wingmen keep between themselves and mountains when pathfinding
+const DEFAULT_CLEARANCE: isize = 1;
+
+/// Seconds to wait before the first reconnect attempt
+const DEFAULT_RECONNECT_BACKOFF: u64 = 2;
+
 /// Arguments provided from the command line
 /// used for spawning servers
-struct ServerArgs {
+pub struct ServerArgs {
     /// URL of the client we're talking to
     url: Url,
     /// The maximum number of wingmen for the
@@ -33,24 +80,446 @@ struct ServerArgs {
     /// to newly joining players, else false
     /// to stay quiet
     announce: bool,
+    /// True to tell newly joining players how
+    /// many players and wingmen are online
+    rich_announce: bool,
+    /// Time between reminders to everyone that
+    /// ground control is available; zero for
+    /// no reminders
+    announce_interval: Duration,
+    /// Time after greeting a player during which
+    /// they aren't greeted again; zero to greet
+    /// every time
+    greet_window: Duration,
     /// The ground controller's name
     ctrl_name: String,
+    /// How long wingmen stay assigned after a
+    /// request before they're recalled, if limited
+    wing_ttl: Option<Duration>,
+    /// Prefix for all ground control commands
+    prefix: String,
+    /// The players this ground controller
+    /// answers, when several share a server
+    shard: commands::Shard,
+    /// Minimum time between wing requests from
+    /// the same player
+    request_cooldown: Duration,
+    /// Maximum number of wingmen across all
+    /// players
+    max_total_wingmen: usize,
+    /// Maximum number of wingmen across all
+    /// players on one team, if limited
+    max_team_wingmen: Option<usize>,
+    /// Options for every spawned wingman
+    wing_options: wing::Options,
+    /// Maximum number of reconnect attempts after
+    /// losing the connection
+    max_reconnects: u32,
+    /// Delay before the first reconnect attempt,
+    /// doubling with each failed attempt
+    reconnect_backoff: Duration,
+    /// Paces reconnects, shared with every
+    /// other server
+    reconnect_limiter: net::ReconnectLimiter,
+    /// True to whisper responses to players
+    /// instead of replying in public chat
+    whisper: bool,
+    /// True to spectate the player with the
+    /// most wingmen instead of looking around
+    follow_active: bool,
+    /// True to go through the motions of
+    /// spawning wingmen without connecting any
+    dry_run: bool,
+    /// Minimum time between any two chat
+    /// messages, to avoid flood protection
+    chat_interval: Duration,
+    /// Time during which a repeat of the same
+    /// error to a player isn't sent again
+    error_window: Duration,
+    /// Put in front of every chat message
+    /// ground control sends
+    chat_tag: String,
+    /// Longest chat message the server allows,
+    /// in characters, or 0 for no limit
+    max_msg_len: usize,
+    /// Names of players allowed to use
+    /// admin commands
+    admins: Vec<String>,
+    /// Names of players allowed to request
+    /// wings; empty to allow everyone
+    allow: Vec<String>,
+    /// Names of players wingmen won't
+    /// be sent to attack
+    protected: Vec<String>,
+    /// Port for the HTTP status endpoint,
+    /// shared by all servers, if enabled
+    http_port: Option<u16>,
+    /// File where every server's assignments
+    /// are saved across restarts, if any
+    state_file: Option<PathBuf>,
+    /// File where every command and its outcome
+    /// are logged as JSON lines, if any
+    event_log: Option<PathBuf>,
+    /// True to only check that every server
+    /// can be logged in to, then exit
+    check: bool,
+    /// True to read admin commands for every
+    /// server from stdin
+    console: bool,
+}
+
+impl ServerArgs {
+    /// Arguments for ground control on the server at `url`, with every
+    /// option at its default
+    ///
+    /// Options can then be changed with the setters below, for example
+    /// `ServerArgs::new(url).max_wingmen(3).whisper(true)`.
+    pub fn new(url: Url) -> Self {
+        ServerArgs {
+            url,
+            max_wingmen: DEFAULT_MAX_WINGMEN,
+            announce: true,
+            rich_announce: false,
+            announce_interval: Duration::from_secs(DEFAULT_ANNOUNCE_INTERVAL * 60),
+            greet_window: Duration::from_secs(DEFAULT_GREET_WINDOW),
+            ctrl_name: DEFAULT_GROUND_CTRL_NAME.to_owned(),
+            wing_ttl: None,
+            prefix: commands::command::DEFAULT_PREFIX.to_owned(),
+            shard: commands::Shard::default(),
+            request_cooldown: Duration::from_secs(DEFAULT_REQUEST_COOLDOWN),
+            max_total_wingmen: DEFAULT_MAX_TOTAL_WINGMEN,
+            max_team_wingmen: None,
+            wing_options: wing::Options {
+                friendly_fire: false,
+                map: Arc::new(types::Map::builtin()),
+                retreat: true,
+                clearance: DEFAULT_CLEARANCE,
+                login: net::login_defaults(),
+                upgrades: true,
+                dodge: false,
+                cautious: false,
+                tick: wing::Tick::default(),
+                threat_radius: wing::DEFAULT_THREAT_RADIUS,
+                spawn_schedule: wing::SpawnSchedule::new(Duration::from_millis(
+                    wing::DEFAULT_SPAWN_STAGGER,
+                )),
+                max_chase_distance: wing::DEFAULT_MAX_CHASE_DISTANCE,
+                target_grace: Duration::from_secs(wing::DEFAULT_TARGET_GRACE),
+                stale_after: Duration::from_secs(net::DEFAULT_STALE_AFTER),
+                name_prefix: DEFAULT_WING_NAME_PREFIX.to_owned(),
+                leader_follow: false,
+                grab_powerups: false,
+            },
+            max_reconnects: DEFAULT_MAX_RECONNECTS,
+            reconnect_backoff: Duration::from_secs(DEFAULT_RECONNECT_BACKOFF),
+            reconnect_limiter: net::ReconnectLimiter::new(net::DEFAULT_RECONNECT_RATE),
+            whisper: false,
+            follow_active: false,
+            dry_run: false,
+            chat_interval: Duration::from_millis(DEFAULT_CHAT_INTERVAL),
+            error_window: Duration::from_secs(DEFAULT_ERROR_WINDOW),
+            chat_tag: String::new(),
+            max_msg_len: DEFAULT_MAX_MSG_LEN,
+            admins: Vec::new(),
+            allow: Vec::new(),
+            protected: Vec::new(),
+            http_port: None,
+            state_file: None,
+            event_log: None,
+            check: false,
+            console: false,
+        }
+    }
+
+    /// Allow each player up to `max_wingmen` wingmen
+    pub fn max_wingmen(self, max_wingmen: u8) -> Self {
+        ServerArgs {
+            max_wingmen,
+            ..self
+        }
+    }
+
+    /// Announce ourselves to newly joining players, or stay quiet
+    pub fn announce(self, announce: bool) -> Self {
+        ServerArgs { announce, ..self }
+    }
+
+    /// Tell newly joining players how many players and wingmen are online
+    pub fn rich_announce(self, rich_announce: bool) -> Self {
+        ServerArgs {
+            rich_announce,
+            ..self
+        }
+    }
+
+    /// Remind everyone that ground control is available this often; zero
+    /// for no reminders
+    pub fn announce_interval(self, announce_interval: Duration) -> Self {
+        ServerArgs {
+            announce_interval,
+            ..self
+        }
+    }
+
+    /// Don't greet a player again if they rejoin within `greet_window` of
+    /// their last greeting; zero to greet them every time
+    pub fn greet_window(self, greet_window: Duration) -> Self {
+        ServerArgs {
+            greet_window,
+            ..self
+        }
+    }
+
+    /// Log in as `ctrl_name`
+    pub fn ctrl_name(self, ctrl_name: String) -> Self {
+        ServerArgs { ctrl_name, ..self }
+    }
+
+    /// Recall wingmen this long after a request, if limited
+    pub fn wing_ttl(self, wing_ttl: Option<Duration>) -> Self {
+        ServerArgs { wing_ttl, ..self }
+    }
+
+    /// Start every ground control command with `prefix`
+    pub fn prefix(self, prefix: String) -> Self {
+        ServerArgs { prefix, ..self }
+    }
+
+    /// Answer only the players in `shard`
+    pub fn shard(self, shard: commands::Shard) -> Self {
+        ServerArgs { shard, ..self }
+    }
+
+    /// Make players wait this long between wing requests
+    pub fn request_cooldown(self, request_cooldown: Duration) -> Self {
+        ServerArgs {
+            request_cooldown,
+            ..self
+        }
+    }
+
+    /// Allow up to `max_total_wingmen` wingmen across all players
+    pub fn max_total_wingmen(self, max_total_wingmen: usize) -> Self {
+        ServerArgs {
+            max_total_wingmen,
+            ..self
+        }
+    }
+
+    /// Allow up to this many wingmen across all players on one team, if
+    /// limited
+    pub fn max_team_wingmen(self, max_team_wingmen: Option<usize>) -> Self {
+        ServerArgs {
+            max_team_wingmen,
+            ..self
+        }
+    }
+
+    /// Spawn every wingman with `wing_options`
+    pub fn wing_options(self, wing_options: wing::Options) -> Self {
+        ServerArgs {
+            wing_options,
+            ..self
+        }
+    }
+
+    /// Try reconnecting up to `max_reconnects` times after losing the
+    /// connection
+    pub fn max_reconnects(self, max_reconnects: u32) -> Self {
+        ServerArgs {
+            max_reconnects,
+            ..self
+        }
+    }
+
+    /// Wait this long before the first reconnect attempt
+    pub fn reconnect_backoff(self, reconnect_backoff: Duration) -> Self {
+        ServerArgs {
+            reconnect_backoff,
+            ..self
+        }
+    }
+
+    /// Wait for `reconnect_limiter` before each reconnect attempt; share
+    /// one limiter between servers to pace their reconnects together
+    pub fn reconnect_limiter(self, reconnect_limiter: net::ReconnectLimiter) -> Self {
+        ServerArgs {
+            reconnect_limiter,
+            ..self
+        }
+    }
+
+    /// Whisper responses to players instead of replying in public chat
+    pub fn whisper(self, whisper: bool) -> Self {
+        ServerArgs { whisper, ..self }
+    }
+
+    /// Spectate the player with the most wingmen instead of looking around
+    pub fn follow_active(self, follow_active: bool) -> Self {
+        ServerArgs {
+            follow_active,
+            ..self
+        }
+    }
+
+    /// Go through the motions of spawning wingmen without connecting any
+    pub fn dry_run(self, dry_run: bool) -> Self {
+        ServerArgs { dry_run, ..self }
+    }
+
+    /// Send chat messages no closer together than `chat_interval`
+    pub fn chat_interval(self, chat_interval: Duration) -> Self {
+        ServerArgs {
+            chat_interval,
+            ..self
+        }
+    }
+
+    /// Don't repeat the same error to a player within `error_window`
+    pub fn error_window(self, error_window: Duration) -> Self {
+        ServerArgs {
+            error_window,
+            ..self
+        }
+    }
+
+    /// Start every chat message with `chat_tag`, like `[GC] `
+    pub fn chat_tag(self, chat_tag: String) -> Self {
+        ServerArgs { chat_tag, ..self }
+    }
+
+    /// Split chat messages longer than `max_msg_len` characters, or never
+    /// if 0
+    pub fn max_msg_len(self, max_msg_len: usize) -> Self {
+        ServerArgs {
+            max_msg_len,
+            ..self
+        }
+    }
+
+    /// Let the named players use admin commands
+    pub fn admins(self, admins: Vec<String>) -> Self {
+        ServerArgs { admins, ..self }
+    }
+
+    /// Answer wing requests only from the named players; empty to allow
+    /// everyone
+    pub fn allow(self, allow: Vec<String>) -> Self {
+        ServerArgs { allow, ..self }
+    }
+
+    /// Refuse to send wings to attack the named players
+    pub fn protected(self, protected: Vec<String>) -> Self {
+        ServerArgs { protected, ..self }
+    }
+
+    /// Serve status over HTTP on this port, if any
+    pub fn http_port(self, http_port: Option<u16>) -> Self {
+        ServerArgs { http_port, ..self }
+    }
+
+    /// Save assignments across restarts in this file, if any
+    pub fn state_file(self, state_file: Option<PathBuf>) -> Self {
+        ServerArgs { state_file, ..self }
+    }
+
+    /// Log every command and its outcome to this file, if any
+    pub fn event_log(self, event_log: Option<PathBuf>) -> Self {
+        ServerArgs { event_log, ..self }
+    }
+
+    /// Only check that the server can be logged in to, then exit
+    pub fn check(self, check: bool) -> Self {
+        ServerArgs { check, ..self }
+    }
+
+    /// Read admin commands for the server from stdin
+    pub fn console(self, console: bool) -> Self {
+        ServerArgs { console, ..self }
+    }
+}
+
+/// Parse a duration like `90s`, `30m` or `2h`; a bare number is in minutes
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let (number, secs_per_unit) = match text.chars().last() {
+        Some('s') => (&text[..text.len() - 1], 1),
+        Some('m') => (&text[..text.len() - 1], 60),
+        Some('h') => (&text[..text.len() - 1], 60 * 60),
+        _ => (text, 60),
+    };
+    let number = number
+        .parse::<u64>()
+        .map_err(|err| format!("invalid duration '{}': {}", text, err))?;
+    number
+        .checked_mul(secs_per_unit)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("invalid duration '{}': too long", text))
+}
+
+/// The number given for the flag `name`, or an error naming it by `label`
+fn number_arg<T>(args: &clap::ArgMatches, name: &str, label: &'static str) -> Result<T, ConfigError>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value = args.value_of(name).unwrap_or("");
+    value
+        .parse()
+        .map_err(|err| ConfigError::InvalidNumber(label, value.to_owned(), format!("{}", err)))
 }
 
 /// Command-line argument parsing. Returns the arguments
-/// to start servers, or a message describing an error.
-fn parse_args() -> Result<Vec<ServerArgs>, String> {
-    use clap::{crate_version, App, Arg};
+/// to start servers, or the problem with the arguments.
+///
+/// Servers come from the command line and from an optional
+/// config file. A flag given on the command line overrides
+/// the config file, which overrides the built-in default.
+fn parse_args<I, T>(argv: I) -> Result<Vec<ServerArgs>, ConfigError>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    use clap::{App, Arg};
     let default_wingmen_str = DEFAULT_MAX_WINGMEN.to_string();
+    let default_total_wingmen_str = DEFAULT_MAX_TOTAL_WINGMEN.to_string();
+    let default_cooldown_str = DEFAULT_REQUEST_COOLDOWN.to_string();
+    let default_announce_interval_str = DEFAULT_ANNOUNCE_INTERVAL.to_string();
+    let default_greet_window_str = DEFAULT_GREET_WINDOW.to_string();
+    let default_reconnects_str = DEFAULT_MAX_RECONNECTS.to_string();
+    let default_backoff_str = DEFAULT_RECONNECT_BACKOFF.to_string();
+    let default_reconnect_rate_str = net::DEFAULT_RECONNECT_RATE.to_string();
+    let default_chat_interval_str = DEFAULT_CHAT_INTERVAL.to_string();
+    let default_max_msg_len_str = DEFAULT_MAX_MSG_LEN.to_string();
+    let default_error_window_str = DEFAULT_ERROR_WINDOW.to_string();
+    let default_clearance_str = DEFAULT_CLEARANCE.to_string();
+    let default_protocol_str = net::DEFAULT_PROTOCOL.to_string();
+    let default_horizon_str = net::DEFAULT_HORIZON.to_string();
+    let default_tick_min_str = wing::DEFAULT_TICK_MIN.to_string();
+    let default_tick_max_str = wing::DEFAULT_TICK_MAX.to_string();
+    let default_threat_radius_str = wing::DEFAULT_THREAT_RADIUS.to_string();
+    let default_spawn_stagger_str = wing::DEFAULT_SPAWN_STAGGER.to_string();
+    let default_target_grace_str = wing::DEFAULT_TARGET_GRACE.to_string();
+    let default_stale_after_str = net::DEFAULT_STALE_AFTER.to_string();
+    let default_connect_timeout_str = net::DEFAULT_CONNECT_TIMEOUT.to_string();
+    let default_login_timeout_str = net::DEFAULT_LOGIN_TIMEOUT.to_string();
+    let default_max_chase_distance_str = wing::DEFAULT_MAX_CHASE_DISTANCE.to_string();
+    let version = commands::version();
     let args = App::new("AIRMASH Ground Control")
         .about("Client for dispatching bots")
-        .version(crate_version!())
+        .version(version.as_str())
         .arg(
             Arg::with_name("servers")
-                .help("The AIRMASH websocket servers to interface")
+                .help("The AIRMASH websocket servers to interface, separated by spaces or commas; follow a URL with #max=N,announce=false,name=NAME to override settings for it alone")
                 .takes_value(true)
                 .multiple(true)
-                .required(true),
+                .required_unless("config"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .help("TOML file listing servers and their settings")
+                .takes_value(true)
+                .required(false),
         )
         .arg(
             Arg::with_name("max_wingmen")
@@ -60,6 +529,21 @@ fn parse_args() -> Result<Vec<ServerArgs>, String> {
                 .takes_value(true)
                 .required(false),
         )
+        .arg(
+            Arg::with_name("max_total_wingmen")
+                .long("max-total-wingmen")
+                .help("The maximum number of wingmen across all players per server")
+                .default_value(&default_total_wingmen_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("max_team_wingmen")
+                .long("max-team-wingmen")
+                .help("The maximum number of wingmen across all players on one team")
+                .takes_value(true)
+                .required(false),
+        )
         .arg(
             Arg::with_name("no_announce")
                 .long("no-announce")
@@ -68,6 +552,112 @@ fn parse_args() -> Result<Vec<ServerArgs>, String> {
                 .required(false)
                 .takes_value(false),
         )
+        .arg(
+            Arg::with_name("rich_announce")
+                .long("rich-announce")
+                .help("When announcing to a new player, also say how many players and wingmen are online")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("announce_interval")
+                .long("announce-interval")
+                .help("Minutes between reminders that ground control is available; 0 to disable")
+                .default_value(&default_announce_interval_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("greet_window")
+                .long("greet-window")
+                .help("Seconds after greeting a player before greeting them again if they rejoin; 0 to greet every time")
+                .default_value(&default_greet_window_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("wing_ttl")
+                .long("wing-ttl")
+                .help("Recall wingmen this long after they were requested, like 30m or 2h; 0 to never recall them")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("follow_active")
+                .long("follow-active")
+                .help("Spectate the player with the most wingmen, for operators watching in the game")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("whisper")
+                .long("whisper")
+                .help("Whisper responses and announcements instead of using public chat")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("dry_run")
+                .long("dry-run")
+                .help("Answer commands as usual, but only log the wingmen that would be spawned")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("chat_interval")
+                .long("chat-interval")
+                .help("Minimum milliseconds between chat messages from ground control")
+                .default_value(&default_chat_interval_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("error_window")
+                .long("error-window")
+                .help("Seconds during which the same error isn't repeated to a player")
+                .default_value(&default_error_window_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("chat_tag")
+                .long("chat-tag")
+                .help("Text to start every chat message from ground control with, like '[GC] '")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("max_msg_len")
+                .long("max-msg-len")
+                .help("Longest chat message the server allows, in characters; longer replies are split between words, or never if 0")
+                .default_value(&default_max_msg_len_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("admins")
+                .long("admins")
+                .help("Comma-separated names of players allowed to use admin commands")
+                .takes_value(true)
+                .use_delimiter(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("allow")
+                .long("allow")
+                .help("Comma-separated names of the only players allowed to request wings")
+                .takes_value(true)
+                .use_delimiter(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("protected")
+                .long("protected")
+                .help("Comma-separated names of players that wings won't attack")
+                .takes_value(true)
+                .use_delimiter(true)
+                .required(false),
+        )
         .arg(
             Arg::with_name("ctrl_name")
                 .long("name")
@@ -75,86 +665,801 @@ fn parse_args() -> Result<Vec<ServerArgs>, String> {
                 .default_value(DEFAULT_GROUND_CTRL_NAME)
                 .required(false),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("wing_name_prefix")
+                .long("wing-name-prefix")
+                .help("Start of every wingman's name, followed by its target's name and number")
+                .default_value(DEFAULT_WING_NAME_PREFIX)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("prefix")
+                .long("prefix")
+                .help("Prefix for all ground control commands")
+                .default_value(commands::command::DEFAULT_PREFIX)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("shard_index")
+                .long("shard-index")
+                .help("This ground controller's shard, from 0, when several share the servers")
+                .default_value("0")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("shard_count")
+                .long("shard-count")
+                .help("Number of ground controllers sharing the servers")
+                .default_value("1")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("request_cooldown")
+                .long("request-cooldown")
+                .help("Seconds a player must wait between wing requests")
+                .default_value(&default_cooldown_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("friendly_fire")
+                .long("friendly-fire")
+                .help("Let wingmen fire even when teammates are in the way")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("no_retreat")
+                .long("no-retreat")
+                .help("Keep wingmen attacking instead of retreating when badly damaged")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("dodge")
+                .long("dodge")
+                .help("Have wingmen swerve out of the way of incoming missiles")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("cautious")
+                .long("cautious")
+                .help("Have wingmen path around clusters of enemies instead of through them")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("leader_follow")
+                .long("leader-follow")
+                .help("Have each player's first wingman lead the rest, which fly in formation with it")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("grab_powerups")
+                .long("grab-powerups")
+                .help("Have wingmen pick up nearby upgrades and powerups while they wait for their target")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("no_upgrades")
+                .long("no-upgrades")
+                .help("Don't let wingmen spend the upgrade points they earn")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("check")
+                .long("check")
+                .help("Check that every server can be logged in to, report how long each took, and exit")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("console")
+                .long("console")
+                .help("Read admin commands like list, recall-all and reload for every server from stdin")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("http_port")
+                .long("http-port")
+                .help("Serve a JSON status endpoint for all servers on this port")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("state_file")
+                .long("state-file")
+                .help("Save wing assignments to this file, and restore them on startup")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("event_log")
+                .long("event-log")
+                .help("Append every command and its outcome to this file as JSON lines")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("clearance")
+                .long("clearance")
+                .help("Map cells wingmen try to keep between themselves and mountains")
+                .default_value(&default_clearance_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("tick_min")
+                .long("tick-min")
+                .help("Fewest milliseconds wingmen wait between steering updates, however low the ping")
+                .default_value(&default_tick_min_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("tick_max")
+                .long("tick-max")
+                .help("Most milliseconds wingmen wait between steering updates, however high the ping")
+                .default_value(&default_tick_max_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("tick_fixed")
+                .long("tick-fixed")
+                .help("Milliseconds wingmen wait between steering updates regardless of ping")
+                .conflicts_with_all(&["tick_min", "tick_max"])
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("spawn_stagger")
+                .long("spawn-stagger")
+                .help("Milliseconds between wingmen logging in, across all servers; 0 to log in at once")
+                .default_value(&default_spawn_stagger_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("target_grace")
+                .long("target-grace")
+                .help("Seconds to wait for a player who left to rejoin before recalling or giving up on them")
+                .default_value(&default_target_grace_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("stale_after")
+                .long("stale-after")
+                .help("Seconds without hearing from a server before reconnecting, or a wingman giving up; 0 to wait forever")
+                .default_value(&default_stale_after_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("connect_timeout")
+                .long("connect-timeout")
+                .help("Seconds to wait for a connection to a server to open before giving up on it; 0 to wait forever")
+                .default_value(&default_connect_timeout_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("login_timeout")
+                .long("login-timeout")
+                .help("Seconds to wait for a server to accept a login before giving up on it; 0 to wait forever")
+                .default_value(&default_login_timeout_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("max_chase_distance")
+                .long("max-chase-distance")
+                .help("Distance from their target beyond which wingmen give up a chase they aren't gaining on; 0 to chase anywhere")
+                .default_value(&default_max_chase_distance_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("threat_radius")
+                .long("threat-radius")
+                .help("Distance from their player within which defend-me wingmen hunt enemies")
+                .default_value(&default_threat_radius_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("map")
+                .long("map")
+                .help("Collision map file used for pathfinding; defaults to the standard map")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("protocol")
+                .long("protocol")
+                .help("Protocol version to log in with")
+                .default_value(&default_protocol_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("flag")
+                .long("flag")
+                .help("Code of the flag shown next to ground control's and its wingmen's names, like GB")
+                .default_value(net::DEFAULT_FLAG)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("horizon")
+                .long("horizon")
+                .help("Distance around each bot that the server reports players and missiles")
+                .default_value(&default_horizon_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("max_reconnects")
+                .long("max-reconnects")
+                .help("Times to try reconnecting after losing a server connection")
+                .default_value(&default_reconnects_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("reconnect_backoff")
+                .long("reconnect-backoff")
+                .help("Seconds to wait before the first reconnect; doubles on each attempt")
+                .default_value(&default_backoff_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("reconnect_rate")
+                .long("reconnect-rate")
+                .help("Reconnects allowed a minute across all servers; 0 allows any number")
+                .default_value(&default_reconnect_rate_str)
+                .takes_value(true)
+                .required(false),
+        )
+        .get_matches_from(argv);
+
+    let config = match args.value_of("config") {
+        Some(path) => config::Config::load(Path::new(path))?,
+        None => config::Config::default(),
+    };
+
+    // Config file servers come first, each with its own settings. Servers on
+    // the command line may have settings of their own inline.
+    let mut servers = Vec::new();
+    for server in config.servers {
+        match Url::parse(&server.url) {
+            Ok(url) => servers.push((url, Some(server), None)),
+            Err(err) => return Err(ConfigError::InvalidUrl(server.url, err.to_string())),
+        }
+    }
+    for arg in args.values_of("servers").into_iter().flatten() {
+        for spec in config::split_servers(arg) {
+            let server = config::ServerConfig::inline(&spec)?;
+            match Url::parse(&server.url) {
+                Ok(url) => servers.push((url, None, Some(server))),
+                Err(err) => return Err(ConfigError::InvalidUrl(server.url, err.to_string())),
+            }
+        }
+    }
+    if servers.is_empty() {
+        return Err(ConfigError::NoServers);
+    }
+    for (url, _, _) in &servers {
+        net::check_scheme(url).map_err(ConfigError::UnsupportedScheme)?;
+    }
 
-    let servers: Result<Vec<Url>, _> = args
-        .values_of("servers")
-        .map(|servers| servers.map(Url::parse))
-        .unwrap() // clap enforces required value
-        .collect();
+    // Only flags the user actually passed override the config file
+    let explicit = |name: &str| args.occurrences_of(name) > 0;
 
-    let servers = match servers {
-        Ok(servers) => servers,
-        Err(err) => return Err(format!("{}", err)),
+    let max_wingmen = match args.value_of("max_wingmen") {
+        Some(max) if explicit("max_wingmen") => match max.parse() {
+            Ok(max) => Some(max),
+            Err(_) => return Err(ConfigError::InvalidMaxWingmen(max.to_owned())),
+        },
+        _ => None,
     };
 
-    let max_wingmen = args
-        .value_of("max_wingmen")
-        .and_then(|max| max.parse().ok())
-        .unwrap_or(DEFAULT_MAX_WINGMEN);
+    let max_total_wingmen = number_arg(&args, "max_total_wingmen", "max total wingmen")?;
+
+    let max_team_wingmen = match args.value_of("max_team_wingmen") {
+        Some(_) => Some(number_arg(&args, "max_team_wingmen", "max team wingmen")?),
+        None => None,
+    };
 
     let announce = !args.is_present("no_announce");
-    let ctrl_name = args
-        .value_of("ctrl_name")
-        .unwrap_or(DEFAULT_GROUND_CTRL_NAME)
+    let announce_override = if explicit("no_announce") {
+        Some(false)
+    } else {
+        None
+    };
+    let rich_announce = args.is_present("rich_announce");
+    let announce_interval = number_arg::<u64>(&args, "announce_interval", "announce interval")?
+        .checked_mul(60)
+        .map(Duration::from_secs)
+        .ok_or_else(|| {
+            ConfigError::InvalidNumber(
+                "announce interval",
+                args.value_of("announce_interval").unwrap_or("").to_owned(),
+                "too long".to_owned(),
+            )
+        })?;
+    let wing_ttl = match args.value_of("wing_ttl").map(parse_duration) {
+        Some(Ok(ttl)) if ttl.as_secs() > 0 => Some(ttl),
+        Some(Ok(_)) | None => None,
+        Some(Err(err)) => return Err(ConfigError::InvalidWingTtl(err)),
+    };
+    let whisper = args.is_present("whisper");
+    let follow_active = args.is_present("follow_active");
+    let dry_run = args.is_present("dry_run");
+    let admins: Vec<String> = args
+        .values_of("admins")
+        .map(|admins| admins.map(str::to_owned).collect())
+        .unwrap_or_default();
+    let allow: Option<Vec<String>> = args
+        .values_of("allow")
+        .map(|allow| allow.map(str::to_owned).collect());
+    let protected: Vec<String> = args
+        .values_of("protected")
+        .map(|protected| protected.map(str::to_owned).collect())
+        .unwrap_or_default();
+    let chat_interval = Duration::from_millis(number_arg(&args, "chat_interval", "chat interval")?);
+    let error_window = Duration::from_secs(number_arg(&args, "error_window", "error window")?);
+    let chat_tag = args.value_of("chat_tag").unwrap_or_default().to_owned();
+    let ctrl_name = if explicit("ctrl_name") {
+        args.value_of("ctrl_name")
+    } else {
+        None
+    };
+
+    let prefix = args
+        .value_of("prefix")
+        .unwrap_or(commands::command::DEFAULT_PREFIX)
         .to_owned();
+    if !prefix.starts_with("--") || prefix.len() <= 2 {
+        return Err(ConfigError::InvalidPrefix(prefix));
+    }
+
+    let shard_arg = |name: &'static str, label: &'static str| -> Result<u32, ConfigError> {
+        let value = args.value_of(name).unwrap_or("");
+        value
+            .parse()
+            .map_err(|err| ConfigError::InvalidNumber(label, value.to_owned(), format!("{}", err)))
+    };
+    let shard = commands::Shard::new(
+        shard_arg("shard_index", "shard index")?,
+        shard_arg("shard_count", "shard count")?,
+    )
+    .map_err(ConfigError::InvalidShard)?;
+
+    let request_cooldown =
+        Duration::from_secs(number_arg(&args, "request_cooldown", "request cooldown")?);
+
+    let max_reconnects = number_arg(&args, "max_reconnects", "max reconnects")?;
+    let reconnect_backoff =
+        Duration::from_secs(number_arg(&args, "reconnect_backoff", "reconnect backoff")?);
+
+    let reconnect_limiter = match args.value_of("reconnect_rate") {
+        Some(rate) => match rate.parse() {
+            Ok(rate) => net::ReconnectLimiter::new(rate),
+            Err(err) => {
+                return Err(ConfigError::InvalidNumber(
+                    "reconnect rate",
+                    rate.to_owned(),
+                    format!("{}", err),
+                ))
+            }
+        },
+        None => net::ReconnectLimiter::new(net::DEFAULT_RECONNECT_RATE),
+    };
+
+    let http_port = match args.value_of("http_port") {
+        Some(port) => match port.parse() {
+            Ok(port) => Some(port),
+            Err(err) => {
+                return Err(ConfigError::InvalidNumber(
+                    "HTTP port",
+                    port.to_owned(),
+                    format!("{}", err),
+                ))
+            }
+        },
+        None => None,
+    };
+
+    let state_file = args.value_of("state_file").map(PathBuf::from);
+    let event_log = args.value_of("event_log").map(PathBuf::from);
+    let check = args.is_present("check");
+    let console = args.is_present("console");
+
+    let login = net::login_defaults();
+    let login = match args.value_of("protocol") {
+        Some(protocol) => match protocol.parse() {
+            Ok(protocol) => login
+                .protocol(protocol)
+                .map_err(ConfigError::UnsupportedProtocol)?,
+            Err(err) => {
+                return Err(ConfigError::InvalidNumber(
+                    "protocol version",
+                    protocol.to_owned(),
+                    format!("{}", err),
+                ))
+            }
+        },
+        None => login,
+    };
+    let login = match args.value_of("flag") {
+        Some(flag) => login.flag(flag).map_err(ConfigError::UnknownFlag)?,
+        None => login,
+    };
+    let login = match args.value_of("horizon") {
+        Some(horizon) => match horizon.parse() {
+            Ok(horizon) => login.horizon(horizon),
+            Err(err) => {
+                return Err(ConfigError::InvalidNumber(
+                    "horizon",
+                    horizon.to_owned(),
+                    format!("{}", err),
+                ))
+            }
+        },
+        None => login,
+    };
+
+    let map = match args.value_of("map") {
+        Some(path) => types::Map::load(Path::new(path)).map_err(ConfigError::InvalidMap)?,
+        None => types::Map::builtin(),
+    };
 
+    let threat_radius = match args.value_of("threat_radius") {
+        Some(value) => match value.parse::<f32>() {
+            Ok(radius) if radius > 0.0 => radius,
+            Ok(_) => {
+                return Err(ConfigError::InvalidNumber(
+                    "threat radius",
+                    value.to_owned(),
+                    "must be more than 0".to_owned(),
+                ))
+            }
+            Err(err) => {
+                return Err(ConfigError::InvalidNumber(
+                    "threat radius",
+                    value.to_owned(),
+                    format!("{}", err),
+                ))
+            }
+        },
+        None => wing::DEFAULT_THREAT_RADIUS,
+    };
+    let max_chase_distance = match args.value_of("max_chase_distance") {
+        Some(value) => match value.parse::<f32>() {
+            Ok(dist) if dist >= 0.0 => dist,
+            Ok(_) => {
+                return Err(ConfigError::InvalidNumber(
+                    "max chase distance",
+                    value.to_owned(),
+                    "must not be negative".to_owned(),
+                ))
+            }
+            Err(err) => {
+                return Err(ConfigError::InvalidNumber(
+                    "max chase distance",
+                    value.to_owned(),
+                    format!("{}", err),
+                ))
+            }
+        },
+        None => wing::DEFAULT_MAX_CHASE_DISTANCE,
+    };
+    let clearance = match args.value_of("clearance") {
+        Some(value) => match value.parse::<isize>() {
+            Ok(cells) if cells >= 0 => cells,
+            Ok(_) => {
+                return Err(ConfigError::InvalidNumber(
+                    "clearance",
+                    value.to_owned(),
+                    "must not be negative".to_owned(),
+                ))
+            }
+            Err(err) => {
+                return Err(ConfigError::InvalidNumber(
+                    "clearance",
+                    value.to_owned(),
+                    format!("{}", err),
+                ))
+            }
+        },
+        None => DEFAULT_CLEARANCE,
+    };
+    let greet_window = Duration::from_secs(number_arg(&args, "greet_window", "greet window")?);
+    let max_msg_len = number_arg(&args, "max_msg_len", "max message length")?;
+    let spawn_stagger = Duration::from_millis(number_arg(&args, "spawn_stagger", "spawn stagger")?);
+    let target_grace = Duration::from_secs(number_arg(&args, "target_grace", "target grace")?);
+    let stale_after = Duration::from_secs(number_arg(&args, "stale_after", "stale after")?);
+    let login = login.timeouts(
+        Duration::from_secs(number_arg(&args, "connect_timeout", "connect timeout")?),
+        Duration::from_secs(number_arg(&args, "login_timeout", "login timeout")?),
+    );
+    let tick = if args.is_present("tick_fixed") {
+        wing::Tick::fixed(number_arg(&args, "tick_fixed", "fixed tick")?)
+    } else {
+        wing::Tick::ping(
+            number_arg(&args, "tick_min", "tick minimum")?,
+            number_arg(&args, "tick_max", "tick maximum")?,
+        )
+    }
+    .map_err(ConfigError::InvalidTick)?;
+
+    let wing_options = wing::Options {
+        friendly_fire: args.is_present("friendly_fire"),
+        map: Arc::new(map),
+        retreat: !args.is_present("no_retreat"),
+        clearance,
+        login,
+        upgrades: !args.is_present("no_upgrades"),
+        dodge: args.is_present("dodge"),
+        cautious: args.is_present("cautious"),
+        tick,
+        threat_radius,
+        spawn_schedule: wing::SpawnSchedule::new(spawn_stagger),
+        max_chase_distance,
+        target_grace,
+        stale_after,
+        name_prefix: args
+            .value_of("wing_name_prefix")
+            .unwrap_or(DEFAULT_WING_NAME_PREFIX)
+            .to_owned(),
+        leader_follow: args.is_present("leader_follow"),
+        grab_powerups: args.is_present("grab_powerups"),
+    };
+
+    // Inline settings win over flags, which win over the config file
     Ok(servers
         .into_iter()
-        .map(|url| ServerArgs {
-            url,
-            max_wingmen,
-            announce,
-            ctrl_name: ctrl_name.clone(),
+        .map(|(url, server, inline)| {
+            let inline = inline.as_ref();
+            ServerArgs::new(url)
+                .max_wingmen(
+                    inline
+                        .and_then(|inline| inline.max_wingmen)
+                        .or(max_wingmen)
+                        .or_else(|| server.as_ref().and_then(|server| server.max_wingmen))
+                        .unwrap_or(DEFAULT_MAX_WINGMEN),
+                )
+                .announce(
+                    inline
+                        .and_then(|inline| inline.announce)
+                        .or(announce_override)
+                        .or_else(|| server.as_ref().and_then(|server| server.announce))
+                        .unwrap_or(announce),
+                )
+                .rich_announce(rich_announce)
+                .announce_interval(announce_interval)
+                .greet_window(greet_window)
+                .ctrl_name(
+                    inline
+                        .and_then(|inline| inline.ctrl_name.clone())
+                        .or_else(|| ctrl_name.map(str::to_owned))
+                        .or_else(|| server.as_ref().and_then(|server| server.ctrl_name.clone()))
+                        .unwrap_or_else(|| DEFAULT_GROUND_CTRL_NAME.to_owned()),
+                )
+                .wing_ttl(wing_ttl)
+                .prefix(prefix.clone())
+                .shard(shard)
+                .request_cooldown(request_cooldown)
+                .max_total_wingmen(max_total_wingmen)
+                .max_team_wingmen(max_team_wingmen)
+                .wing_options(wing_options.clone())
+                .max_reconnects(max_reconnects)
+                .reconnect_backoff(reconnect_backoff)
+                .reconnect_limiter(reconnect_limiter.clone())
+                .whisper(whisper)
+                .follow_active(follow_active)
+                .dry_run(dry_run)
+                .chat_interval(chat_interval)
+                .error_window(error_window)
+                .chat_tag(chat_tag.clone())
+                .max_msg_len(max_msg_len)
+                .admins(admins.clone())
+                .allow(
+                    allow
+                        .clone()
+                        .or_else(|| server.as_ref().and_then(|server| server.allow.clone()))
+                        .unwrap_or_default(),
+                )
+                .protected(protected.clone())
+                .http_port(http_port)
+                .state_file(state_file.clone())
+                .event_log(event_log.clone())
+                .check(check)
+                .console(console)
         })
         .collect())
 }
 
+/// One line reporting whether ground control could log in to `url`, and
+/// how long it took
+fn check_report(url: &Url, result: &Result<Duration, String>) -> String {
+    match result {
+        Ok(took) => format!(
+            "ok      {} logged in in {} ms",
+            url,
+            took.as_secs() * 1000 + u64::from(took.subsec_millis())
+        ),
+        Err(err) => format!("FAILED  {}: {}", url, err),
+    }
+}
+
+/// Log in to each server in turn as ground control, without spectating or
+/// answering anyone, then disconnect, printing how each went
+///
+/// Returns the number of servers that couldn't be logged in to.
+async fn check_servers(args: Vec<ServerArgs>) -> usize {
+    let mut failed = 0;
+    for arg in args {
+        let start = Instant::now();
+        let result = await!(server::login(
+            arg.url.clone(),
+            arg.ctrl_name.clone(),
+            arg.wing_options.login
+        ))
+        // Dropping the client disconnects it
+        .map(|_| start.elapsed());
+        if result.is_err() {
+            failed += 1;
+        }
+        println!("{}", check_report(&arg.url, &result));
+    }
+    failed
+}
+
+/// The outcome of starting every server
+#[derive(Debug, Default)]
+struct StartSummary {
+    /// Number of servers that started on the first try
+    started: usize,
+    /// Servers that failed to start
+    failed: Vec<Url>,
+}
+
 /// Spawns tasks that communicate with the servers
-async fn start_servers(args: Vec<ServerArgs>) {
+///
+/// A server that fails to start doesn't stop the others. It's retried in the
+/// background, like a lost connection, unless reconnects are disabled. The
+/// servers run until `shutdown` is requested.
+async fn start_servers(args: Vec<ServerArgs>, shutdown: server::Shutdown) -> StartSummary {
+    let http_port = args.first().and_then(|arg| arg.http_port);
+    let console = args.first().map_or(false, |arg| arg.console);
+    let state = args
+        .first()
+        .and_then(|arg| arg.state_file.clone())
+        .map(state::StateFile::load);
+    // Commands are still handled if the log can't be opened
+    let event_log = args
+        .first()
+        .and_then(|arg| arg.event_log.clone())
+        .and_then(|path| {
+            event_log::EventLog::open(&path)
+                .map_err(|err| log::error!("{}", err))
+                .ok()
+        });
+    let metrics = Arc::new(metrics::Metrics::default());
+    let mut statuses = Vec::new();
+    let mut commands = Vec::new();
+    let mut summary = StartSummary::default();
     for arg in args {
-        let mut client = match await!(Client::new_insecure(arg.url.clone())) {
-            Ok(client) => client,
-            Err(err) => {
-                log::error!("client connection error: {}", err);
-                return;
-            }
+        let status = status::ServerStatus::new(arg.url.to_string());
+        statuses.push(status.clone());
+        let receiver = if console {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            commands.push(sender);
+            Some(receiver)
+        } else {
+            None
         };
-
-        if let Err(err) = await!(client.send(protocol::client::Login {
-            flag: "UN".to_owned(),
-            name: arg.ctrl_name,
-            session: "none".to_owned(),
-            horizon_x: 3000,
-            horizon_y: 3000,
-            protocol: 5,
-        })) {
-            log::error!("client login error {}", err);
-            return;
-        } else if let Err(err) = await!(client.wait_for_login()) {
-            log::error!("wait for login error {}", err);
-            return;
-        }
-
-        // Force ground control to spectate
-        if let Err(err) = await!(client.send(protocol::client::Command {
-            com: "spectate".to_owned(),
-            data: "-3".to_owned(),
-        })) {
-            log::error!("force spectate error {}", err);
-            return;
+        match await!(server::Server::connect(
+            arg,
+            metrics.clone(),
+            status.clone(),
+            state.clone(),
+            event_log.clone()
+        )) {
+            Ok(server) => {
+                log::info!("Starting ground control on server {}", server.url());
+                tokio::spawn_async(run_server(server, shutdown.clone(), receiver));
+                summary.started += 1;
+            }
+            Err((arg, err)) => {
+                log::error!("error starting ground control on {}: {}", arg.url, err);
+                summary.failed.push(arg.url.clone());
+                if arg.max_reconnects > 0 {
+                    tokio::spawn_async(start_later(
+                        arg,
+                        metrics.clone(),
+                        status,
+                        state.clone(),
+                        event_log.clone(),
+                        shutdown.clone(),
+                        receiver,
+                    ));
+                }
+            }
         }
+    }
+
+    if console {
+        // The command line hasn't changed, but the config file may have
+        console::spawn(commands, || {
+            parse_args(std::env::args_os())
+                .map(|args| args.into_iter().map(|arg| arg.allow).collect())
+                .map_err(|err| err.to_string())
+        });
+    }
+    if let Some(port) = http_port {
+        status::serve(port, statuses, metrics, shutdown);
+    }
+    summary
+}
 
+/// Keep trying to start ground control on a server that failed to start
+async fn start_later(
+    arg: ServerArgs,
+    metrics: Arc<metrics::Metrics>,
+    status: status::Handle,
+    state: Option<state::Handle>,
+    event_log: Option<event_log::Handle>,
+    shutdown: server::Shutdown,
+    console: Option<console::Receiver>,
+) {
+    let client = await!(server::connect_with_backoff(
+        arg.url.clone(),
+        arg.ctrl_name.clone(),
+        arg.wing_options.login,
+        arg.max_reconnects,
+        arg.reconnect_backoff,
+        arg.reconnect_limiter.clone(),
+        shutdown.clone()
+    ));
+    if let Some(client) = client {
         log::info!("Starting ground control on server {}", arg.url);
-        let server = server::Server::new(arg.url, client, arg.max_wingmen, arg.announce);
-        tokio::spawn_async(server.run());
+        let server = server::Server::new(arg, client, metrics, status, state, event_log);
+        await!(run_server(server, shutdown, console));
+    }
+}
+
+/// Run a server until it stops, and log why it stopped
+async fn run_server(
+    server: server::Server,
+    shutdown: server::Shutdown,
+    console: Option<console::Receiver>,
+) {
+    let url = server.url().clone();
+    match await!(server.run(shutdown, console)) {
+        server::DisconnectReason::Shutdown => log::info!("Ground control on {} shut down", url),
+        server::DisconnectReason::Banned => {
+            log::error!("Ground control was banned from {}; not reconnecting", url)
+        }
+        reason => log::error!("Ground control on {} stopped: {:?}", url, reason),
     }
 }
 
 fn main() {
     env_logger::init();
 
-    let args = match parse_args() {
+    let args = match parse_args(std::env::args_os()) {
         Err(err) => {
             log::error!("{}", err);
             process::exit(1);
@@ -162,5 +1467,442 @@ fn main() {
         Ok(args) => args,
     };
 
-    tokio::run_async(start_servers(args));
+    if args.first().map_or(false, |arg| arg.check) {
+        let (done, failed) = std::sync::mpsc::channel();
+        tokio::run_async(async move {
+            let _ = done.send(await!(check_servers(args)));
+        });
+        // The check can only fail to report if it panicked
+        if failed.recv().unwrap_or(1) > 0 {
+            process::exit(1);
+        }
+        return;
+    }
+
+    let shutdown = server::Shutdown::default();
+    let handler_shutdown = shutdown.clone();
+    if let Err(err) = ctrlc::set_handler(move || {
+        log::info!("Received Ctrl-C; shutting down");
+        handler_shutdown.request();
+    }) {
+        log::error!("error setting Ctrl-C handler: {}", err);
+        process::exit(1);
+    }
+
+    // Returns once every server and wingman has shut down
+    tokio::run_async(async move {
+        let summary = await!(start_servers(args, shutdown));
+        if summary.failed.is_empty() {
+            log::info!("Started ground control on {} servers", summary.started);
+        } else {
+            log::warn!(
+                "Started ground control on {} servers; {} failed to start: {:?}",
+                summary.started,
+                summary.failed.len(),
+                summary.failed.iter().map(Url::as_str).collect::<Vec<_>>()
+            );
+        }
+    });
+    log::info!("Ground control shut down");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Vec<ServerArgs>, ConfigError> {
+        parse_args(std::iter::once("ground-control").chain(args.iter().cloned()))
+    }
+
+    fn parse_err(args: &[&str]) -> ConfigError {
+        match parse(args) {
+            Ok(_) => panic!("expected {:?} to be rejected", args),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn parses_servers_with_defaults() {
+        let servers = parse(&["ws://localhost:3501", "wss://localhost:3502"]).expect("valid args");
+        assert_eq!(servers.len(), 2);
+        assert_eq!(servers[1].url.as_str(), "wss://localhost:3502/");
+        assert_eq!(servers[0].max_wingmen, DEFAULT_MAX_WINGMEN);
+        assert_eq!(servers[0].prefix, commands::command::DEFAULT_PREFIX);
+        assert_eq!(servers[0].ctrl_name, DEFAULT_GROUND_CTRL_NAME);
+    }
+
+    #[test]
+    fn rejects_bad_urls() {
+        match parse_err(&["not a url"]) {
+            ConfigError::InvalidUrl(url, _) => assert_eq!(url, "not a url"),
+            err => panic!("unexpected error {:?}", err),
+        }
+        match parse_err(&["http://localhost:3501"]) {
+            ConfigError::UnsupportedScheme(_) => (),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn console_is_opt_in() {
+        assert!(parse(&["--console", "ws://localhost:3501"]).unwrap()[0].console);
+        assert!(!parse(&["ws://localhost:3501"]).unwrap()[0].console);
+    }
+
+    #[test]
+    fn rejects_bad_max_wingmen() {
+        assert_eq!(
+            parse_err(&["--max-wingmen", "300", "ws://localhost:3501"]),
+            ConfigError::InvalidMaxWingmen("300".to_owned())
+        );
+        let servers = parse(&["--max-wingmen", "3", "ws://localhost:3501"]).expect("valid args");
+        assert_eq!(servers[0].max_wingmen, 3);
+    }
+
+    #[test]
+    fn check_reports_each_server() {
+        let servers = parse(&["--check", "ws://localhost:3501"]).expect("valid args");
+        assert!(servers[0].check);
+        assert!(!parse(&["ws://localhost:3501"]).unwrap()[0].check);
+
+        let url = Url::parse("ws://localhost:3501").unwrap();
+        assert_eq!(
+            check_report(&url, &Ok(Duration::from_millis(1234))),
+            "ok      ws://localhost:3501/ logged in in 1234 ms"
+        );
+        assert_eq!(
+            check_report(&url, &Err("client connection error: refused".to_owned())),
+            "FAILED  ws://localhost:3501/: client connection error: refused"
+        );
+    }
+
+    #[test]
+    fn inline_settings_override_flags_per_server() {
+        let servers = parse(&[
+            "--max-wingmen",
+            "4",
+            "ws://localhost:3501#max=2,name=CTRL,ws://localhost:3502",
+            "ws://localhost:3503#announce=false",
+        ])
+        .expect("valid args");
+        assert_eq!(servers.len(), 3);
+        assert_eq!(servers[0].url.as_str(), "ws://localhost:3501/");
+        assert_eq!(servers[0].max_wingmen, 2);
+        assert_eq!(servers[0].ctrl_name, "CTRL");
+        assert_eq!(servers[1].max_wingmen, 4);
+        assert_eq!(servers[1].ctrl_name, DEFAULT_GROUND_CTRL_NAME);
+        assert!(servers[1].announce);
+        assert!(!servers[2].announce);
+
+        match parse_err(&["ws://localhost:3501#max=many"]) {
+            ConfigError::InvalidServerOption(server, _) => {
+                assert_eq!(server, "ws://localhost:3501")
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_prefix() {
+        let err = parse_err(&["--prefix", "gc", "ws://localhost:3501"]);
+        assert_eq!(err, ConfigError::InvalidPrefix("gc".to_owned()));
+        assert_eq!(
+            err.to_string(),
+            "invalid prefix 'gc': must start with '--' followed by a name"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_shards() {
+        match parse_err(&[
+            "--shard-index",
+            "2",
+            "--shard-count",
+            "2",
+            "ws://localhost:3501",
+        ]) {
+            ConfigError::InvalidShard(_) => (),
+            err => panic!("unexpected error {:?}", err),
+        }
+        match parse_err(&["--shard-index", "one", "ws://localhost:3501"]) {
+            ConfigError::InvalidNumber(name, value, _) => {
+                assert_eq!(name, "shard index");
+                assert_eq!(value, "one");
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn flag_is_checked_and_shared_with_wingmen() {
+        let parsed = parse(&["--flag", "gb", "ws://localhost:3501"])
+            .expect("valid args")
+            .remove(0);
+        let login = parsed.wing_options.login.login("ctrl".to_owned());
+        assert_eq!(login.flag, "GB");
+
+        match parse_err(&["--flag", "XX", "ws://localhost:3501"]) {
+            ConfigError::UnknownFlag(msg) => assert!(msg.contains("valid codes are AD, AE")),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn timeouts_are_shared_with_wingmen() {
+        let parsed = parse(&[
+            "--connect-timeout",
+            "3",
+            "--login-timeout",
+            "0",
+            "ws://localhost:3501",
+        ])
+        .expect("valid args")
+        .remove(0);
+        assert_eq!(
+            parsed.wing_options.login,
+            net::login_defaults().timeouts(Duration::from_secs(3), Duration::from_secs(0))
+        );
+
+        match parse_err(&["--login-timeout", "soon", "ws://localhost:3501"]) {
+            ConfigError::InvalidNumber("login timeout", value, _) => assert_eq!(value, "soon"),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn parses_greet_window() {
+        let servers = parse(&["--greet-window", "60", "ws://localhost:3501"]).expect("valid args");
+        assert_eq!(servers[0].greet_window, Duration::from_secs(60));
+
+        match parse_err(&["--greet-window", "5m", "ws://localhost:3501"]) {
+            ConfigError::InvalidNumber("greet window", value, _) => assert_eq!(value, "5m"),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn parses_wingmen_caps() {
+        let servers = parse(&["ws://localhost:3501"]).expect("valid args");
+        assert_eq!(servers[0].max_total_wingmen, DEFAULT_MAX_TOTAL_WINGMEN);
+        assert_eq!(servers[0].max_team_wingmen, None);
+
+        let servers = parse(&[
+            "--max-total-wingmen",
+            "20",
+            "--max-team-wingmen",
+            "12",
+            "ws://localhost:3501",
+        ])
+        .expect("valid args");
+        assert_eq!(servers[0].max_total_wingmen, 20);
+        assert_eq!(servers[0].max_team_wingmen, Some(12));
+
+        match parse_err(&["--max-total-wingmen", "lots", "ws://localhost:3501"]) {
+            ConfigError::InvalidNumber("max total wingmen", value, _) => assert_eq!(value, "lots"),
+            err => panic!("unexpected error {:?}", err),
+        }
+        match parse_err(&["--max-team-wingmen", "1O", "ws://localhost:3501"]) {
+            ConfigError::InvalidNumber("max team wingmen", value, _) => assert_eq!(value, "1O"),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_announce_interval() {
+        match parse_err(&["--announce-interval", "hourly", "ws://localhost:3501"]) {
+            ConfigError::InvalidNumber("announce interval", value, _) => {
+                assert_eq!(value, "hourly")
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+        let forever = u64::max_value().to_string();
+        match parse_err(&["--announce-interval", &forever, "ws://localhost:3501"]) {
+            ConfigError::InvalidNumber("announce interval", value, reason) => {
+                assert_eq!(value, forever);
+                assert_eq!(reason, "too long");
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_error_window() {
+        match parse_err(&["--error-window", "a minute", "ws://localhost:3501"]) {
+            ConfigError::InvalidNumber("error window", value, _) => assert_eq!(value, "a minute"),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_chat_interval() {
+        match parse_err(&["--chat-interval", "1.5", "ws://localhost:3501"]) {
+            ConfigError::InvalidNumber("chat interval", value, _) => assert_eq!(value, "1.5"),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_request_cooldown() {
+        match parse_err(&["--request-cooldown", "10s", "ws://localhost:3501"]) {
+            ConfigError::InvalidNumber("request cooldown", value, _) => assert_eq!(value, "10s"),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_reconnect_settings() {
+        match parse_err(&["--max-reconnects", "five", "ws://localhost:3501"]) {
+            ConfigError::InvalidNumber("max reconnects", value, _) => assert_eq!(value, "five"),
+            err => panic!("unexpected error {:?}", err),
+        }
+        match parse_err(&["--reconnect-backoff", "2s", "ws://localhost:3501"]) {
+            ConfigError::InvalidNumber("reconnect backoff", value, _) => assert_eq!(value, "2s"),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn parses_max_msg_len() {
+        let servers = parse(&["--max-msg-len", "0", "ws://localhost:3501"]).expect("valid args");
+        assert_eq!(servers[0].max_msg_len, 0);
+
+        match parse_err(&["--max-msg-len", "long", "ws://localhost:3501"]) {
+            ConfigError::InvalidNumber("max message length", value, _) => {
+                assert_eq!(value, "long")
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(parse_duration("5"), Ok(Duration::from_secs(5 * 60)));
+        assert!(parse_duration("soon").is_err());
+        // Too long to count in seconds
+        assert!(parse_duration("18446744073709551615h").is_err());
+    }
+
+    #[test]
+    fn builder_defaults_match_command_line_defaults() {
+        let url = Url::parse("ws://localhost:3501").unwrap();
+        let parsed = parse(&["ws://localhost:3501"])
+            .expect("valid args")
+            .remove(0);
+        let built = ServerArgs::new(url);
+        assert_eq!(built.url, parsed.url);
+        assert_eq!(built.max_wingmen, parsed.max_wingmen);
+        assert_eq!(built.announce, parsed.announce);
+        assert_eq!(built.ctrl_name, parsed.ctrl_name);
+        assert_eq!(built.prefix, parsed.prefix);
+        assert_eq!(built.request_cooldown, parsed.request_cooldown);
+        assert_eq!(built.max_total_wingmen, parsed.max_total_wingmen);
+        assert_eq!(built.error_window, parsed.error_window);
+        assert_eq!(built.chat_tag, parsed.chat_tag);
+        assert_eq!(built.max_msg_len, parsed.max_msg_len);
+        assert_eq!(built.greet_window, parsed.greet_window);
+        assert_eq!(
+            built.wing_options.name_prefix,
+            parsed.wing_options.name_prefix
+        );
+        assert_eq!(
+            built.wing_options.target_grace,
+            parsed.wing_options.target_grace
+        );
+        assert_eq!(
+            built.wing_options.stale_after,
+            parsed.wing_options.stale_after
+        );
+        assert_eq!(built.wing_options.login, parsed.wing_options.login);
+
+        let built = ServerArgs::new(built.url).max_wingmen(3).whisper(true);
+        assert_eq!(built.max_wingmen, 3);
+        assert!(built.whisper);
+    }
+
+    #[test]
+    fn parses_tick_options() {
+        let servers = parse(&["ws://localhost:3501"]).expect("valid args");
+        assert_eq!(servers[0].wing_options.tick, wing::Tick::default());
+
+        let servers = parse(&[
+            "--tick-min",
+            "50",
+            "--tick-max",
+            "200",
+            "ws://localhost:3501",
+        ])
+        .expect("valid args");
+        let tick = servers[0].wing_options.tick;
+        assert_eq!(tick.interval(10), Duration::from_millis(50));
+        assert_eq!(tick.interval(60), Duration::from_millis(120));
+        assert_eq!(tick.interval(500), Duration::from_millis(200));
+
+        let servers = parse(&["--tick-fixed", "75", "ws://localhost:3501"]).expect("valid args");
+        assert_eq!(
+            servers[0].wing_options.tick.interval(500),
+            Duration::from_millis(75)
+        );
+
+        match parse_err(&[
+            "--tick-min",
+            "300",
+            "--tick-max",
+            "200",
+            "ws://localhost:3501",
+        ]) {
+            ConfigError::InvalidTick(_) => (),
+            err => panic!("unexpected error {:?}", err),
+        }
+
+        match parse_err(&["--tick-fixed", "0", "ws://localhost:3501"]) {
+            ConfigError::InvalidTick(_) => (),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn parses_max_chase_distance() {
+        let servers = parse(&["ws://localhost:3501"]).expect("valid args");
+        assert_eq!(
+            servers[0].wing_options.max_chase_distance,
+            wing::DEFAULT_MAX_CHASE_DISTANCE
+        );
+
+        let servers =
+            parse(&["--max-chase-distance", "0", "ws://localhost:3501"]).expect("valid args");
+        assert_eq!(servers[0].wing_options.max_chase_distance, 0.0);
+
+        match parse_err(&["--max-chase-distance=-100", "ws://localhost:3501"]) {
+            ConfigError::InvalidNumber(name, value, _) => {
+                assert_eq!(name, "max chase distance");
+                assert_eq!(value, "-100");
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn parses_clearance() {
+        let servers = parse(&["ws://localhost:3501"]).expect("valid args");
+        assert_eq!(servers[0].wing_options.clearance, DEFAULT_CLEARANCE);
+
+        let servers = parse(&["--clearance", "0", "ws://localhost:3501"]).expect("valid args");
+        assert_eq!(servers[0].wing_options.clearance, 0);
+
+        match parse_err(&["--clearance=-1", "ws://localhost:3501"]) {
+            ConfigError::InvalidNumber(name, value, reason) => {
+                assert_eq!(name, "clearance");
+                assert_eq!(value, "-1");
+                assert_eq!(reason, "must not be negative");
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+        match parse_err(&["--clearance", "wide", "ws://localhost:3501"]) {
+            ConfigError::InvalidNumber("clearance", value, _) => assert_eq!(value, "wide"),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
 }
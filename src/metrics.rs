@@ -0,0 +1,115 @@
+//! Counters shared by every server
+//!
+//! All counters are atomics, so servers update them without
+//! waiting on each other or on the status endpoint. The
+//! endpoint renders them in the Prometheus text format.
+
+use crate::commands::BadCommand;
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Reasons a command can be rejected, as reported by `BadCommand::kind`
+const REJECTION_KINDS: [&str; 13] = [
+    "unknown",
+    "no_wings",
+    "too_many_wings",
+    "already_winged",
+    "no_such_player",
+    "cooling_down",
+    "unknown_ship",
+    "server_busy",
+    "not_authorized",
+    "bad_setting",
+    "not_attacking",
+    "bad_patrol_point",
+    "protected_target",
+];
+
+/// Counters describing what ground control has done since starting
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Requests for wings, whether granted or not
+    wing_requests: AtomicUsize,
+    /// Rejected commands, indexed like `REJECTION_KINDS`
+    rejected: [AtomicUsize; 13],
+    /// Wingmen spawned
+    wingmen_spawned: AtomicUsize,
+    /// Players shot down by wingmen
+    wingman_kills: AtomicUsize,
+    /// Wingmen currently assigned to players
+    active_wingmen: AtomicUsize,
+}
+
+impl Metrics {
+    /// Count a request for wings
+    pub fn wing_requested(&self) {
+        self.wing_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a rejected command
+    pub fn rejected(&self, err: &BadCommand) {
+        match REJECTION_KINDS.iter().position(|&kind| kind == err.kind()) {
+            Some(index) => {
+                self.rejected[index].fetch_add(1, Ordering::Relaxed);
+            }
+            None => log::warn!("no metric for rejected command {:?}", err),
+        }
+    }
+
+    /// Count spawned wingmen
+    pub fn wingmen_spawned(&self, wings: usize) {
+        self.wingmen_spawned.fetch_add(wings, Ordering::Relaxed);
+    }
+
+    /// Count a player shot down by a wingman
+    pub fn wingman_kill(&self) {
+        self.wingman_kills.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Adjust the number of active wingmen when a server's count changes
+    /// from `before` to `after`
+    pub fn active_wingmen_changed(&self, before: usize, after: usize) {
+        if after > before {
+            self.active_wingmen
+                .fetch_add(after - before, Ordering::Relaxed);
+        } else {
+            self.active_wingmen
+                .fetch_sub(before - after, Ordering::Relaxed);
+        }
+    }
+
+    /// Render the counters in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let counter = |out: &mut String, name: &str, value: &AtomicUsize| {
+            let _ = writeln!(out, "# TYPE ground_control_{} counter", name);
+            let _ = writeln!(
+                out,
+                "ground_control_{} {}",
+                name,
+                value.load(Ordering::Relaxed)
+            );
+        };
+
+        counter(&mut out, "wing_requests_total", &self.wing_requests);
+        let _ = writeln!(out, "# TYPE ground_control_rejected_commands_total counter");
+        for (kind, value) in REJECTION_KINDS.iter().zip(self.rejected.iter()) {
+            let _ = writeln!(
+                out,
+                "ground_control_rejected_commands_total{{reason=\"{}\"}} {}",
+                kind,
+                value.load(Ordering::Relaxed)
+            );
+        }
+        counter(&mut out, "wingmen_spawned_total", &self.wingmen_spawned);
+        counter(&mut out, "wingman_kills_total", &self.wingman_kills);
+        let _ = writeln!(out, "# TYPE ground_control_active_wingmen gauge");
+        let _ = writeln!(
+            out,
+            "ground_control_active_wingmen {}",
+            self.active_wingmen.load(Ordering::Relaxed)
+        );
+        out
+    }
+}
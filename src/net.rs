@@ -0,0 +1,531 @@
+//! Connections to AIRMASH servers
+//!
+//! Ground control and its wingmen connect the same way, so
+//! wingmen always use the same scheme and login settings as
+//! their server.
+//!
+//! `airmash_client` answers the server's pings itself, and keeps
+//! `world.ping` up to date from them. A connection can still die
+//! without being closed, so a `Watchdog` notices when a server has
+//! gone quiet.
+//!
+//! A `ReconnectLimiter` paces reconnects across every server, so
+//! ground controllers don't all rush back to a server the moment it
+//! restarts.
+
+use airmash_client::{Client, ClientBase};
+use airmash_protocol as protocol;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time;
+use tokio::prelude::FutureExt;
+use url::Url;
+
+/// Protocol versions spoken by the `airmash_protocol` crate
+pub const SUPPORTED_PROTOCOLS: &[u8] = &[5];
+
+/// Protocol version sent when logging in
+pub const DEFAULT_PROTOCOL: u8 = 5;
+
+/// Distance around a player, in each direction, that the server
+/// reports to us
+pub const DEFAULT_HORIZON: u16 = 3000;
+
+/// Seconds without a packet from the server before its connection is
+/// taken to be dead, unless configured. The server pings every few
+/// seconds, so a healthy connection is never this quiet.
+pub const DEFAULT_STALE_AFTER: u64 = 30;
+
+/// Seconds to wait for a connection to open, unless configured
+pub const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+
+/// Seconds to wait for the server to accept our login once connected,
+/// unless configured
+pub const DEFAULT_LOGIN_TIMEOUT: u64 = 10;
+
+/// Reconnects allowed a minute across every server, unless configured
+pub const DEFAULT_RECONNECT_RATE: u32 = 20;
+
+/// Reconnects that may go ahead back to back before the rate applies
+const RECONNECT_BURST: u32 = 3;
+
+/// Flag shown next to our names unless configured
+pub const DEFAULT_FLAG: &str = "UN";
+
+/// Flag codes the server knows, as listed by `airmash_protocol`. Some
+/// are only shown for players of a high enough level.
+pub const FLAG_CODES: &[&str] = &[
+    "AD",
+    "AE",
+    "AL",
+    "AM",
+    "AQ",
+    "AR",
+    "AT",
+    "AU",
+    "AZ",
+    "BA",
+    "BD",
+    "BE",
+    "BG",
+    "BH",
+    "BO",
+    "BR",
+    "BT",
+    "BY",
+    "CA",
+    "CH",
+    "CL",
+    "CN",
+    "CO",
+    "COMMUNIST",
+    "CONFEDERATE",
+    "CR",
+    "CU",
+    "CY",
+    "CZ",
+    "DE",
+    "DK",
+    "DO",
+    "DZ",
+    "EC",
+    "EE",
+    "EG",
+    "ES",
+    "EU",
+    "FI",
+    "FR",
+    "GB",
+    "GE",
+    "GR",
+    "GT",
+    "HK",
+    "HN",
+    "HR",
+    "HU",
+    "ID",
+    "IE",
+    "IL",
+    "IM",
+    "IMPERIAL",
+    "IN",
+    "IQ",
+    "IR",
+    "IS",
+    "IT",
+    "JM",
+    "JO",
+    "JOLLY",
+    "JP",
+    "KP",
+    "KR",
+    "KW",
+    "KZ",
+    "LB",
+    "LI",
+    "LK",
+    "LT",
+    "LU",
+    "LV",
+    "LY",
+    "MA",
+    "MC",
+    "MD",
+    "ME",
+    "MK",
+    "MO",
+    "MT",
+    "MX",
+    "MY",
+    "NG",
+    "NL",
+    "NO",
+    "NP",
+    "NZ",
+    "OM",
+    "PA",
+    "PE",
+    "PH",
+    "PK",
+    "PL",
+    "PR",
+    "PT",
+    "PY",
+    "QA",
+    "RAINBOW",
+    "RO",
+    "RS",
+    "RU",
+    "SA",
+    "SE",
+    "SG",
+    "SI",
+    "SK",
+    "SM",
+    "SO",
+    "SV",
+    "SY",
+    "TH",
+    "TM",
+    "TN",
+    "TR",
+    "TT",
+    "TW",
+    "TZ",
+    "UA",
+    "UN",
+    "US",
+    "UY",
+    "UZ",
+    "VE",
+    "VN",
+    "ZA",
+];
+
+/// Horizons are clamped to this range; the server ignores anything
+/// outside a normal screen size anyway
+const MIN_HORIZON: u16 = 500;
+const MAX_HORIZON: u16 = 5000;
+
+/// Settings sent to the server when logging in, and how long to wait
+/// for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoginOptions {
+    /// Protocol version
+    protocol: u8,
+    /// Code of the flag shown next to our name, one of `FLAG_CODES`
+    flag: &'static str,
+    /// Horizontal distance the server reports around us
+    horizon_x: u16,
+    /// Vertical distance the server reports around us
+    horizon_y: u16,
+    /// How long to wait for the connection to open, or forever if 0
+    connect_timeout: time::Duration,
+    /// How long to wait for the server to accept our login, or forever if 0
+    login_timeout: time::Duration,
+}
+
+/// The login settings used unless overridden
+pub fn login_defaults() -> LoginOptions {
+    LoginOptions {
+        protocol: DEFAULT_PROTOCOL,
+        flag: DEFAULT_FLAG,
+        horizon_x: DEFAULT_HORIZON,
+        horizon_y: DEFAULT_HORIZON,
+        connect_timeout: time::Duration::from_secs(DEFAULT_CONNECT_TIMEOUT),
+        login_timeout: time::Duration::from_secs(DEFAULT_LOGIN_TIMEOUT),
+    }
+}
+
+impl Default for LoginOptions {
+    fn default() -> Self {
+        login_defaults()
+    }
+}
+
+impl LoginOptions {
+    /// Use protocol version `protocol`, if it's supported
+    pub fn protocol(self, protocol: u8) -> Result<Self, String> {
+        if SUPPORTED_PROTOCOLS.contains(&protocol) {
+            Ok(LoginOptions { protocol, ..self })
+        } else {
+            Err(format!(
+                "unsupported protocol version {}: supported versions are {:?}",
+                protocol, SUPPORTED_PROTOCOLS
+            ))
+        }
+    }
+
+    /// Fly the flag with code `flag`, like `GB`, if the server knows it
+    pub fn flag(self, flag: &str) -> Result<Self, String> {
+        let upper = flag.to_uppercase();
+        match FLAG_CODES.iter().find(|&&code| code == upper) {
+            Some(&flag) => Ok(LoginOptions { flag, ..self }),
+            None => Err(format!(
+                "unknown flag code '{}': valid codes are {}",
+                flag,
+                FLAG_CODES.join(", ")
+            )),
+        }
+    }
+
+    /// Use the same `horizon` in both directions, clamped to a size the
+    /// server accepts
+    pub fn horizon(self, horizon: u16) -> Self {
+        let clamped = horizon.max(MIN_HORIZON).min(MAX_HORIZON);
+        if clamped != horizon {
+            log::warn!("horizon {} out of range; using {}", horizon, clamped);
+        }
+        LoginOptions {
+            horizon_x: clamped,
+            horizon_y: clamped,
+            ..self
+        }
+    }
+
+    /// Give up on opening a connection after `connect`, and on the server
+    /// accepting our login after `login`; 0 waits forever
+    pub fn timeouts(self, connect: time::Duration, login: time::Duration) -> Self {
+        LoginOptions {
+            connect_timeout: connect,
+            login_timeout: login,
+            ..self
+        }
+    }
+
+    /// The login packet for a player called `name`
+    pub fn login(&self, name: String) -> protocol::client::Login {
+        protocol::client::Login {
+            flag: self.flag.to_owned(),
+            name,
+            session: "none".to_owned(),
+            horizon_x: self.horizon_x,
+            horizon_y: self.horizon_y,
+            protocol: self.protocol,
+        }
+    }
+}
+
+/// Check that we know how to connect to the URL's scheme
+pub fn check_scheme(url: &Url) -> Result<(), String> {
+    match url.scheme() {
+        "ws" | "wss" => Ok(()),
+        scheme => Err(format!(
+            "unsupported scheme '{}' in {}: use ws or wss",
+            scheme, url
+        )),
+    }
+}
+
+/// Open a client connection to `url`
+///
+/// `wss` URLs are connected over TLS. Only `ws` URLs are unencrypted.
+pub async fn open(url: Url) -> Result<ClientBase, String> {
+    check_scheme(&url)?;
+    let client = if url.scheme() == "wss" {
+        await!(Client::new(url))
+    } else {
+        await!(Client::new_insecure(url))
+    };
+    client.map_err(|err| err.to_string())
+}
+
+/// Open a client connection to `url` like `open`, giving up once the
+/// connect timeout in `login` has passed
+pub async fn open_within(url: Url, login: LoginOptions) -> Result<ClientBase, String> {
+    let limit = login.connect_timeout;
+    if limit == time::Duration::from_secs(0) {
+        return await!(open(url));
+    }
+    match await!(timeout(open(url), limit)) {
+        Some(result) => result,
+        None => Err(format!("timed out after {}s", limit.as_secs())),
+    }
+}
+
+/// Wait for the server to accept our login, giving up once the login
+/// timeout in `login` has passed
+pub async fn wait_for_login(client: &mut ClientBase, login: LoginOptions) -> Result<(), String> {
+    let limit = login.login_timeout;
+    let result = if limit == time::Duration::from_secs(0) {
+        Some(await!(client.wait_for_login()))
+    } else {
+        await!(timeout(client.wait_for_login(), limit))
+    };
+    match result {
+        Some(result) => result.map_err(|err| err.to_string()),
+        None => Err(format!("timed out after {}s", limit.as_secs())),
+    }
+}
+
+/// Wait for `duration`, even without a connected client
+pub async fn sleep(duration: time::Duration) {
+    // tokio's await! also accepts the timer's futures 0.1 `Delay`
+    let delay = tokio::timer::Delay::new(time::Instant::now() + duration);
+    warn_on_err!(tokio::await!(delay));
+}
+
+/// Wait for `future`, or give up with `None` once `duration` has passed
+///
+/// A timer failure counts as running out of time.
+pub async fn timeout<F, T, E>(future: F, duration: time::Duration) -> Option<Result<T, E>>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    // tokio's timeouts are for futures 0.1, so convert there and back
+    let future = tokio::async_await::compat::backward::Compat::new(future).timeout(duration);
+    match tokio::await!(future) {
+        Ok(value) => Some(Ok(value)),
+        Err(err) => err.into_inner().map(Err),
+    }
+}
+
+/// Paces reconnects across every server, so a network blip doesn't send
+/// every ground controller back at once
+///
+/// Clones share the same limit. It works like a bucket holding
+/// `RECONNECT_BURST` reconnects that refills at the configured rate:
+/// each reconnect takes one, waiting for a refill if the bucket is empty.
+#[derive(Debug, Clone, Default)]
+pub struct ReconnectLimiter {
+    /// When the bucket will be full again, if it's been drawn from
+    full: Arc<Mutex<Option<time::Instant>>>,
+    /// Time to refill one reconnect, or 0 for no limit
+    interval: time::Duration,
+}
+
+impl ReconnectLimiter {
+    /// Allow `per_minute` reconnects a minute, or any number if 0
+    pub fn new(per_minute: u32) -> Self {
+        let interval = if per_minute == 0 {
+            time::Duration::from_secs(0)
+        } else {
+            time::Duration::from_secs(60) / per_minute
+        };
+        ReconnectLimiter {
+            full: Arc::new(Mutex::new(None)),
+            interval,
+        }
+    }
+
+    /// Take a reconnect from the bucket, and return how long from `now`
+    /// until it may go ahead
+    pub fn reserve(&self, now: time::Instant) -> time::Duration {
+        let none = time::Duration::from_secs(0);
+        if self.interval == none {
+            return none;
+        }
+        let mut full = match self.full.lock() {
+            Ok(full) => full,
+            Err(_) => return none,
+        };
+        let start = full.map_or(now, |full| full.max(now));
+        *full = Some(start + self.interval);
+        (start - now)
+            .checked_sub(self.interval * (RECONNECT_BURST - 1))
+            .unwrap_or(none)
+    }
+}
+
+/// Notices when a server has gone quiet for too long
+///
+/// A quiet server has probably dropped the connection without closing it,
+/// so waiting for its next packet would block forever.
+#[derive(Debug, Clone, Copy)]
+pub struct Watchdog {
+    /// When we last heard from the server
+    heard: time::Instant,
+    /// How long the server may stay quiet, or forever if 0
+    limit: time::Duration,
+}
+
+impl Watchdog {
+    /// Start watching at `now` for a server quiet for longer than `limit`,
+    /// or never if `limit` is 0
+    pub fn new(limit: time::Duration, now: time::Instant) -> Self {
+        Watchdog { heard: now, limit }
+    }
+
+    /// Note that the server sent us something at `now`
+    pub fn heard(&mut self, now: time::Instant) {
+        self.heard = now;
+    }
+
+    /// How much longer to wait for the server as of `now`, or `None` to wait
+    /// forever
+    pub fn remaining(&self, now: time::Instant) -> Option<time::Duration> {
+        if self.limit == time::Duration::from_secs(0) {
+            return None;
+        }
+        let deadline = self.heard + self.limit;
+        Some(if deadline > now {
+            deadline - now
+        } else {
+            time::Duration::from_secs(0)
+        })
+    }
+
+    /// True if the server has been quiet too long as of `now`
+    pub fn is_stale(&self, now: time::Instant) -> bool {
+        self.remaining(now) == Some(time::Duration::from_secs(0))
+    }
+
+    /// How long the server may stay quiet
+    pub fn limit(&self) -> time::Duration {
+        self.limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchdog_goes_off_after_silence() {
+        let start = time::Instant::now();
+        let secs = |secs| start + time::Duration::from_secs(secs);
+        let mut watchdog = Watchdog::new(time::Duration::from_secs(30), start);
+
+        assert_eq!(
+            watchdog.remaining(secs(10)),
+            Some(time::Duration::from_secs(20))
+        );
+        assert!(!watchdog.is_stale(secs(29)));
+        assert!(watchdog.is_stale(secs(30)));
+
+        // Hearing from the server starts the wait over
+        watchdog.heard(secs(31));
+        assert!(!watchdog.is_stale(secs(40)));
+        assert_eq!(
+            watchdog.remaining(secs(70)),
+            Some(time::Duration::from_secs(0))
+        );
+    }
+
+    #[test]
+    fn flag_codes_are_checked() {
+        let login = login_defaults();
+        assert_eq!(login.login("ctrl".to_owned()).flag, DEFAULT_FLAG);
+        let login = login.flag("gb").expect("known flag");
+        assert_eq!(login.login("ctrl".to_owned()).flag, "GB");
+        assert_eq!(login.flag("JOLLY").unwrap().flag, "JOLLY");
+
+        let err = login.flag("XX").unwrap_err();
+        assert!(err.contains("'XX'"), "{}", err);
+        assert!(err.contains("UN, US"), "{}", err);
+    }
+
+    #[test]
+    fn watchdog_of_zero_waits_forever() {
+        let start = time::Instant::now();
+        let watchdog = Watchdog::new(time::Duration::from_secs(0), start);
+        assert_eq!(
+            watchdog.remaining(start + time::Duration::from_secs(3600)),
+            None
+        );
+        assert!(!watchdog.is_stale(start + time::Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn reconnects_wait_once_the_burst_is_spent() {
+        let start = time::Instant::now();
+        let secs = |secs| time::Duration::from_secs(secs);
+        let limiter = ReconnectLimiter::new(6);
+
+        // The burst goes ahead at once, then one every ten seconds
+        for _ in 0..RECONNECT_BURST {
+            assert_eq!(limiter.reserve(start), secs(0));
+        }
+        assert_eq!(limiter.reserve(start), secs(10));
+        assert_eq!(limiter.reserve(start), secs(20));
+
+        // Clones share the bucket, which refills over time
+        assert_eq!(limiter.clone().reserve(start + secs(25)), secs(5));
+        assert_eq!(limiter.reserve(start + secs(90)), secs(0));
+
+        let unlimited = ReconnectLimiter::new(0);
+        for _ in 0..10 {
+            assert_eq!(unlimited.reserve(start), secs(0));
+        }
+    }
+}
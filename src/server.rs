@@ -7,86 +7,1173 @@
 //! `Server` is the main component that handles client requests
 //! and manages bots.
 
+use crate::assignments::Assignments;
+use crate::client::GameClient;
 use crate::commands;
 use crate::commands::ControlTower;
+use crate::console;
+use crate::event_log;
+use crate::event_log::{Outcome, Record};
+use crate::metrics::Metrics;
+use crate::net;
+use crate::state;
+use crate::status;
+use crate::types;
 use crate::wing;
+use crate::ServerArgs;
 
 use airmash_client::{ClientBase, ClientEvent};
 use airmash_protocol as protocol;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{atomic, mpsc, Arc};
 use std::time;
 
 use url::Url;
 
+/// Signal used to ask every server to shut down
+///
+/// Unlike a `wing::Flag`, dropping a `Shutdown` does nothing;
+/// shutdown is only requested explicitly.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    inner: Arc<atomic::AtomicBool>,
+}
+
+impl Shutdown {
+    /// Ask the servers to shut down
+    pub fn request(&self) {
+        self.inner.store(true, atomic::Ordering::SeqCst);
+    }
+
+    /// True if shutdown has been requested
+    pub fn requested(&self) -> bool {
+        self.inner.load(atomic::Ordering::SeqCst)
+    }
+}
+
+/// Connect to the server at `url` and log in as `ctrl_name` with `login`
+///
+/// Returns a message describing the error if either step fails, or takes
+/// longer than `login` allows.
+pub async fn login(
+    url: Url,
+    ctrl_name: String,
+    login: net::LoginOptions,
+) -> Result<ClientBase, String> {
+    let mut client = match await!(net::open_within(url, login)) {
+        Ok(client) => client,
+        Err(err) => return Err(format!("client connection error: {}", err)),
+    };
+
+    if let Err(err) = await!(client.send(login.login(ctrl_name))) {
+        return Err(format!("client login error {}", err));
+    } else if let Err(err) = await!(net::wait_for_login(&mut client, login)) {
+        return Err(format!("wait for login error {}", err));
+    }
+    Ok(client)
+}
+
+/// Connect to the server at `url`, log in as `ctrl_name` with `login`, and spectate
+///
+/// Returns a message describing the error if any step fails.
+pub async fn connect(
+    url: Url,
+    ctrl_name: String,
+    login: net::LoginOptions,
+) -> Result<ClientBase, String> {
+    let mut client = await!(self::login(url, ctrl_name, login))?;
+
+    // Force ground control to spectate
+    if let Err(err) = await!(client.send(protocol::client::Command {
+        com: "spectate".to_owned(),
+        data: FREE_SPECTATE.to_owned(),
+    })) {
+        return Err(format!("force spectate error {}", err));
+    }
+
+    Ok(client)
+}
+
+/// Spectate target that lets the spectator look around freely
+const FREE_SPECTATE: &str = "-3";
+
+/// Most chat messages we'll hold before dropping new ones
+const MAX_QUEUED_CHAT: usize = 32;
+
+/// How long requested wings have to connect before we tell the player
+/// they couldn't be deployed
+const DEPLOY_TIMEOUT: time::Duration = time::Duration::from_secs(15);
+
+/// Time between saves of our assignments to the state file
+const STATE_SAVE_INTERVAL: time::Duration = time::Duration::from_secs(30);
+
+/// Longest we wait for a packet before looking for console commands, when
+/// there's a console
+const CONSOLE_POLL: time::Duration = time::Duration::from_millis(250);
+
+/// How a player's wings were last asked to fly, kept so the assignment
+/// can be saved
+struct Orders {
+    /// Name of the player who asked for the wings
+    owner: String,
+    /// Name of the player the wings follow
+    target: String,
+    /// Every player the wings attack in turn, starting with `target`, if
+    /// there are several
+    rotation: Vec<String>,
+    /// Every player any of the wings may be after, including those sent
+    /// after others before a top-up
+    following: Vec<String>,
+    /// The ship the wings fly, if not the default
+    ship: Option<protocol::PlaneType>,
+    /// Whether the wings attack or defend their target
+    behavior: commands::Behavior,
+    /// The range the wings open fire within, if not the default
+    range: Option<u16>,
+    /// The wingman the others fly in formation with, if wings follow a leader
+    lead: wing::Lead,
+}
+
+/// A player with wings who left the game, and may be back
+struct Departure {
+    /// Name of the player, to recognize them if they rejoin
+    name: String,
+    /// When they left
+    left: time::Instant,
+}
+
+/// Requested wings, waiting for a wingman to connect
+struct Deployment {
+    /// Name of the player who requested the wings
+    user: String,
+    /// Messages confirming the wings are on their way
+    confirmation: Vec<String>,
+    /// Wingmen that haven't connected or failed yet
+    waiting: usize,
+    /// When the wingmen were spawned
+    started: time::Instant,
+}
+
+/// Why a server stopped running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// Shutdown was requested
+    Shutdown,
+    /// The game server kicked us; reconnecting may work
+    Kicked,
+    /// The game server banned us; reconnecting won't work
+    Banned,
+    /// The connection was lost, and reconnecting failed
+    ConnectionLost,
+}
+
+/// The reason behind an error packet, if it disconnects us
+fn disconnect_reason(error: &protocol::server::Error) -> Option<DisconnectReason> {
+    use protocol::ErrorType;
+    match error.error {
+        ErrorType::Banned | ErrorType::BannedForPacketFlooding | ErrorType::AccountBanned => {
+            Some(DisconnectReason::Banned)
+        }
+        ErrorType::DisconnectedForPacketFlooding | ErrorType::Kicked | ErrorType::AfkTimeout => {
+            Some(DisconnectReason::Kicked)
+        }
+        _ => None,
+    }
+}
+
+/// A one-line summary of a server's run, for the log when it stops
+///
+/// `players` and `wingmen` count the wings still out when it stopped, which
+/// are recalled or abandoned along with it.
+fn exit_summary(
+    url: &str,
+    reason: DisconnectReason,
+    uptime: time::Duration,
+    players: usize,
+    wingmen: usize,
+) -> String {
+    format!(
+        "ground control for {} exited: reason={:?} uptime={}s players_with_wings={} wingmen={}",
+        url,
+        reason,
+        uptime.as_secs(),
+        players,
+        wingmen
+    )
+}
+
+/// Tell `user` that none of their wings connected
+fn deploy_failed_message(user: &str) -> String {
+    format!(
+        "Sorry {}, your wings couldn't take off; try again later",
+        user
+    )
+}
+
+/// A chat message waiting to be sent
+enum Chat {
+    /// Sent to everyone
+    Public(String),
+    /// Whispered to one player
+    Whisper(protocol::Player, String),
+}
+
+/// Try to connect to the server at `url` up to `attempts` times, backing off
+/// exponentially from `backoff` between attempts
+///
+/// Each attempt also waits its turn with `limiter`, which paces reconnects
+/// across every server. Returns `None` if every attempt failed or shutdown
+/// was requested.
+pub async fn connect_with_backoff(
+    url: Url,
+    ctrl_name: String,
+    login: net::LoginOptions,
+    attempts: u32,
+    backoff: time::Duration,
+    limiter: net::ReconnectLimiter,
+    shutdown: Shutdown,
+) -> Option<ClientBase> {
+    let mut backoff = backoff;
+    for attempt in 1..=attempts {
+        log::info!(
+            "Connecting to {} in {:?} (attempt {} of {})",
+            url,
+            backoff,
+            attempt,
+            attempts
+        );
+        await!(net::sleep(backoff));
+        let throttle = limiter.reserve(time::Instant::now());
+        if throttle > time::Duration::from_secs(0) {
+            log::warn!(
+                "too many reconnects across all servers; holding {} back another {:?}",
+                url,
+                throttle
+            );
+            await!(net::sleep(throttle));
+        }
+        if shutdown.requested() {
+            return None;
+        }
+
+        match await!(connect(url.clone(), ctrl_name.clone(), login)) {
+            Ok(client) => {
+                log::info!("Connected to {}", url);
+                return Some(client);
+            }
+            Err(err) => log::warn!("connection to {} failed: {}", url, err),
+        }
+        backoff *= 2;
+    }
+
+    log::error!("giving up on {} after {} attempts", url, attempts);
+    None
+}
+
 /// A connected server that can drop into an event
 /// loop, handling client messages
-pub struct Server {
+pub struct Server<C = ClientBase> {
     /// Client connection
-    client: ClientBase,
+    client: C,
     /// The control tower that handles wingmen commands and
     /// responses
     tower: ControlTower,
     /// The server that we're talking to
     url: Url,
-    /// Players to associated wingmen control flags
-    wingmen: HashMap<protocol::Player, Vec<wing::Flag>>,
+    /// Players to associated wingmen, within the server's limits
+    wingmen: Assignments,
+    /// Wings that haven't connected yet, by the player who requested them
+    deployments: HashMap<protocol::Player, Deployment>,
+    /// How each player's wings were last asked to fly
+    orders: HashMap<protocol::Player, Orders>,
+    /// Players with wings who left, whose wings are kept for the target
+    /// grace in case they rejoin
+    departed: HashMap<protocol::Player, Departure>,
+    /// The new IDs of players who rejoined, by the ID their wingmen still
+    /// report events for
+    rejoined: HashMap<protocol::Player, protocol::Player>,
+    /// Where our assignments are saved, if anywhere
+    state: Option<state::Handle>,
+    /// Saved assignments still to be restored, until the player list arrives
+    restore: Option<Vec<state::Assignment>>,
+    /// Where every command and its outcome are logged, if anywhere
+    event_log: Option<event_log::Handle>,
+    /// When we last saved our assignments
+    last_save: time::Instant,
     /// True to announce ourselves to new players, else false
     announce: bool,
+    /// True to tell new players how busy the server is when we announce
+    /// ourselves
+    rich_announce: bool,
+    /// Time between reminders to everyone that we're here, if enabled
+    announce_interval: Option<time::Duration>,
+    /// When we last reminded everyone that we're here
+    last_announce: time::Instant,
+    /// How long after greeting a player we don't greet them again, so a
+    /// player rejoining over and over isn't greeted every time
+    greet_window: time::Duration,
+    /// When we last greeted each player, by name, within the greet window
+    greeted: HashMap<String, time::Instant>,
+    /// Minimum time between wing requests from the same player
+    request_cooldown: time::Duration,
+    /// When each player last had a wing request granted
+    last_request: HashMap<protocol::Player, time::Instant>,
+    /// The players we answer, when several controllers share the server
+    shard: commands::Shard,
+    /// How long wingmen stay after a player's last granted request, if limited
+    wing_ttl: Option<time::Duration>,
+    /// Options for every wingman we spawn
+    wing_options: wing::Options,
+    /// Our name, used when logging back in
+    ctrl_name: String,
+    /// Maximum number of reconnect attempts
+    max_reconnects: u32,
+    /// Delay before the first reconnect attempt
+    reconnect_backoff: time::Duration,
+    /// Paces our reconnects along with every other server's
+    reconnect_limiter: net::ReconnectLimiter,
+    /// Events reported by our wingmen
+    events: mpsc::Receiver<wing::Event>,
+    /// Cloned into a `wing::Reporter` for each wingman
+    event_sender: mpsc::Sender<wing::Event>,
+    /// Commands typed at the admin console, if there is one
+    console: Option<console::Receiver>,
+    /// True to whisper responses to players, else false to reply in
+    /// public chat
+    whisper: bool,
+    /// True to spectate the player with the most wingmen, else false to
+    /// spectate freely
+    follow_active: bool,
+    /// The player we're spectating, or `None` if we're spectating freely
+    spectating: Option<protocol::Player>,
+    /// True to pretend to spawn wingmen without connecting them
+    dry_run: bool,
+    /// Names of players allowed to use admin commands
+    admins: Vec<String>,
+    /// Names of players allowed to request wings; empty to allow everyone
+    allow: Vec<String>,
+    /// Names of players wingmen won't be sent to attack
+    protected: Vec<String>,
+    /// Chat messages waiting to be sent, oldest first
+    outbox: VecDeque<Chat>,
+    /// Minimum time between any two chat messages we send
+    chat_interval: time::Duration,
+    /// When we last sent a chat message
+    last_chat: Option<time::Instant>,
+    /// The last error sent to each player, and when
+    last_error: HashMap<protocol::Player, (String, time::Instant)>,
+    /// Time during which a repeat of the same error to a player isn't sent
+    error_window: time::Duration,
+    /// Put in front of every chat message we send, so players can tell
+    /// ours apart
+    chat_tag: String,
+    /// Longest chat message the server allows, tag included, or 0 for
+    /// no limit
+    max_msg_len: usize,
+    /// Our status, as reported by the status endpoint
+    status: status::Handle,
+    /// Counters shared with every other server
+    metrics: Arc<Metrics>,
+    /// Number of wingmen last counted in the active wingmen metric
+    counted_wingmen: usize,
+}
+
+impl Server<ClientBase> {
+    /// Connect to the server at the URL in `args`, log in, spectate, and create
+    /// a server using the new client
+    ///
+    /// This is the whole start-up sequence for embedding ground control; see
+    /// `new` for the remaining parameters. If the connection fails, `args` is
+    /// handed back with a message describing the error, so starting can be
+    /// retried later.
+    pub async fn connect(
+        args: ServerArgs,
+        metrics: Arc<Metrics>,
+        status: status::Handle,
+        state: Option<state::Handle>,
+        event_log: Option<event_log::Handle>,
+    ) -> Result<Self, (ServerArgs, String)> {
+        match await!(connect(
+            args.url.clone(),
+            args.ctrl_name.clone(),
+            args.wing_options.login
+        )) {
+            Ok(client) => Ok(Server::new(args, client, metrics, status, state, event_log)),
+            Err(err) => Err((args, err)),
+        }
+    }
 }
 
-impl Server {
-    /// Create a new server connected to the specified URL using the fully-initialized
-    /// client. The maximum number of wingment per player are capped at `max_wingmen`.
+impl<C: GameClient> Server<C> {
+    /// Create a new server using the fully-initialized client, which must be
+    /// connected to the URL in `args`. See `ServerArgs` for the remaining options.
     ///
-    /// If the server should announce itself to new players, set `announce` to `true`.
-    /// Announcing mostly means that we will tell them about the help command.
-    pub fn new(url: Url, client: ClientBase, max_wingmen: u8, announce: bool) -> Self {
+    /// The client is usually a `ClientBase`, as made by `connect`, but any
+    /// `GameClient` will do.
+    ///
+    /// The server updates the shared `metrics` and its own `status` as it runs.
+    /// If there's a `state` file, the assignments saved there are restored once
+    /// the server has the player list, and new assignments are saved as it runs.
+    /// If there's an `event_log`, every command and its outcome are logged there.
+    pub fn new(
+        args: ServerArgs,
+        client: C,
+        metrics: Arc<Metrics>,
+        status: status::Handle,
+        state: Option<state::Handle>,
+        event_log: Option<event_log::Handle>,
+    ) -> Self {
+        let (event_sender, events) = mpsc::channel();
+        // Reminders share the opt-out with announcements to new players
+        let announce_interval = if args.announce && args.announce_interval.as_secs() > 0 {
+            Some(args.announce_interval)
+        } else {
+            None
+        };
+        let restore = state
+            .as_ref()
+            .map(|state| state.assignments(args.url.as_str()));
         Server {
             client,
-            tower: ControlTower::new(max_wingmen),
-            url,
-            wingmen: HashMap::new(),
-            announce,
+            tower: ControlTower::new(args.max_wingmen, &args.prefix),
+            url: args.url,
+            wingmen: Assignments::new(args.max_total_wingmen, args.max_team_wingmen),
+            deployments: HashMap::new(),
+            orders: HashMap::new(),
+            departed: HashMap::new(),
+            rejoined: HashMap::new(),
+            state,
+            restore,
+            event_log,
+            last_save: time::Instant::now(),
+            announce: args.announce,
+            rich_announce: args.rich_announce,
+            announce_interval,
+            last_announce: time::Instant::now(),
+            greet_window: args.greet_window,
+            greeted: HashMap::new(),
+            request_cooldown: args.request_cooldown,
+            last_request: HashMap::new(),
+            shard: args.shard,
+            wing_ttl: args.wing_ttl,
+            wing_options: args.wing_options,
+            ctrl_name: args.ctrl_name,
+            max_reconnects: args.max_reconnects,
+            reconnect_backoff: args.reconnect_backoff,
+            reconnect_limiter: args.reconnect_limiter,
+            events,
+            event_sender,
+            console: None,
+            whisper: args.whisper,
+            follow_active: args.follow_active,
+            spectating: None,
+            dry_run: args.dry_run,
+            admins: args.admins,
+            allow: args.allow,
+            protected: args.protected,
+            outbox: VecDeque::new(),
+            chat_interval: args.chat_interval,
+            last_chat: None,
+            last_error: HashMap::new(),
+            error_window: args.error_window,
+            chat_tag: args.chat_tag,
+            max_msg_len: args.max_msg_len,
+            status,
+            metrics,
+            counted_wingmen: 0,
+        }
+    }
+
+    /// The game server we're connected to
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Update the active wingmen metric with our current count
+    fn update_metrics(&mut self) {
+        let wingmen = self.wingmen.total();
+        self.metrics
+            .active_wingmen_changed(self.counted_wingmen, wingmen);
+        self.counted_wingmen = wingmen;
+    }
+
+    /// Update our status with the wings assigned to each player
+    fn update_status(&self) {
+        let wingmen: BTreeMap<String, usize> = self
+            .wingmen
+            .iter()
+            .map(|(id, wings)| {
+                let name = self.owner_name(id);
+                (name, wings)
+            })
+            .collect();
+        if let Ok(mut status) = self.status.lock() {
+            status.set_wingmen(wingmen);
         }
     }
 
     fn player_name(&self, id: protocol::Player) -> Option<String> {
+        self.client.player_name(id.0)
+    }
+
+    /// Name of a player with wings, for listing them, even if they're away
+    fn owner_name(&self, id: protocol::Player) -> String {
+        match (self.player_name(id), self.departed.get(&id)) {
+            (Some(name), _) => name,
+            (None, Some(departure)) => format!("{} (away)", departure.name),
+            (None, None) => format!("player {}", id.0),
+        }
+    }
+
+    /// Number of players in the game, not counting ourselves or any wingmen
+    fn human_players(&self) -> usize {
         self.client
-            .world
-            .players
-            .get(&id.0)
-            .map(|player| player.name.clone())
+            .player_names()
+            .iter()
+            .filter(|name| {
+                **name != self.ctrl_name && !name.starts_with(&self.wing_options.name_prefix)
+            })
+            .count()
     }
 
-    /// Spawn the number of wingmen specified by wings that track the named player
-    async fn spawn_wingmen(&mut self, id: protocol::Player, wings: u8) {
-        let name = match self.player_name(id) {
-            None => {
-                log::warn!("spawn_wingmen called with unknown player ID {}", id.0);
-                return;
-            }
-            Some(name) => name,
-        };
+    /// True if `wings` more wingmen for player `id` would stay within the server's
+    /// total and per-team limits
+    fn has_capacity(&self, id: protocol::Player, wings: u8) -> bool {
+        let client = &self.client;
+        self.wingmen
+            .has_capacity(id, wings, |owner| client.player_team(owner.0))
+    }
+
+    /// Spawn the number of wingmen specified by wings that track the named target
+    ///
+    /// The wingmen are assigned to the requesting player `id`, and they're added to
+    /// any that are already assigned to that player. Each wingman takes the next
+    /// free formation slot and flies `ship`, or the default ship if `None`, and
+    /// either attacks or defends the first of `targets` according to
+    /// `behavior`. Attacking wingmen move on to the rest in turn. They open
+    /// fire within `range`, or the default range if `None`. When wings follow a
+    /// leader, the wingman in the first slot leads, and later wingmen fall in
+    /// behind whoever leads the player's wing.
+    ///
+    /// Returns `false` without spawning anything if the wingmen would exceed the
+    /// server's total or per-team limits. In a dry run, placeholder wingmen are
+    /// assigned but nothing connects to the server.
+    async fn spawn_wingmen(
+        &mut self,
+        id: protocol::Player,
+        targets: Vec<String>,
+        wings: u8,
+        ship: Option<protocol::PlaneType>,
+        behavior: commands::Behavior,
+        range: Option<u16>,
+    ) -> bool {
+        if !self.has_capacity(id, wings) {
+            return false;
+        }
 
-        let mut flags = Vec::new();
-        for _ in 0..wings {
+        let target = targets[0].clone();
+        let names = if targets.len() > 1 {
+            targets.clone()
+        } else {
+            Vec::new()
+        };
+        let owner = self.player_name(id).unwrap_or_default();
+        let assigned = self.wingmen.count(id);
+        // Extra wings fall in behind the leader of those already flying,
+        // who keep after their own targets
+        let (lead, mut following) = match self.orders.get(&id) {
+            Some(orders) if assigned > 0 => (orders.lead.clone(), orders.following.clone()),
+            _ => (wing::Lead::default(), Vec::new()),
+        };
+        for name in &targets {
+            if !following.contains(name) {
+                following.push(name.clone());
+            }
+        }
+        let rotation = wing::Rotation::new(targets);
+        let mut spawned = Vec::new();
+        for slot in assigned..assigned + usize::from(wings) {
             let flag = wing::Flag::default();
+            let (control, control_receiver) = mpsc::channel();
+            if self.dry_run {
+                log::info!(
+                    "dry run: would spawn a {:?} wingman in slot {} for {}",
+                    behavior,
+                    slot,
+                    target
+                );
+                spawned.push((flag, control));
+                continue;
+            }
+            let login_name = wing::wing_name(&self.wing_options.name_prefix, &target, slot + 1);
+            if slot == 0 && self.wing_options.leader_follow {
+                lead.appoint(login_name.clone());
+            }
             tokio::spawn_async(wing::Wingman::spawn(
                 self.url.clone(),
-                name.clone(),
+                target.clone(),
+                login_name,
+                slot,
+                ship,
+                behavior,
+                rotation.clone(),
+                range,
                 flag.clone(),
+                self.wing_options.clone(),
+                wing::Reporter::new(id, target.clone(), self.event_sender.clone()),
+                control_receiver,
+                lead.clone(),
             ));
-            flags.push(flag);
+            spawned.push((flag, control));
         }
-        self.wingmen.insert(id, flags);
+        self.wingmen.add(id, spawned);
+        self.orders.insert(
+            id,
+            Orders {
+                owner,
+                target,
+                rotation: names,
+                following,
+                ship,
+                behavior,
+                range,
+                lead,
+            },
+        );
+        self.metrics.wingmen_spawned(usize::from(wings));
+        true
+    }
+
+    /// Our current assignments, in the form they're saved
+    ///
+    /// Wings added for a player with different orders are saved with the
+    /// player's latest orders.
+    fn saved_assignments(&self) -> Vec<state::Assignment> {
+        let mut assignments: Vec<state::Assignment> = self
+            .wingmen
+            .iter()
+            .filter_map(|(id, wings)| {
+                let orders = self.orders.get(&id)?;
+                Some(state::Assignment {
+                    player: self.player_name(id)?,
+                    target: orders.target.clone(),
+                    rotation: orders.rotation.clone(),
+                    wings: wings as u8,
+                    ship: orders.ship.map(|ship| commands::ship_name(ship).to_owned()),
+                    behavior: orders.behavior,
+                    range: orders.range,
+                })
+            })
+            .collect();
+        // Sorted, so the state file only changes when the assignments do
+        assignments.sort_by(|a, b| a.player.cmp(&b.player));
+        assignments
+    }
+
+    /// Save our assignments to the state file, if there is one
+    ///
+    /// Nothing is saved until the saved assignments have been restored, so
+    /// they aren't overwritten before they're read.
+    fn save_state(&mut self) {
+        if self.restore.is_some() {
+            return;
+        }
+        if let Some(state) = &self.state {
+            state.save(self.url.as_str(), self.saved_assignments());
+        }
+        self.last_save = time::Instant::now();
+    }
+
+    /// Save our assignments every `STATE_SAVE_INTERVAL`
+    fn save_state_periodically(&mut self) {
+        if self.last_save.elapsed() >= STATE_SAVE_INTERVAL {
+            self.save_state();
+        }
+    }
+
+    /// Respawn the wings saved in the state file, once we have the player list
+    ///
+    /// Assignments are dropped if the player or their target has left the game,
+    /// or if the player now belongs to another shard.
+    async fn restore_assignments(&mut self) {
+        if self.client.player_names().is_empty() {
+            return;
+        }
+        let assignments = match self.restore.take() {
+            Some(assignments) => assignments,
+            None => return,
+        };
+
+        for assignment in assignments {
+            let id = match self.client.player_id(&assignment.player) {
+                Some(id) => protocol::Player(id),
+                None => {
+                    log::info!("not restoring wings for {}; they left", assignment.player);
+                    continue;
+                }
+            };
+            // Wings that took on several players in turn go back to those
+            // still here
+            let mut targets = if assignment.rotation.is_empty() {
+                vec![assignment.target.clone()]
+            } else {
+                assignment.rotation.clone()
+            };
+            targets.retain(|target| self.client.player_id(target).is_some());
+            if !self.shard.owns(&assignment.player) || targets.is_empty() {
+                log::info!("not restoring wings for {}", assignment.player);
+                continue;
+            }
+            let ship = assignment
+                .ship
+                .as_ref()
+                .and_then(|ship| commands::ship_type(ship));
+            if await!(self.spawn_wingmen(
+                id,
+                targets,
+                assignment.wings,
+                ship,
+                assignment.behavior,
+                assignment.range
+            )) {
+                log::info!(
+                    "restored {} wings for {}",
+                    assignment.wings,
+                    assignment.player
+                );
+                self.last_request.insert(id, time::Instant::now());
+                self.reply(
+                    id,
+                    "Ground Control is back; your wings are returning".to_owned(),
+                );
+            } else {
+                log::warn!("no room to restore wings for {}", assignment.player);
+            }
+        }
+        self.save_state();
+    }
+
+    /// Returns the time a player must still wait before requesting wings, or `None`
+    /// if they may request wings now
+    fn cooldown_remaining(&self, id: protocol::Player) -> Option<time::Duration> {
+        self.last_request
+            .get(&id)
+            .map(|last| last.elapsed())
+            .filter(|elapsed| *elapsed < self.request_cooldown)
+            .map(|elapsed| self.request_cooldown - elapsed)
     }
 
     /// Remove the wingmen following the named player
     async fn clear_wingmen(&mut self, id: protocol::Player) {
-        if let Some(flags) = self.wingmen.remove(&id) {
-            log::debug!("clear_wingmen dropping {} wings", flags.len());
+        // Wingmen still waiting to log in are called off too
+        self.deployments.remove(&id);
+        let wings = self.wingmen.clear(id);
+        if wings > 0 {
+            if self.dry_run {
+                log::info!("dry run: would recall {} wings", wings);
+            }
+            log::debug!("clear_wingmen dropping {} wings", wings);
+        }
+    }
+
+    /// Remove up to `wings` of the wingmen assigned to the player
+    ///
+    /// The most recently spawned wingmen are removed first.
+    async fn remove_wingmen(&mut self, id: protocol::Player, wings: u8) {
+        let removed = self.wingmen.remove(id, wings);
+        if self.dry_run {
+            log::info!("dry run: would recall {} wings", removed);
+        }
+        log::debug!("remove_wingmen dropping {} wings", removed);
+    }
+
+    /// Keep the wings of a player who left, in case they're back within the
+    /// target grace, or recall them now if there's no grace
+    async fn player_left(&mut self, id: protocol::Player) {
+        self.last_error.remove(&id);
+        let grace = self.wing_options.target_grace > time::Duration::from_secs(0);
+        let name = self
+            .orders
+            .get(&id)
+            .filter(|_| grace && self.wingmen.contains(id))
+            .map(|orders| orders.owner.clone());
+        if let Some(name) = name {
+            log::debug!("keeping the wings of {} while they're gone", name);
+            self.departed.insert(
+                id,
+                Departure {
+                    name,
+                    left: time::Instant::now(),
+                },
+            );
+            return;
+        }
+        self.last_request.remove(&id);
+        await!(self.clear_wingmen(id));
+    }
+
+    /// Hand the wings a player left behind back to them when they rejoin
+    /// under a new ID
+    fn player_rejoined(&mut self, id: protocol::Player, name: &str) {
+        let old = match self
+            .departed
+            .iter()
+            .find(|(_, departure)| departure.name == name)
+        {
+            Some((&old, _)) => old,
+            None => return,
+        };
+        self.departed.remove(&old);
+        self.wingmen.transfer(old, id);
+        if let Some(orders) = self.orders.remove(&old) {
+            self.orders.insert(id, orders);
+        }
+        if let Some(last) = self.last_request.remove(&old) {
+            self.last_request.insert(id, last);
+        }
+        if let Some(deployment) = self.deployments.remove(&old) {
+            self.deployments.insert(id, deployment);
+        }
+        // Wingmen from earlier visits report under older IDs still
+        for new in self.rejoined.values_mut() {
+            if *new == old {
+                *new = id;
+            }
+        }
+        self.rejoined.insert(old, id);
+        log::info!("{} rejoined; handing back their wings", name);
+        self.reply(
+            id,
+            format!("Welcome back {}, your wings waited for you", name),
+        );
+    }
+
+    /// Recall the wings of players who haven't rejoined within the target grace
+    async fn expire_departures(&mut self) {
+        let grace = self.wing_options.target_grace;
+        let expired: Vec<_> = self
+            .departed
+            .iter()
+            .filter(|(_, departure)| departure.left.elapsed() > grace)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in expired {
+            if let Some(departure) = self.departed.remove(&id) {
+                log::debug!("{} didn't come back; recalling their wings", departure.name);
+            }
+            self.last_request.remove(&id);
+            await!(self.clear_wingmen(id));
+        }
+    }
+
+    /// Send an order to each of the player's wingmen in the air
+    fn order_wingmen(&self, id: protocol::Player, order: wing::Order) {
+        self.wingmen.order(id, &order);
+    }
+
+    /// Send the player's wingmen after the named target, without reconnecting them
+    fn retarget_wingmen(&mut self, id: protocol::Player, target: String) {
+        self.order_wingmen(id, wing::Order::Retarget(target.clone()));
+        if self.dry_run {
+            log::info!("dry run: would retarget wings onto {}", target);
+        }
+        if let Some(orders) = self.orders.get_mut(&id) {
+            // Retargeted wingmen drop their rotation
+            orders.rotation.clear();
+            orders.following = vec![target.clone()];
+            orders.target = target;
+        }
+    }
+
+    /// Switch the player's wingmen to the behavior, without reconnecting them
+    ///
+    /// Defending wings always guard the player who asked for them, so wings
+    /// switching to defend turn back from anyone else they were chasing.
+    fn set_wingmen_mode(
+        &mut self,
+        id: protocol::Player,
+        owner: &str,
+        behavior: commands::Behavior,
+    ) {
+        let chasing = self
+            .orders
+            .get(&id)
+            .map_or(false, |orders| orders.target != owner);
+        if behavior != commands::Behavior::Attack && chasing {
+            self.retarget_wingmen(id, owner.to_owned());
+        }
+        self.order_wingmen(id, wing::Order::Behave(behavior));
+        if self.dry_run {
+            log::info!("dry run: would switch wings to {:?}", behavior);
+        }
+        if let Some(orders) = self.orders.get_mut(&id) {
+            orders.behavior = behavior;
+        }
+    }
+
+    /// Recall every player's wings at once, at the request of the named admin,
+    /// announcing it in chat if `announce` is true
+    fn recall_all(&mut self, admin: &str, announce: bool) {
+        // Dropping the flags shuts down the wingmen
+        let wings = self.wingmen.clear_all();
+        self.deployments.clear();
+        self.orders.clear();
+        self.departed.clear();
+        self.rejoined.clear();
+        log::warn!(
+            "{} recalled all {} wings on server {}",
+            admin,
+            wings,
+            self.url
+        );
+        if self.dry_run {
+            log::info!("dry run: would recall {} wings", wings);
+        }
+        if announce {
+            self.queue_chat(Chat::Public(format!(
+                "Ground Control recalled all {} wings",
+                wings
+            )));
+        }
+    }
+
+    /// Carry out the commands typed at the admin console since we last looked
+    ///
+    /// These are the in-game admin commands, answered in the log instead of
+    /// in chat.
+    async fn handle_console(&mut self) {
+        let commands: Vec<_> = match &self.console {
+            Some(console) => console.try_iter().collect(),
+            None => return,
+        };
+        for command in commands {
+            match command {
+                console::Command::List => {
+                    for line in self.wingmen_list() {
+                        log::info!("{}: {}", self.url, line);
+                    }
+                }
+                console::Command::RecallAll => self.recall_all("the console", true),
+                console::Command::Recall(name) => await!(self.recall_player(name)),
+                console::Command::Allow(allow) => {
+                    log::info!(
+                        "{}: answering wing requests from {}",
+                        self.url,
+                        if allow.is_empty() {
+                            "everyone".to_owned()
+                        } else {
+                            allow.join(", ")
+                        }
+                    );
+                    self.allow = allow;
+                }
+                // The console handles these itself
+                console::Command::Reload | console::Command::Help => (),
+            }
+        }
+    }
+
+    /// Recall the wings of the named player from the console, even if
+    /// they're away
+    async fn recall_player(&mut self, name: String) {
+        let away = self
+            .departed
+            .iter()
+            .find(|(_, departure)| departure.name == name)
+            .map(|(&id, _)| id);
+        let id = match self.client.player_id(&name).map(protocol::Player).or(away) {
+            Some(id) => id,
+            None => {
+                log::warn!("{}: no player named {}", self.url, name);
+                return;
+            }
+        };
+        self.departed.remove(&id);
+        let wings = self.wingmen.count(id);
+        await!(self.clear_wingmen(id));
+        log::warn!(
+            "the console recalled {} wings of {} on server {}",
+            wings,
+            name,
+            self.url
+        );
+        if wings > 0 && away.is_none() {
+            self.reply(
+                id,
+                format!("{}, Ground Control recalled your {} wings", name, wings),
+            );
+        }
+    }
+
+    /// Queue a chat message, to be sent once the chat interval allows
+    ///
+    /// Messages too long for the server, once tagged, are split up and
+    /// queued as several.
+    fn queue_chat(&mut self, chat: Chat) {
+        let room = match self.max_msg_len {
+            0 => 0,
+            max_len => max_len.saturating_sub(self.chat_tag.chars().count()).max(1),
+        };
+        let pieces: Vec<Chat> = match chat {
+            Chat::Public(text) => commands::split_for_chat(&text, room)
+                .into_iter()
+                .map(Chat::Public)
+                .collect(),
+            Chat::Whisper(id, text) => commands::split_for_chat(&text, room)
+                .into_iter()
+                .map(|text| Chat::Whisper(id, text))
+                .collect(),
+        };
+        for chat in pieces {
+            if self.outbox.len() >= MAX_QUEUED_CHAT {
+                log::warn!("chat queue for {} is full; dropping message", self.url);
+                break;
+            }
+            self.outbox.push_back(chat);
+        }
+    }
+
+    /// Queue a message meant for one player, either as a whisper or in public chat
+    fn reply(&mut self, id: protocol::Player, msg: String) {
+        if self.whisper {
+            self.queue_chat(Chat::Whisper(id, msg));
+        } else {
+            self.queue_chat(Chat::Public(msg));
+        }
+    }
+
+    /// Reply to a player with an error, unless we sent them the same error
+    /// within the error window
+    ///
+    /// A player spamming a bad command gets the error once, not once per try.
+    fn reply_error(&mut self, id: protocol::Player, msg: String) {
+        if let Some((last, when)) = self.last_error.get(&id) {
+            if *last == msg && when.elapsed() < self.error_window {
+                log::debug!("not repeating error to player {}: {}", id.0, msg);
+                return;
+            }
+        }
+        self.last_error
+            .insert(id, (msg.clone(), time::Instant::now()));
+        self.reply(id, msg);
+    }
+
+    /// Send the oldest queued chat message, unless we sent one too recently
+    async fn send_queued_chat(&mut self) {
+        if let Some(last) = self.last_chat {
+            if last.elapsed() < self.chat_interval {
+                return;
+            }
+        }
+
+        match self.outbox.pop_front() {
+            Some(chat) => await!(self.send_response(chat)),
+            None => return,
         }
+        self.last_chat = Some(time::Instant::now());
+    }
+
+    /// Send a chat message right away, tagged as coming from us
+    ///
+    /// Every chat message we send goes through here.
+    async fn send_response(&mut self, chat: Chat) {
+        match chat {
+            Chat::Public(text) => {
+                let text = format!("{}{}", self.chat_tag, text);
+                warn_on_err!(await!(self.client.chat(text)))
+            }
+            Chat::Whisper(id, text) => {
+                let text = format!("{}{}", self.chat_tag, text);
+                warn_on_err!(await!(self.client.whisper(id, text)))
+            }
+        }
+    }
+
+    /// One line per player with wings, for the list command, in order of
+    /// their names so the list reads the same every time
+    fn wingmen_list(&self) -> Vec<String> {
+        let mut owners: Vec<(String, usize)> = self
+            .wingmen
+            .iter()
+            .map(|(id, wings)| (self.owner_name(id), wings))
+            .collect();
+        owners.sort();
+        let mut lines: Vec<String> = owners
+            .into_iter()
+            .map(|(name, wings)| format!("{}: {} wings", name, wings))
+            .collect();
+        if lines.is_empty() {
+            lines.push("No wings assigned".to_owned());
+        }
+        lines
+    }
+
+    /// Each player under attack, with the number of wings attacking them,
+    /// most attacked first, for the targets command
+    ///
+    /// Wings whose owner has left without us noticing are recalled rather
+    /// than listed. Wings waiting for their owner to rejoin are left alone.
+    fn attacked_players(&mut self) -> Vec<(String, usize)> {
+        let mut gone = Vec::new();
+        let mut targets: HashMap<String, usize> = HashMap::new();
+        for (id, wings) in self.wingmen.iter() {
+            let name = match self.player_name(id) {
+                Some(name) => name,
+                None => {
+                    if !self.departed.contains_key(&id) {
+                        gone.push(id);
+                    }
+                    continue;
+                }
+            };
+            let target = match self.orders.get(&id) {
+                Some(orders) if orders.behavior != commands::Behavior::Attack => continue,
+                Some(orders) => orders.target.clone(),
+                None => name,
+            };
+            if self.client.player_id(&target).is_some() {
+                *targets.entry(target).or_insert(0) += wings;
+            }
+        }
+
+        for id in gone {
+            let wings = self.wingmen.clear(id);
+            log::info!("recalled {} wings of departed player {}", wings, id.0);
+            self.orders.remove(&id);
+        }
+
+        let mut targets: Vec<(String, usize)> = targets.into_iter().collect();
+        targets.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        targets
+    }
+
+    /// Log a command and its outcome to the event log, if there is one
+    fn log_event(&self, id: protocol::Player, user: &str, message: &str, outcome: Outcome) {
+        let log = match &self.event_log {
+            Some(log) => log,
+            None => return,
+        };
+        log.record(&Record {
+            timestamp: event_log::timestamp(),
+            server: self.url.as_str(),
+            user,
+            message,
+            outcome,
+            wings: self.wingmen.count(id),
+        });
+    }
+
+    /// Count and log a command that was turned down
+    fn rejected(
+        &self,
+        id: protocol::Player,
+        user: &str,
+        message: &str,
+        err: &commands::BadCommand,
+    ) {
+        self.metrics.rejected(err);
+        self.log_event(id, user, message, Outcome::rejected(err));
     }
 
     /// Handle a user's message, possibly spawning or clearing bots
@@ -98,70 +1185,1026 @@ impl Server {
             }
             Some(name) => name,
         };
+        if !self.shard.owns(&name) {
+            // Another ground controller answers this player, but an admin's
+            // recall reaches the wings of every shard
+            let cmd = commands::Command::new(&message, &name, 0);
+            let recall = match self.tower.parse_command(cmd) {
+                Some(Ok(resp)) => resp.kind() == Some(commands::ResponseKind::RecallAll),
+                _ => false,
+            };
+            if recall && self.admins.contains(&name) {
+                self.recall_all(&name, false);
+            }
+            return;
+        }
 
-        let wingmen_count = self
-            .wingmen
-            .get(&id)
-            .as_ref()
-            .map(|wings| wings.len() as u8)
-            .unwrap_or(0u8);
+        let wingmen_count = self.wingmen.count(id) as u8;
         let cmd = commands::Command::new(&message, &name, wingmen_count);
         match self.tower.parse_command(cmd) {
             // Not for us; do nothing
             None => (),
             // Bad command sent from the user
-            Some(Err(err)) => warn_on_err!(await!(self.client.chat(format!("{}", err)))),
+            Some(Err(err)) => {
+                self.rejected(id, &name, &message, &err);
+                let msg = format!("{}", err);
+                self.reply_error(id, msg)
+            }
             // Good command; take some action
-            Some(Ok(resp)) => {
+            Some(Ok(mut resp)) => {
                 match resp.kind() {
-                    Some(commands::ResponseKind::SetWings { wings, .. }) => {
-                        await!(self.spawn_wingmen(id, wings))
+                    Some(commands::ResponseKind::SetWings {
+                        wings,
+                        targets,
+                        ship,
+                        behavior,
+                        range,
+                    })
+                    | Some(commands::ResponseKind::AddWings {
+                        wings,
+                        targets,
+                        ship,
+                        behavior,
+                        range,
+                    }) => {
+                        self.metrics.wing_requested();
+                        if !self.allow.is_empty() && !self.allow.contains(&name) {
+                            let err = commands::BadCommand::NotAuthorized(&name);
+                            self.rejected(id, &name, &message, &err);
+                            self.reply(id, format!("{}", err));
+                            return;
+                        }
+                        if let Some(remaining) = self.cooldown_remaining(id) {
+                            // Round up so we never tell a user to wait 0 seconds
+                            let secs =
+                                remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+                            let err = commands::BadCommand::CoolingDown(&name, secs);
+                            self.rejected(id, &name, &message, &err);
+                            self.reply(id, format!("{}", err));
+                            return;
+                        }
+                        if let commands::Behavior::Patrol { x, y } = behavior {
+                            let map = &self.wing_options.map;
+                            let point = types::Position::new(x as f32, y as f32);
+                            if map.map_position(point).is_occupied(map) {
+                                let point = format!("{} {}", x, y);
+                                let err = commands::BadCommand::BadPatrolPoint(&point);
+                                self.rejected(id, &name, &message, &err);
+                                self.reply(id, format!("{}", err));
+                                return;
+                            }
+                        }
+                        // Leave out any targets who aren't here, unless nobody is
+                        let (found, missing): (Vec<String>, Vec<String>) = targets
+                            .into_iter()
+                            .partition(|target| self.client.player_id(target).is_some());
+                        if found.is_empty() && !missing.is_empty() {
+                            let err = commands::BadCommand::NoSuchPlayer(&missing[0]);
+                            self.rejected(id, &name, &message, &err);
+                            self.reply(id, format!("{}", err));
+                            return;
+                        }
+                        // Only confirm the targets who are here
+                        resp = self.tower.with_targets(resp, &name, wingmen_count, &found);
+                        // Patrolling wings fly from their owner out to the point
+                        let targets = if found.is_empty() {
+                            vec![name.clone()]
+                        } else {
+                            found
+                        };
+                        let protected = targets
+                            .iter()
+                            .find(|&target| self.protected.contains(target));
+                        if let (commands::Behavior::Attack, Some(target)) = (behavior, protected) {
+                            let err = commands::BadCommand::ProtectedTarget(target);
+                            self.rejected(id, &name, &message, &err);
+                            self.reply(id, format!("{}", err));
+                            return;
+                        }
+                        if !await!(self.spawn_wingmen(id, targets, wings, ship, behavior, range)) {
+                            let err = commands::BadCommand::ServerBusy(&name);
+                            self.rejected(id, &name, &message, &err);
+                            self.reply(id, format!("{}", err));
+                            return;
+                        }
+                        for target in &missing {
+                            log::warn!("{} asked for wings on {}, who isn't here", name, target);
+                            let err = commands::BadCommand::NoSuchPlayer(target);
+                            self.reply(id, format!("{}; leaving them out", err));
+                        }
+                        self.last_request.insert(id, time::Instant::now());
+                        if !self.dry_run {
+                            let outcome = Outcome::Accepted { kind: resp.kind() };
+                            self.log_event(id, &name, &message, outcome);
+                            // Only confirm once a wingman has actually connected
+                            self.deployments.insert(
+                                id,
+                                Deployment {
+                                    user: name.clone(),
+                                    confirmation: resp.msg(),
+                                    waiting: usize::from(wings),
+                                    started: time::Instant::now(),
+                                },
+                            );
+                            return;
+                        }
+                    }
+                    Some(commands::ResponseKind::Retarget { target }) => {
+                        if self.client.player_id(&target).is_none() {
+                            let err = commands::BadCommand::NoSuchPlayer(&target);
+                            self.rejected(id, &name, &message, &err);
+                            self.reply(id, format!("{}", err));
+                            return;
+                        }
+                        if self.protected.contains(&target) {
+                            let err = commands::BadCommand::ProtectedTarget(&target);
+                            self.rejected(id, &name, &message, &err);
+                            self.reply(id, format!("{}", err));
+                            return;
+                        }
+                        let behavior = self
+                            .orders
+                            .get(&id)
+                            .map(|orders| orders.behavior)
+                            .filter(|&behavior| behavior != commands::Behavior::Attack);
+                        if let Some(behavior) = behavior {
+                            let err = commands::BadCommand::NotAttacking(&name, behavior);
+                            self.rejected(id, &name, &message, &err);
+                            self.reply(id, format!("{}", err));
+                            return;
+                        }
+                        self.retarget_wingmen(id, target);
+                    }
+                    Some(commands::ResponseKind::SetMode { behavior }) => {
+                        // Wings added with other orders may still be after
+                        // someone other than the latest target
+                        let following = match self.orders.get(&id) {
+                            Some(orders) => orders.following.clone(),
+                            None => vec![name.clone()],
+                        };
+                        let protected = following
+                            .iter()
+                            .find(|&target| self.protected.contains(target));
+                        if let (commands::Behavior::Attack, Some(target)) = (behavior, protected) {
+                            let err = commands::BadCommand::ProtectedTarget(target);
+                            self.rejected(id, &name, &message, &err);
+                            self.reply(id, format!("{}", err));
+                            return;
+                        }
+                        self.set_wingmen_mode(id, &name, behavior);
+                    }
+                    Some(commands::ResponseKind::RemoveWings { wings }) => {
+                        await!(self.remove_wingmen(id, wings))
                     }
                     Some(commands::ResponseKind::ClearWings) => await!(self.clear_wingmen(id)),
+                    Some(commands::ResponseKind::RecallAll) => {
+                        // Don't let other players know the command exists
+                        if !self.admins.contains(&name) {
+                            let err = commands::BadCommand::Unknown(&message);
+                            self.rejected(id, &name, &message, &err);
+                            self.reply(id, format!("{}", err));
+                            return;
+                        }
+                        self.recall_all(&name, true);
+                    }
+                    Some(commands::ResponseKind::Stats) => {
+                        let wingmen = self.wingmen.total();
+                        let users = self.wingmen.players();
+                        let ping = u32::from(self.client.ping());
+                        for msg in commands::stats_message(wingmen, users, ping) {
+                            self.reply(id, msg);
+                        }
+                    }
+                    Some(commands::ResponseKind::Targets) => {
+                        let targets = self.attacked_players();
+                        for msg in commands::targets_message(&targets) {
+                            self.reply(id, msg);
+                        }
+                    }
+                    Some(commands::ResponseKind::ListWings) => {
+                        // Don't let other players know the command exists
+                        if !self.admins.contains(&name) {
+                            let err = commands::BadCommand::Unknown(&message);
+                            self.rejected(id, &name, &message, &err);
+                            self.reply(id, format!("{}", err));
+                            return;
+                        }
+                        for msg in self.wingmen_list() {
+                            self.reply(id, msg);
+                        }
+                    }
                     None => (),
                 };
+                self.log_event(id, &name, &message, Outcome::Accepted { kind: resp.kind() });
                 // Send reply
                 let msgs = resp.msg();
                 for msg in msgs {
-                    warn_on_err!(await!(self.client.chat(msg)));
-                    warn_on_err!(await!(self.client.wait(time::Duration::from_millis(1000))));
+                    self.reply(id, msg);
                 }
             }
         }
     }
 
     /// Handle a packet from the connected server
-    async fn handle_packet(&mut self, packet: protocol::ServerPacket) {
+    ///
+    /// Returns the reason if the packet tells us we're being disconnected.
+    async fn handle_packet(&mut self, packet: protocol::ServerPacket) -> Option<DisconnectReason> {
+        if let protocol::ServerPacket::PlayerNew(ref player_new) = packet {
+            self.player_rejoined(player_new.id, &player_new.name);
+        }
         match packet {
+            protocol::ServerPacket::Error(ref error) => {
+                log::warn!("error from {}: {:?}", self.url, error.error);
+                return disconnect_reason(error);
+            }
             protocol::ServerPacket::ChatPublic(chat_public) => {
                 await!(self.handle_message(chat_public.id, chat_public.text))
             }
             protocol::ServerPacket::PlayerLeave(player_leave) => {
-                await!(self.clear_wingmen(player_leave.id))
+                await!(self.player_left(player_leave.id))
             }
-            protocol::ServerPacket::PlayerNew(ref player_new) if self.announce => {
-                let msg = format!(
-                    "Ground Control, standing by for {}! Use {} for help.",
-                    player_new.name,
-                    commands::command::HELP
+            protocol::ServerPacket::PlayerNew(ref player_new)
+                if self.announce
+                    && self.shard.owns(&player_new.name)
+                    && !player_new.name.starts_with(&self.wing_options.name_prefix) =>
+            {
+                if !self.first_greeting(&player_new.name, time::Instant::now()) {
+                    log::debug!("greeted {} recently; not greeting again", player_new.name);
+                    return None;
+                }
+                let scale = if self.rich_announce {
+                    Some((self.human_players(), self.wingmen.total()))
+                } else {
+                    None
+                };
+                let msgs = commands::greeting_message(
+                    &player_new.name,
+                    &self.tower.commands().help,
+                    scale,
                 );
-                warn_on_err!(await!(self.client.chat(msg)));
+                for msg in msgs {
+                    self.reply(player_new.id, msg);
+                }
             }
             _ => (),
         };
+        None
     }
 
-    /// Run the server event loop
-    pub async fn run(mut self) {
+    /// Note that we're greeting the named player at `now`, unless we already
+    /// did within the greet window, returning true if we should greet them
+    fn first_greeting(&mut self, name: &str, now: time::Instant) -> bool {
+        let window = self.greet_window;
+        match self.greeted.get(name) {
+            Some(&greeted) if now.duration_since(greeted) < window => false,
+            _ => {
+                if window > time::Duration::from_secs(0) {
+                    self.greeted.insert(name.to_owned(), now);
+                }
+                true
+            }
+        }
+    }
+
+    /// Forget the players we greeted longer ago than the greet window
+    fn forget_greetings(&mut self) {
+        let window = self.greet_window;
+        self.greeted.retain(|_, greeted| greeted.elapsed() < window);
+    }
+
+    /// Remind everyone that we're here, if it's been long enough since we last did
+    ///
+    /// Only the first shard reminds everyone, so players hear it once.
+    fn announce_periodically(&mut self) {
+        let interval = match self.announce_interval {
+            Some(interval) if self.shard.is_first() => interval,
+            _ => return,
+        };
+        if self.last_announce.elapsed() >= interval {
+            let msg = format!(
+                "Ground Control, standing by! Use {} for help.",
+                self.tower.commands().help
+            );
+            self.queue_chat(Chat::Public(msg));
+            self.last_announce = time::Instant::now();
+        }
+    }
+
+    /// Recall the wingmen of players whose last granted request is older than
+    /// the wing TTL, if there is one
+    fn recall_expired_wingmen(&mut self) {
+        let ttl = match self.wing_ttl {
+            Some(ttl) => ttl,
+            None => return,
+        };
+        let expired: Vec<_> = self
+            .wingmen
+            .iter()
+            .map(|(id, _)| id)
+            .filter(|id| {
+                self.last_request
+                    .get(id)
+                    .map_or(true, |last| last.elapsed() > ttl)
+            })
+            .collect();
+        for id in expired {
+            let wings = self.wingmen.clear(id);
+            if wings > 0 {
+                log::debug!("recalling {} wings after their TTL", wings);
+                self.reply(id, "Recalled your wings (timed out)".to_owned());
+            }
+        }
+    }
+
+    /// Tell players what happened to their wingmen since we last checked
+    fn report_events(&mut self) {
+        // Our own sender keeps the channel open, so this never sees a disconnect
+        while let Ok(mut event) = self.events.try_recv() {
+            // Wingmen report for the ID their player had when they spawned
+            if let Some(&id) = self.rejoined.get(&event.owner) {
+                event.owner = id;
+            }
+            let owner = match self.player_name(event.owner) {
+                Some(owner) => owner,
+                None => {
+                    log::debug!(
+                        "dropping wingman event for departed player {}",
+                        event.owner.0
+                    );
+                    continue;
+                }
+            };
+            let msg = match event.kind {
+                wing::EventKind::Connected => {
+                    if let Some(deployment) = self.deployments.remove(&event.owner) {
+                        for msg in deployment.confirmation {
+                            self.reply(event.owner, msg);
+                        }
+                    }
+                    continue;
+                }
+                wing::EventKind::Failed => {
+                    let failed = match self.deployments.get_mut(&event.owner) {
+                        Some(deployment) => {
+                            deployment.waiting = deployment.waiting.saturating_sub(1);
+                            deployment.waiting == 0
+                        }
+                        None => false,
+                    };
+                    if !failed {
+                        continue;
+                    }
+                    self.deployments.remove(&event.owner);
+                    deploy_failed_message(&owner)
+                }
+                wing::EventKind::Killed { victim } => {
+                    self.metrics.wingman_kill();
+                    format!("{}, your wing just took down {}!", owner, victim)
+                }
+                wing::EventKind::Unreachable => format!(
+                    "{}, a wing can't reach {} right now and is heading home",
+                    owner, event.target
+                ),
+                wing::EventKind::TargetLost => {
+                    format!("{}, a wing lost track of {}", owner, event.target)
+                }
+                wing::EventKind::TargetNotFound => format!(
+                    "{}, a wing couldn't find {} in the game and turned back",
+                    owner, event.target
+                ),
+            };
+            self.reply(event.owner, msg);
+        }
+    }
+
+    /// Tell players whose wings haven't connected within `DEPLOY_TIMEOUT` that
+    /// they couldn't be deployed
+    fn expire_deployments(&mut self) {
+        let expired: Vec<_> = self
+            .deployments
+            .iter()
+            .filter(|(_, deployment)| deployment.started.elapsed() > DEPLOY_TIMEOUT)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in expired {
+            if let Some(deployment) = self.deployments.remove(&id) {
+                self.reply(id, deploy_failed_message(&deployment.user));
+            }
+        }
+    }
+
+    /// Forget wingmen that have shut down on their own
+    fn prune_wingmen(&mut self) {
+        self.wingmen.prune();
+        let wingmen = &self.wingmen;
+        self.orders.retain(|&id, _| wingmen.contains(id));
+        self.departed.retain(|&id, _| wingmen.contains(id));
+        self.rejoined.retain(|_, &mut id| wingmen.contains(id));
+    }
+
+    /// Recall all wingmen and say goodbye before disconnecting
+    async fn shut_down(&mut self) {
+        // Save before recalling, so the wings come back after a restart
+        self.save_state();
+        let wings = self.wingmen.clear_all();
+        log::info!(
+            "Shutting down ground control on server {}; recalled {} wings",
+            self.url,
+            wings
+        );
+
+        // Skip anything still queued, but respect the chat interval for our goodbye
+        self.outbox.clear();
+        if let Some(last) = self.last_chat {
+            if let Some(wait) = self.chat_interval.checked_sub(last.elapsed()) {
+                await!(net::sleep(wait));
+            }
+        }
+        let goodbye = "Ground Control signing off. All wings recalled!".to_owned();
+        await!(self.send_response(Chat::Public(goodbye)));
+    }
+
+    /// Spectate the player with the most wingmen, if we follow the action
+    ///
+    /// Once nobody has wingmen, like when the followed player leaves and
+    /// their wingmen are recalled, we go back to spectating freely.
+    async fn follow_busiest(&mut self) {
+        if !self.follow_active {
+            return;
+        }
+        // Ties go to the lowest ID, so we don't flip between players
+        let busiest = self
+            .wingmen
+            .iter()
+            .max_by_key(|&(id, wings)| (wings, std::cmp::Reverse(id.0)))
+            .map(|(id, _)| id);
+        if busiest == self.spectating {
+            return;
+        }
+
+        let data = match busiest {
+            Some(id) => id.0.to_string(),
+            None => FREE_SPECTATE.to_owned(),
+        };
+        match await!(self.client.command("spectate", data)) {
+            Ok(_) => self.spectating = busiest,
+            Err(err) => log::warn!("error spectating on {}: {}", self.url, err),
+        }
+    }
+
+    /// Reconnect after losing the connection, backing off exponentially between
+    /// attempts
+    ///
+    /// Our wingmen assignments are kept. Returns `true` once reconnected, or `false`
+    /// if every attempt failed or shutdown was requested.
+    async fn reconnect(&mut self, shutdown: Shutdown) -> bool {
+        match await!(C::reconnect(
+            self.url.clone(),
+            self.ctrl_name.clone(),
+            self.wing_options.login,
+            self.max_reconnects,
+            self.reconnect_backoff,
+            self.reconnect_limiter.clone(),
+            shutdown
+        )) {
+            Some(client) => {
+                // Logging back in starts us spectating freely again
+                self.client = client;
+                self.spectating = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Run the server event loop until `shutdown` is requested, or the
+    /// connection fails and we can't reconnect
+    ///
+    /// Commands typed at the admin `console`, if there is one, are carried
+    /// out between packets. Returns the reason we stopped, after logging a
+    /// summary of the wings that were still out.
+    pub async fn run(
+        mut self,
+        shutdown: Shutdown,
+        console: Option<console::Receiver>,
+    ) -> DisconnectReason {
+        self.console = console;
+        let started = time::Instant::now();
+        let reason = await!(self.serve(shutdown));
+        log::info!(
+            "{}",
+            exit_summary(
+                self.url.as_str(),
+                reason,
+                started.elapsed(),
+                self.wingmen.players(),
+                self.wingmen.total()
+            )
+        );
+        match reason {
+            DisconnectReason::Shutdown => await!(self.shut_down()),
+            DisconnectReason::Banned => {
+                self.wingmen.clear_all();
+            }
+            _ => (),
+        }
+        reason
+    }
+
+    /// Handle events until `shutdown` is requested or we're disconnected for
+    /// good, returning why
+    ///
+    /// A server that sends nothing for too long has probably dropped the
+    /// connection without closing it, so we reconnect as if it had.
+    async fn serve(&mut self, shutdown: Shutdown) -> DisconnectReason {
+        let mut watchdog = net::Watchdog::new(self.wing_options.stale_after, time::Instant::now());
         loop {
-            match await!(self.client.next()) {
-                Err(err) => {
+            if shutdown.requested() {
+                return DisconnectReason::Shutdown;
+            }
+
+            let now = time::Instant::now();
+            // Console commands shouldn't have to wait for the next packet
+            let wait = match (watchdog.remaining(now), &self.console) {
+                (Some(remaining), Some(_)) => Some(remaining.min(CONSOLE_POLL)),
+                (None, Some(_)) => Some(CONSOLE_POLL),
+                (remaining, None) => remaining,
+            };
+            let next = match wait {
+                Some(_) if watchdog.is_stale(now) => None,
+                Some(wait) => await!(net::timeout(self.client.next(), wait)),
+                None => Some(await!(self.client.next())),
+            };
+            match next {
+                // Only stopped to look at the console
+                None if !watchdog.is_stale(time::Instant::now()) => (),
+                None => {
+                    log::warn!(
+                        "nothing from {} in {}s; taking the connection to be dead",
+                        self.url,
+                        watchdog.limit().as_secs()
+                    );
+                    if !await!(self.reconnect(shutdown.clone())) {
+                        return DisconnectReason::ConnectionLost;
+                    }
+                    watchdog.heard(time::Instant::now());
+                }
+                Some(Err(err)) => {
                     log::error!("error awaiting client's next message {}", err);
-                    return;
+                    if !await!(self.reconnect(shutdown.clone())) {
+                        return DisconnectReason::ConnectionLost;
+                    }
+                    watchdog.heard(time::Instant::now());
+                }
+                Some(Ok(None)) => {
+                    log::error!("connection to {} closed", self.url);
+                    if !await!(self.reconnect(shutdown.clone())) {
+                        return DisconnectReason::ConnectionLost;
+                    }
+                    watchdog.heard(time::Instant::now());
+                }
+                Some(Ok(Some(ClientEvent::Packet(packet)))) => {
+                    watchdog.heard(time::Instant::now());
+                    match await!(self.handle_packet(packet)) {
+                        Some(DisconnectReason::Banned) => {
+                            log::error!("banned from {}; recalling wings and giving up", self.url);
+                            return DisconnectReason::Banned;
+                        }
+                        Some(reason) => {
+                            log::warn!("{:?} from {}; reconnecting", reason, self.url);
+                            if !await!(self.reconnect(shutdown.clone())) {
+                                return reason;
+                            }
+                            watchdog.heard(time::Instant::now());
+                        }
+                        None => (),
+                    }
+                    self.update_status();
+                }
+                _ => (),
+            }
+
+            await!(self.restore_assignments());
+            await!(self.handle_console());
+            self.report_events();
+            self.expire_deployments();
+            await!(self.expire_departures());
+            self.prune_wingmen();
+            self.recall_expired_wingmen();
+            self.update_metrics();
+            self.announce_periodically();
+            self.forget_greetings();
+            self.save_state_periodically();
+            await!(self.follow_busiest());
+            await!(self.send_queued_chat());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{FakeClient, Sent};
+
+    fn chat(id: u16, text: &str) -> protocol::ServerPacket {
+        protocol::ServerPacket::ChatPublic(protocol::server::ChatPublic {
+            id: protocol::Player(id),
+            text: text.to_owned(),
+        })
+    }
+
+    fn leave(id: u16) -> protocol::ServerPacket {
+        protocol::ServerPacket::PlayerLeave(protocol::server::PlayerLeave {
+            id: protocol::Player(id),
+        })
+    }
+
+    /// Ground control with no delays, pretending to spawn its wingmen
+    fn args(target_grace: time::Duration) -> ServerArgs {
+        let args = ServerArgs::new(Url::parse("ws://localhost:3501/ffa").unwrap())
+            .announce(false)
+            .dry_run(true)
+            .chat_interval(time::Duration::from_secs(0));
+        let wing_options = wing::Options {
+            target_grace,
+            ..args.wing_options.clone()
+        };
+        args.wing_options(wing_options)
+    }
+
+    /// Like `args`, with every chat message tagged
+    fn tagged_args(tag: &str) -> ServerArgs {
+        args(time::Duration::from_secs(0)).chat_tag(tag.to_owned())
+    }
+
+    /// Play every scripted packet through ground control, then send the chat
+    /// it queued, and return the wings each player has at the end
+    fn play(game: FakeClient, args: ServerArgs) -> Vec<(u16, usize)> {
+        let (done, result) = mpsc::channel();
+        tokio::run_async(async move {
+            let status = status::ServerStatus::new(args.url.to_string());
+            let metrics = Arc::new(Metrics::default());
+            let mut server = Server::new(args, game, metrics, status, None, None);
+            while let Ok(Some(ClientEvent::Packet(packet))) = await!(server.client.next()) {
+                await!(server.handle_packet(packet));
+            }
+            while !server.outbox.is_empty() {
+                await!(server.send_queued_chat());
+            }
+            let mut wings: Vec<_> = server.wingmen.iter().map(|(id, n)| (id.0, n)).collect();
+            wings.sort();
+            done.send(wings).unwrap();
+        });
+        // A panic in the server drops the sender, failing the test here
+        result.recv().expect("server finished the script")
+    }
+
+    #[test]
+    fn wing_request_assigns_wings_and_replies() {
+        let game = FakeClient::default()
+            .player(1, "xplay", 1)
+            .player(2, "detect", 2)
+            .packet(chat(1, "--gc-wings 3"));
+        let sent = game.sent();
+
+        assert_eq!(play(game, args(time::Duration::from_secs(0))), vec![(1, 3)]);
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        match &sent[0] {
+            Sent::Chat(text) => {
+                assert!(text.starts_with("OK xplay, "), "unexpected reply {}", text)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn replies_carry_the_chat_tag() {
+        let game = FakeClient::default()
+            .player(1, "xplay", 1)
+            .packet(chat(1, "--gc-wings 3 @nobody"));
+        let sent = game.sent();
+
+        play(game, tagged_args("[GC] "));
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec![Sent::Chat(format!(
+                "[GC] {}",
+                commands::BadCommand::NoSuchPlayer("nobody")
+            ))]
+        );
+    }
+
+    #[test]
+    fn long_replies_are_split_to_fit() {
+        let game = FakeClient::default()
+            .player(1, "xplay", 1)
+            .packet(chat(1, "--gc-wings 3 @nobody"));
+        let sent = game.sent();
+
+        play(game, tagged_args("[GC] ").max_msg_len(20));
+        let expected = commands::BadCommand::NoSuchPlayer("nobody").to_string();
+        let sent = sent.lock().unwrap();
+        assert!(sent.len() > 1);
+        let mut words = Vec::new();
+        for sent in sent.iter() {
+            match sent {
+                Sent::Chat(text) => {
+                    assert!(text.starts_with("[GC] "), "untagged {}", text);
+                    assert!(text.chars().count() <= 20, "too long {}", text);
+                    words.extend(text["[GC] ".len()..].split_whitespace().map(str::to_owned));
                 }
-                Ok(Some(ClientEvent::Packet(packet))) => await!(self.handle_packet(packet)),
-                _ => continue,
+                other => panic!("unexpected {:?}", other),
             }
         }
+        assert_eq!(words.join(" "), expected);
+    }
+
+    #[test]
+    fn protected_players_are_not_attacked() {
+        let game = FakeClient::default()
+            .player(1, "xplay", 1)
+            .player(2, "admin", 2)
+            .packet(chat(1, "--gc-wings 3 @admin"))
+            .packet(chat(2, "--gc-wings 2"))
+            .packet(chat(2, "--gc-defend 2"));
+        let sent = game.sent();
+
+        let args = args(time::Duration::from_secs(0)).protected(vec!["admin".to_owned()]);
+        assert_eq!(play(game, args), vec![(2, 2)]);
+        let refusal = commands::BadCommand::ProtectedTarget("admin").to_string();
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[0], Sent::Chat(refusal.clone()));
+        assert_eq!(sent[1], Sent::Chat(refusal));
+    }
+
+    #[test]
+    fn mode_switch_checks_every_target_of_topped_up_wings() {
+        let game = FakeClient::default()
+            .player(1, "admin", 1)
+            .player(2, "bob", 2)
+            .packet(chat(1, "--gc-defend 1"))
+            .packet(chat(1, "--gc-wings 1 @bob"))
+            .packet(chat(1, "--gc-mode attack"));
+        let sent = game.sent();
+
+        let args = args(time::Duration::from_secs(0))
+            .protected(vec!["admin".to_owned()])
+            .request_cooldown(time::Duration::from_secs(0));
+        assert_eq!(play(game, args), vec![(1, 2)]);
+        let refusal = commands::BadCommand::ProtectedTarget("admin").to_string();
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 3);
+        // The first wingman is still defending admin, so can't turn on them
+        assert_eq!(sent[2], Sent::Chat(refusal));
+    }
+
+    #[test]
+    fn mode_switches_wings_without_respawning() {
+        let game = FakeClient::default()
+            .player(1, "xplay", 1)
+            .player(2, "detect", 2)
+            .packet(chat(1, "--gc-wings 3 @detect"))
+            .packet(chat(1, "--gc-mode defend"))
+            .packet(chat(1, "--gc-targets"));
+        let sent = game.sent();
+
+        assert_eq!(play(game, args(time::Duration::from_secs(0))), vec![(1, 3)]);
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(
+            sent[1],
+            Sent::Chat("OK xplay, your wings are falling back to defend you!".to_owned())
+        );
+        // Defending wings turned back from detect
+        assert_eq!(sent[2], Sent::Chat("Nobody is under attack".to_owned()));
+    }
+
+    #[test]
+    fn missing_targets_are_left_out() {
+        let game = FakeClient::default()
+            .player(1, "xplay", 1)
+            .player(2, "alice", 2)
+            .player(3, "carol", 2)
+            .packet(chat(1, "--gc-wings 2 @alice @bob @carol"))
+            .packet(chat(3, "--gc-wings 2 @bob @dave"));
+        let sent = game.sent();
+
+        assert_eq!(play(game, args(time::Duration::from_secs(0))), vec![(1, 2)]);
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 3);
+        let missing = commands::BadCommand::NoSuchPlayer("bob").to_string();
+        assert_eq!(
+            sent[0],
+            Sent::Chat(format!("{}; leaving them out", missing))
+        );
+        // Confirmed for just the players who are here
+        match &sent[1] {
+            Sent::Chat(text) => assert!(
+                text.starts_with("OK xplay, 2 wings are coming for alice, carol!"),
+                "unexpected reply {}",
+                text
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(sent[2], Sent::Chat(missing));
+    }
+
+    #[test]
+    fn bad_request_is_answered_without_wings() {
+        let game = FakeClient::default()
+            .player(1, "xplay", 1)
+            .packet(chat(1, "--gc-wings 3 @nobody"));
+        let sent = game.sent();
+
+        assert!(play(game, args(time::Duration::from_secs(0))).is_empty());
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec![Sent::Chat(
+                commands::BadCommand::NoSuchPlayer("nobody").to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn player_leaving_clears_their_wings() {
+        let game = FakeClient::default()
+            .player(1, "xplay", 1)
+            .player(2, "detect", 1)
+            .packet(chat(1, "--gc-wings 3"))
+            .packet(chat(2, "--gc-wings 2"))
+            .packet(leave(1));
+
+        assert_eq!(play(game, args(time::Duration::from_secs(0))), vec![(2, 2)]);
+    }
+
+    #[test]
+    fn player_leaving_keeps_their_wings_for_the_grace() {
+        let game = FakeClient::default()
+            .player(1, "xplay", 1)
+            .packet(chat(1, "--gc-wings 3"))
+            .packet(leave(1));
+
+        assert_eq!(
+            play(game, args(time::Duration::from_secs(60))),
+            vec![(1, 3)]
+        );
+    }
+
+    #[test]
+    fn exit_summary_counts_what_was_left() {
+        let summary = exit_summary(
+            "ws://localhost:3501/ffa",
+            DisconnectReason::ConnectionLost,
+            time::Duration::from_secs(90),
+            2,
+            5,
+        );
+        assert_eq!(
+            summary,
+            "ground control for ws://localhost:3501/ffa exited: reason=ConnectionLost \
+             uptime=90s players_with_wings=2 wingmen=5"
+        );
+    }
+
+    #[test]
+    fn console_recalls_wings_by_name() {
+        let game = FakeClient::default()
+            .player(1, "xplay", 1)
+            .player(2, "detect", 2)
+            .packet(chat(1, "--gc-wings 3"))
+            .packet(chat(2, "--gc-wings 2"));
+        let sent = game.sent();
+        let (commands, console) = mpsc::channel();
+        commands
+            .send(console::Command::Recall("xplay".to_owned()))
+            .unwrap();
+        commands
+            .send(console::Command::Recall("nobody".to_owned()))
+            .unwrap();
+
+        let (done, result) = mpsc::channel();
+        tokio::run_async(async move {
+            let args = args(time::Duration::from_secs(0));
+            let status = status::ServerStatus::new(args.url.to_string());
+            let metrics = Arc::new(Metrics::default());
+            let mut server = Server::new(args, game, metrics, status, None, None);
+            server.console = Some(console);
+            while let Ok(Some(ClientEvent::Packet(packet))) = await!(server.client.next()) {
+                await!(server.handle_packet(packet));
+            }
+            await!(server.handle_console());
+            while !server.outbox.is_empty() {
+                await!(server.send_queued_chat());
+            }
+            let wings: Vec<_> = server.wingmen.iter().map(|(id, n)| (id.0, n)).collect();
+            done.send(wings).unwrap();
+        });
+
+        assert_eq!(result.recv().expect("server finished"), vec![(2, 2)]);
+        let sent = sent.lock().unwrap();
+        assert_eq!(
+            sent.last(),
+            Some(&Sent::Chat(
+                "xplay, Ground Control recalled your 3 wings".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn players_are_greeted_once_a_window() {
+        let args = args(time::Duration::from_secs(0)).greet_window(time::Duration::from_secs(60));
+        let status = status::ServerStatus::new(args.url.to_string());
+        let metrics = Arc::new(Metrics::default());
+        let mut server = Server::new(args, FakeClient::default(), metrics, status, None, None);
+        let start = time::Instant::now();
+        let secs = |secs| start + time::Duration::from_secs(secs);
+
+        assert!(server.first_greeting("xplay", start));
+        assert!(!server.first_greeting("xplay", secs(30)));
+        assert!(server.first_greeting("detect", secs(30)));
+        // Rejoining within the window doesn't start it over
+        assert!(server.first_greeting("xplay", secs(61)));
+
+        let args = ServerArgs::new(Url::parse("ws://localhost:3501/ffa").unwrap())
+            .greet_window(time::Duration::from_secs(0));
+        let status = status::ServerStatus::new(args.url.to_string());
+        let metrics = Arc::new(Metrics::default());
+        let mut server = Server::new(args, FakeClient::default(), metrics, status, None, None);
+        assert!(server.first_greeting("xplay", start));
+        assert!(server.first_greeting("xplay", start));
+        assert!(server.greeted.is_empty());
+    }
+
+    #[test]
+    fn only_allowed_players_get_wings() {
+        let game = FakeClient::default()
+            .player(1, "trusted", 1)
+            .player(2, "stranger", 2)
+            .packet(chat(1, "--gc-wings 2"))
+            .packet(chat(2, "--gc-wings 2"))
+            .packet(chat(2, "--gc-status"))
+            .packet(chat(2, "--gc-help"));
+        let sent = game.sent();
+        let args = args(time::Duration::from_secs(0)).allow(vec!["trusted".to_owned()]);
+
+        assert_eq!(play(game, args), vec![(1, 2)]);
+        let sent = sent.lock().unwrap();
+        let refusal = commands::BadCommand::NotAuthorized("stranger").to_string();
+        assert_eq!(sent[1], Sent::Chat(refusal.clone()));
+        // Everything else still works for them
+        assert_eq!(sent[2], Sent::Chat("You have no wings assigned".to_owned()));
+        assert!(sent.len() > 3, "help went unanswered");
+        assert!(sent[3..]
+            .iter()
+            .all(|sent| *sent != Sent::Chat(refusal.clone())));
+    }
+
+    #[test]
+    fn admin_recall_reaches_every_shard() {
+        let game = FakeClient::default()
+            .player(1, "xplay", 1)
+            .player(2, "mike", 1)
+            .player(3, "boss", 2)
+            .packet(chat(1, "--gc-wings 1"))
+            .packet(chat(2, "--gc-wings 3"))
+            .packet(chat(3, "--gc-recall-all"));
+        let sent = game.sent();
+        // Shard 0 answers boss; this one answers xplay and mike
+        let args = ServerArgs {
+            shard: commands::Shard::new(1, 2).unwrap(),
+            ..args(time::Duration::from_secs(0)).admins(vec!["boss".to_owned()])
+        };
+
+        assert_eq!(play(game, args), vec![]);
+        // Only the replies to xplay and mike; boss hears from shard 0
+        assert_eq!(sent.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn list_is_sorted_by_name() {
+        let game = FakeClient::default()
+            .player(1, "zed", 1)
+            .player(2, "mike", 1)
+            .player(3, "alice", 2)
+            .player(4, "boss", 2)
+            .packet(chat(1, "--gc-wings 1"))
+            .packet(chat(2, "--gc-wings 3"))
+            .packet(chat(3, "--gc-wings 2"))
+            .packet(chat(4, "--gc-list"));
+        let sent = game.sent();
+        let args = args(time::Duration::from_secs(0)).admins(vec!["boss".to_owned()]);
+
+        assert_eq!(play(game, args), vec![(1, 1), (2, 3), (3, 2)]);
+        let sent = sent.lock().unwrap();
+        assert_eq!(
+            sent[sent.len() - 3..].to_vec(),
+            vec![
+                Sent::Chat("alice: 2 wings".to_owned()),
+                Sent::Chat("mike: 3 wings".to_owned()),
+                Sent::Chat("zed: 1 wings".to_owned()),
+            ]
+        );
     }
 }
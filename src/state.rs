@@ -0,0 +1,180 @@
+//! Wing assignments saved across restarts
+//!
+//! A `wing::Flag` belongs to a live wingman and can't be saved, so each
+//! `Server` saves who has wings, how many, and how they fly. When ground
+//! control starts again, the saved assignments are read back and wingmen
+//! are respawned for the players who are still in the game.
+//!
+//! Every server shares one state file, with each server's assignments
+//! stored under its URL.
+
+use crate::commands::Behavior;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// The wings assigned to one player
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Assignment {
+    /// Name of the player the wings are assigned to
+    pub player: String,
+    /// Name of the player the wings follow
+    pub target: String,
+    /// Every player the wings attack in turn, starting with `target`, if
+    /// there are several
+    #[serde(default)]
+    pub rotation: Vec<String>,
+    /// Number of wings
+    pub wings: u8,
+    /// The ship the wings fly, if not the default
+    pub ship: Option<String>,
+    /// Whether the wings attack or defend their target
+    pub behavior: Behavior,
+    /// The range the wings open fire within, if not the default
+    pub range: Option<u16>,
+}
+
+/// Saved assignments, by server URL
+type Assignments = BTreeMap<String, Vec<Assignment>>;
+
+/// The state file shared by every server
+#[derive(Debug)]
+pub struct StateFile {
+    /// Where the state is saved
+    path: PathBuf,
+    /// The latest assignments of every server
+    servers: Mutex<Assignments>,
+}
+
+/// The state file, shared between servers
+pub type Handle = Arc<StateFile>;
+
+/// Parse the contents of a state file
+fn parse(text: &str) -> Result<Assignments, String> {
+    serde_json::from_str(text).map_err(|err| format!("{}", err))
+}
+
+impl StateFile {
+    /// Read the state saved at `path`
+    ///
+    /// A missing or corrupt file is logged, and ground control starts with
+    /// no assignments.
+    pub fn load(path: PathBuf) -> Handle {
+        let servers = match fs::read_to_string(&path) {
+            Ok(text) => parse(&text).unwrap_or_else(|err| {
+                log::warn!("ignoring corrupt state file {}: {}", path.display(), err);
+                Assignments::new()
+            }),
+            Err(err) => {
+                log::info!("no saved state at {}: {}", path.display(), err);
+                Assignments::new()
+            }
+        };
+        Arc::new(StateFile {
+            path,
+            servers: Mutex::new(servers),
+        })
+    }
+
+    /// The assignments saved for the server at `url`
+    pub fn assignments(&self, url: &str) -> Vec<Assignment> {
+        self.servers
+            .lock()
+            .ok()
+            .and_then(|servers| servers.get(url).cloned())
+            .unwrap_or_default()
+    }
+
+    /// Replace the assignments of the server at `url`, and write every
+    /// server's assignments to the file
+    ///
+    /// The file is written to a temporary file first, then renamed, so a
+    /// crash while saving doesn't leave a partly written file behind.
+    pub fn save(&self, url: &str, assignments: Vec<Assignment>) {
+        let text = {
+            let mut servers = match self.servers.lock() {
+                Ok(servers) => servers,
+                Err(_) => return,
+            };
+            servers.insert(url.to_owned(), assignments);
+            match serde_json::to_string_pretty(&*servers) {
+                Ok(text) => text,
+                Err(err) => {
+                    log::error!("error serializing state: {}", err);
+                    return;
+                }
+            }
+        };
+
+        let tmp = self.path.with_extension("tmp");
+        if let Err(err) = fs::write(&tmp, text).and_then(|_| fs::rename(&tmp, &self.path)) {
+            log::warn!("error saving state to {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_saved_assignments() {
+        let text = r#"{
+            "ws://localhost:3501/ffa": [
+                {
+                    "player": "pilot",
+                    "target": "enemy",
+                    "wings": 3,
+                    "ship": "mohawk",
+                    "behavior": "Attack",
+                    "range": null
+                }
+            ]
+        }"#;
+        let servers = parse(text).unwrap();
+        let assignments = &servers["ws://localhost:3501/ffa"];
+        assert_eq!(assignments.len(), 1);
+        assert_eq!(assignments[0].player, "pilot");
+        assert_eq!(assignments[0].wings, 3);
+        assert_eq!(assignments[0].ship, Some("mohawk".to_owned()));
+        assert_eq!(assignments[0].behavior, Behavior::Attack);
+        // Saved before wings took on several players in turn
+        assert!(assignments[0].rotation.is_empty());
+    }
+
+    #[test]
+    fn parses_saved_rotation() {
+        let text = r#"{
+            "ws://localhost:3501/ffa": [
+                {
+                    "player": "pilot",
+                    "target": "alice",
+                    "rotation": ["alice", "bob"],
+                    "wings": 2,
+                    "ship": null,
+                    "behavior": "Attack",
+                    "range": null
+                }
+            ]
+        }"#;
+        let servers = parse(text).unwrap();
+        let assignments = &servers["ws://localhost:3501/ffa"];
+        assert_eq!(assignments[0].rotation, vec!["alice", "bob"]);
+    }
+
+    #[test]
+    fn rejects_corrupt_state() {
+        assert!(parse("{\"ws://localhost\": [{\"player\": ").is_err());
+        assert!(parse("[]").is_err());
+    }
+
+    #[test]
+    fn missing_file_starts_empty() {
+        let state = StateFile::load(PathBuf::from("/nonexistent/ground-control.json"));
+        assert!(state.assignments("ws://localhost:3501/ffa").is_empty());
+    }
+}
@@ -0,0 +1,126 @@
+//! Read-only HTTP status endpoint
+//!
+//! Each `Server` keeps its `ServerStatus` up to date as it runs.
+//! The endpoint reports the latest status of every server as
+//! JSON, so it answers even while a server is busy with packets.
+//! Shared metrics are served from `/metrics`.
+
+use crate::metrics::Metrics;
+use crate::server::Shutdown;
+
+use futures::{future, Future, Stream};
+use hyper::service::service_fn_ok;
+use hyper::{header, Body, Response};
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time;
+
+/// How often the endpoint checks whether shutdown was requested
+const SHUTDOWN_POLL: time::Duration = time::Duration::from_millis(500);
+
+/// The state of one server, as shown by the endpoint
+#[derive(Debug)]
+pub struct ServerStatus {
+    /// The game server we're connected to
+    url: String,
+    /// When ground control started on this server
+    started: time::Instant,
+    /// Wings assigned to each player, by name
+    wingmen: BTreeMap<String, usize>,
+}
+
+/// A server's status, shared between the server and the endpoint
+pub type Handle = Arc<Mutex<ServerStatus>>;
+
+impl ServerStatus {
+    /// Create a status handle for a server connected to `url`
+    pub fn new(url: String) -> Handle {
+        Arc::new(Mutex::new(ServerStatus {
+            url,
+            started: time::Instant::now(),
+            wingmen: BTreeMap::new(),
+        }))
+    }
+
+    /// Replace the wings assigned to each player
+    pub fn set_wingmen(&mut self, wingmen: BTreeMap<String, usize>) {
+        self.wingmen = wingmen;
+    }
+
+    fn report(&self) -> Report<'_> {
+        Report {
+            url: &self.url,
+            total_wingmen: self.wingmen.values().sum(),
+            wingmen: &self.wingmen,
+            uptime_secs: self.started.elapsed().as_secs(),
+        }
+    }
+}
+
+/// The JSON body for one server
+#[derive(Serialize)]
+struct Report<'s> {
+    url: &'s str,
+    total_wingmen: usize,
+    wingmen: &'s BTreeMap<String, usize>,
+    uptime_secs: u64,
+}
+
+/// The JSON body for every server
+fn render(servers: &[Handle]) -> String {
+    let guards: Vec<_> = servers
+        .iter()
+        .filter_map(|status| status.lock().ok())
+        .collect();
+    let reports: Vec<Report> = guards.iter().map(|status| status.report()).collect();
+    serde_json::to_string(&reports).unwrap_or_else(|err| {
+        log::error!("error rendering status {}", err);
+        "[]".to_owned()
+    })
+}
+
+/// Serve the status of `servers` and the `metrics` on `port` until `shutdown`
+/// is requested
+///
+/// Must be called from within the tokio runtime.
+pub fn serve(port: u16, servers: Vec<Handle>, metrics: Arc<Metrics>, shutdown: Shutdown) {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let servers = Arc::new(servers);
+    let builder = match hyper::Server::try_bind(&addr) {
+        Ok(builder) => builder,
+        Err(err) => {
+            log::error!("error binding status endpoint to {}: {}", addr, err);
+            return;
+        }
+    };
+
+    let stop = tokio::timer::Interval::new_interval(SHUTDOWN_POLL)
+        .take_while(move |_| future::ok(!shutdown.requested()))
+        .for_each(|_| Ok(()))
+        .map_err(|err| log::warn!("status shutdown timer error {}", err));
+
+    let endpoint = builder
+        .serve(move || {
+            let servers = servers.clone();
+            let metrics = metrics.clone();
+            service_fn_ok(move |req| {
+                let (content_type, body) = if req.uri().path() == "/metrics" {
+                    ("text/plain; version=0.0.4", metrics.render())
+                } else {
+                    ("application/json", render(&servers))
+                };
+                Response::builder()
+                    .header(header::CONTENT_TYPE, content_type)
+                    .body(Body::from(body))
+                    .unwrap() // the header is valid
+            })
+        })
+        .with_graceful_shutdown(stop)
+        .map_err(|err| log::error!("status endpoint error {}", err));
+
+    log::info!("Serving status on {}", addr);
+    tokio::spawn(endpoint);
+}
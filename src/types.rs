@@ -1,11 +1,419 @@
 use airmash_protocol::Position;
 use line_drawing::Bresenham;
-use pathfinding::prelude::absdiff;
+use lru::LruCache;
+use pathfinding::prelude::{absdiff, astar};
 
-const BOUNDARY_X: f32 = 16384.0;
-const BOUNDARY_Y: f32 = BOUNDARY_X / 2.0;
-const MAP_MAX_X: isize = 512;
-const MAP_MAX_Y: isize = MAP_MAX_X / 2;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Distance from the center of the map to its left and right edges
+pub const BOUNDARY_X: f32 = 16384.0;
+/// Distance from the center of the map to its top and bottom edges
+pub const BOUNDARY_Y: f32 = BOUNDARY_X / 2.0;
+
+/// Number of line-of-sight results a map remembers
+const LINE_OF_SIGHT_CACHE_SIZE: usize = 8192;
+
+/// Pathfinding cost of a step to an orthogonally adjacent cell
+const ORTHOGONAL_COST: isize = 10;
+/// Pathfinding cost of a step to a diagonally adjacent cell (~10 * sqrt(2))
+const DIAGONAL_COST: isize = 14;
+/// Extra pathfinding cost of a step into a cell too close to an obstacle
+const CLEARANCE_COST: isize = 40;
+/// Extra pathfinding cost of a step into a cell to avoid, enough that paths
+/// go a long way around rather than through one
+const AVOID_COST: isize = 200;
+/// Extra pathfinding cost of a step into an enemy's cell, falling off to
+/// nothing at `DANGER_RADIUS`
+const DANGER_COST: isize = 80;
+/// Cells around an enemy that cost extra to path through
+const DANGER_RADIUS: isize = 3;
+
+/// Offsets to a cell's eight neighbors, in the order pathfinding visits them:
+/// row by row from the top left, skipping the cell itself
+const NEIGHBOR_OFFSETS: [(isize, isize); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+/// What fills a map cell, as the number for it in a map file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terrain {
+    /// Open sky (0)
+    Passable,
+    /// Something that can't be flown through, like a mountain or base wall (1)
+    Obstacle,
+    /// Sky that can be flown through but shouldn't be, like a spawn
+    /// protection zone (2)
+    Avoid,
+}
+
+impl Terrain {
+    /// The terrain numbered `value` in a map file, if there is one
+    pub fn from_cell(value: u8) -> Option<Terrain> {
+        match value {
+            0 => Some(Terrain::Passable),
+            1 => Some(Terrain::Obstacle),
+            2 => Some(Terrain::Avoid),
+            _ => None,
+        }
+    }
+
+    /// Extra pathfinding cost of a step into a cell of this terrain
+    #[inline]
+    fn cost(self) -> isize {
+        match self {
+            Terrain::Avoid => AVOID_COST,
+            Terrain::Passable | Terrain::Obstacle => 0,
+        }
+    }
+}
+
+/// A collision map covering the whole game world
+///
+/// Each cell holds a `Terrain`. Pathfinding never goes through obstacles,
+/// and only goes through cells to avoid when going around would cost a
+/// lot more. The cells evenly divide the world, so the map's dimensions set
+/// the size of each cell in world units.
+///
+/// The map is static, so it remembers recent line-of-sight checks.
+/// Share one map between all wingmen so they share those results.
+pub struct Map {
+    /// Cells indexed by row (y), then column (x)
+    cells: Vec<Vec<Terrain>>,
+    /// Number of columns
+    max_x: isize,
+    /// Number of rows
+    max_y: isize,
+    /// Recent results of `obstacle_between`
+    line_of_sight: Mutex<LruCache<(MapPosition, MapPosition), Option<MapPosition>>>,
+}
+
+impl Map {
+    /// Create a map from non-empty rows of equal length
+    fn from_cells(cells: Vec<Vec<Terrain>>) -> Map {
+        Map {
+            max_x: cells[0].len() as isize,
+            max_y: cells.len() as isize,
+            cells,
+            line_of_sight: Mutex::new(LruCache::new(LINE_OF_SIGHT_CACHE_SIZE)),
+        }
+    }
+
+    /// The map of the standard AIRMASH world
+    pub fn builtin() -> Map {
+        Map::from_cells(
+            crate::map::MAP
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|&cell| Terrain::from_cell(cell).unwrap_or(Terrain::Obstacle))
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+
+    /// Parse a map from whitespace-delimited cells, one row per line
+    ///
+    /// Each cell is the number of a `Terrain`. Every row must have the same
+    /// number of cells. Blank lines are skipped.
+    pub fn parse(text: &str) -> Result<Map, String> {
+        let mut cells = Vec::new();
+        for (line_no, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let row: Result<Vec<u8>, _> = line.split_whitespace().map(str::parse).collect();
+            let row = match row {
+                Ok(row) => row,
+                Err(err) => return Err(format!("line {}: {}", line_no + 1, err)),
+            };
+            let row: Vec<Terrain> = match row.iter().map(|&cell| Terrain::from_cell(cell)).collect()
+            {
+                Some(row) => row,
+                None => {
+                    return Err(format!(
+                        "line {}: cells must be 0 (open), 1 (obstacle) or 2 (avoid)",
+                        line_no + 1
+                    ))
+                }
+            };
+            if let Some(first) = cells.first().map(Vec::len) {
+                if row.len() != first {
+                    return Err(format!(
+                        "line {}: expected {} cells, found {}",
+                        line_no + 1,
+                        first,
+                        row.len()
+                    ));
+                }
+            }
+            cells.push(row);
+        }
+
+        if cells.is_empty() {
+            return Err("map has no cells".to_owned());
+        }
+
+        Ok(Map::from_cells(cells))
+    }
+
+    /// Load a map from the file at `path`; see `parse` for the format
+    pub fn load(path: &Path) -> Result<Map, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) => return Err(format!("error reading map {}: {}", path.display(), err)),
+        };
+        Map::parse(&text).map_err(|err| format!("invalid map {}: {}", path.display(), err))
+    }
+
+    /// Width of a cell in world units
+    #[inline]
+    fn cell_width(&self) -> f32 {
+        2.0 * BOUNDARY_X / self.max_x as f32
+    }
+
+    /// Height of a cell in world units
+    #[inline]
+    fn cell_height(&self) -> f32 {
+        2.0 * BOUNDARY_Y / self.max_y as f32
+    }
+
+    /// The map cell containing the world position
+    ///
+    /// Positions beyond the edge of the world are clamped to the nearest
+    /// cell on the map.
+    pub fn map_position(&self, pos: Position) -> MapPosition {
+        let x = (((pos.x.inner() + BOUNDARY_X) / self.cell_width()).max(0.0) as isize)
+            .min(self.max_x - 1);
+        let y = (((pos.y.inner() + BOUNDARY_Y) / self.cell_height()).max(0.0) as isize)
+            .min(self.max_y - 1);
+
+        MapPosition::new(x, y)
+    }
+
+    /// The world position at the center of the map cell
+    pub fn world_position(&self, pos: MapPosition) -> Position {
+        Position::new(
+            (pos.x as f32 + 0.5) * self.cell_width() - BOUNDARY_X,
+            (pos.y as f32 + 0.5) * self.cell_height() - BOUNDARY_Y,
+        )
+    }
+
+    /// True if the position is on the map
+    #[inline]
+    pub fn contains(&self, pos: MapPosition) -> bool {
+        pos.x >= 0 && pos.x < self.max_x && pos.y >= 0 && pos.y < self.max_y
+    }
+
+    /// The terrain at the position; off the map counts as an obstacle
+    #[inline]
+    pub fn terrain(&self, pos: MapPosition) -> Terrain {
+        if self.contains(pos) {
+            self.cells[pos.y as usize][pos.x as usize]
+        } else {
+            Terrain::Obstacle
+        }
+    }
+
+    /// True if the position is off the map or an obstacle
+    ///
+    /// Cells to avoid aren't occupied; they only cost more to path through.
+    #[inline]
+    pub fn is_occupied(&self, pos: MapPosition) -> bool {
+        self.terrain(pos) == Terrain::Obstacle
+    }
+
+    /// Extra pathfinding cost of a step into the position for its terrain
+    #[inline]
+    pub fn terrain_cost(&self, pos: MapPosition) -> isize {
+        self.terrain(pos).cost()
+    }
+
+    /// The highest terrain cost of any cell on a straight line between the
+    /// positions
+    fn max_terrain_between(&self, from: MapPosition, to: MapPosition) -> isize {
+        Bresenham::new(from.into(), to.into())
+            .map(|(x, y)| self.terrain_cost(MapPosition::new(x, y)))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The first cell to avoid on a straight line between the positions,
+    /// if any
+    pub fn avoid_between(&self, from: MapPosition, to: MapPosition) -> Option<MapPosition> {
+        Bresenham::new(from.into(), to.into())
+            .map(|(x, y)| MapPosition::new(x, y))
+            .find(|&pos| self.terrain(pos) == Terrain::Avoid)
+    }
+
+    /// The highest terrain cost of any cell along a path
+    fn max_terrain_along(&self, path: &[MapPosition]) -> isize {
+        path.iter()
+            .map(|&pos| self.terrain_cost(pos))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Detect the position of an obstacle between the two positions.
+    ///
+    /// Results are cached, so repeated checks between the same positions
+    /// are cheap.
+    pub fn obstacle_between(&self, from: MapPosition, to: MapPosition) -> Option<MapPosition> {
+        let key = (from, to);
+        if let Ok(mut cache) = self.line_of_sight.lock() {
+            if let Some(&obstacle) = cache.get(&key) {
+                return obstacle;
+            }
+        }
+
+        let obstacle = Bresenham::new(from.into(), to.into()).find_map(|(x, y)| {
+            let pos = MapPosition::new(x, y);
+            if self.is_occupied(pos) {
+                Some(pos)
+            } else {
+                None
+            }
+        });
+
+        if let Ok(mut cache) = self.line_of_sight.lock() {
+            cache.put(key, obstacle);
+        }
+        obstacle
+    }
+
+    /// Shorten a path by skipping waypoints that can be flown past in a
+    /// straight line
+    ///
+    /// From each kept waypoint, we jump to the furthest later waypoint that's
+    /// in line of sight. The first and last waypoints are always kept.
+    pub fn smooth_path(&self, path: &[MapPosition]) -> Vec<MapPosition> {
+        self.smooth_path_avoiding(path, &DangerMap::default())
+    }
+
+    /// Shorten a path like `smooth_path`, without cutting any closer to the
+    /// enemies in `danger` than the path itself does
+    ///
+    /// Neither version cuts through cells to avoid that the path went around.
+    pub fn smooth_path_avoiding(
+        &self,
+        path: &[MapPosition],
+        danger: &DangerMap,
+    ) -> Vec<MapPosition> {
+        let mut smoothed = Vec::new();
+        let mut from = match path.first() {
+            Some(&first) => first,
+            None => return smoothed,
+        };
+        smoothed.push(from);
+
+        let mut index = 0;
+        while index + 1 < path.len() {
+            let next = (index + 1..path.len())
+                .rev()
+                .find(|&later| {
+                    self.obstacle_between(from, path[later]).is_none()
+                        && self.max_terrain_between(from, path[later])
+                            <= self.max_terrain_along(&path[index..=later])
+                        && danger.max_between(from, path[later])
+                            <= danger.max_along(&path[index..=later])
+                })
+                .unwrap_or(index + 1);
+            from = path[next];
+            smoothed.push(from);
+            index = next;
+        }
+        smoothed
+    }
+}
+
+impl Default for Map {
+    fn default() -> Self {
+        Map::builtin()
+    }
+}
+
+impl fmt::Debug for Map {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Map {{ {} x {} }}", self.max_x, self.max_y)
+    }
+}
+
+/// Extra pathfinding cost of the cells around enemy planes
+///
+/// Unlike the `Map`, enemies move, so each wingman builds its own danger
+/// map from the enemies it can see and rebuilds it now and then. Cells near
+/// several enemies add up the cost of each, so crowds cost the most.
+#[derive(Debug, Default, Clone)]
+pub struct DangerMap {
+    /// Extra cost of each cell near an enemy
+    costs: HashMap<MapPosition, isize>,
+}
+
+impl DangerMap {
+    /// Build a danger map around enemies at the given world positions
+    pub fn new<I: IntoIterator<Item = Position>>(map: &Map, enemies: I) -> DangerMap {
+        let mut costs = HashMap::new();
+        for enemy in enemies {
+            let center = map.map_position(enemy);
+            for dy in -DANGER_RADIUS..=DANGER_RADIUS {
+                for dx in -DANGER_RADIUS..=DANGER_RADIUS {
+                    let pos = MapPosition::new(center.x + dx, center.y + dy);
+                    if !map.contains(pos) {
+                        continue;
+                    }
+                    let falloff = DANGER_RADIUS + 1 - dx.abs().max(dy.abs());
+                    *costs.entry(pos).or_insert(0) += DANGER_COST * falloff / (DANGER_RADIUS + 1);
+                }
+            }
+        }
+        DangerMap { costs }
+    }
+
+    /// Extra pathfinding cost of a step into the position
+    #[inline]
+    pub fn cost(&self, pos: MapPosition) -> isize {
+        self.costs.get(&pos).cloned().unwrap_or(0)
+    }
+
+    /// The first cell near an enemy on a straight line between the
+    /// positions, if any
+    pub fn danger_between(&self, from: MapPosition, to: MapPosition) -> Option<MapPosition> {
+        if self.costs.is_empty() {
+            return None;
+        }
+        Bresenham::new(from.into(), to.into())
+            .map(|(x, y)| MapPosition::new(x, y))
+            .find(|&pos| self.cost(pos) > 0)
+    }
+
+    /// The highest cost of any cell on a straight line between the positions
+    fn max_between(&self, from: MapPosition, to: MapPosition) -> isize {
+        if self.costs.is_empty() {
+            return 0;
+        }
+        Bresenham::new(from.into(), to.into())
+            .map(|(x, y)| self.cost(MapPosition::new(x, y)))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The highest cost of any cell along a path
+    fn max_along(&self, path: &[MapPosition]) -> isize {
+        path.iter().map(|&pos| self.cost(pos)).max().unwrap_or(0)
+    }
+}
 
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct MapPosition {
@@ -19,42 +427,120 @@ impl MapPosition {
     }
 
     #[inline]
-    pub fn is_occupied(self) -> bool {
-        self.x < 0
-            || self.x >= MAP_MAX_X
-            || self.y < 0
-            || self.y >= MAP_MAX_Y
-            || crate::map::MAP[self.y as usize][self.x as usize] == 1
+    pub fn is_occupied(self, map: &Map) -> bool {
+        map.is_occupied(self)
     }
 
     /// Detect the position of an obstacle between the two positions.
-    pub fn obstacle_between(self, other: MapPosition) -> Option<MapPosition> {
-        Bresenham::new(self.into(), other.into()).find_map(|(x, y)| {
-            let pos = MapPosition::new(x, y);
-            if pos.is_occupied() {
-                Some(pos)
-            } else {
-                None
-            }
-        })
+    #[inline]
+    pub fn obstacle_between(self, other: MapPosition, map: &Map) -> Option<MapPosition> {
+        map.obstacle_between(self, other)
     }
 
+    /// Manhattan distance in map cells
+    ///
+    /// Cells aren't a fixed size in world units; use `distance_world` to
+    /// compare against world distances.
     #[inline]
     pub fn distance(self, other: MapPosition) -> isize {
         absdiff(self.x, other.x) + absdiff(self.y, other.y)
     }
 
-    pub fn adjacent_positions(self) -> impl Iterator<Item = MapPosition> {
-        UnoccupiedMapPositionIter::new(self)
+    /// Euclidean distance in world units between the centers of the cells
+    #[inline]
+    pub fn distance_world(self, other: MapPosition, map: &Map) -> f32 {
+        let dx = (self.x - other.x) as f32 * map.cell_width();
+        let dy = (self.y - other.y) as f32 * map.cell_height();
+        (dx * dx + dy * dy).sqrt()
     }
-}
 
-impl From<Position> for MapPosition {
-    fn from(pos: Position) -> MapPosition {
-        let x = (((pos.x.inner() + BOUNDARY_X) / 64.0).abs().max(0.0) as isize).min(MAP_MAX_X - 1);
-        let y = (((pos.y.inner() + BOUNDARY_Y) / 64.0).abs().max(0.0) as isize).min(MAP_MAX_Y - 1);
+    /// Octile distance, the cost of the cheapest unobstructed path between
+    /// the positions when diagonal steps cost `DIAGONAL_COST`
+    #[inline]
+    pub fn octile_distance(self, other: MapPosition) -> isize {
+        let dx = absdiff(self.x, other.x);
+        let dy = absdiff(self.y, other.y);
+        ORTHOGONAL_COST * dx.max(dy) + (DIAGONAL_COST - ORTHOGONAL_COST) * dx.min(dy)
+    }
 
-        MapPosition::new(x, y)
+    /// Pathfinding cost of a step to an adjacent position
+    #[inline]
+    pub fn step_cost(self, next: MapPosition) -> isize {
+        if self.x != next.x && self.y != next.y {
+            DIAGONAL_COST
+        } else {
+            ORTHOGONAL_COST
+        }
+    }
+
+    /// True if the position isn't occupied, and no obstacle is within `margin`
+    /// cells of it in any direction. The edge of the map counts as an obstacle.
+    ///
+    /// This only matters for pathfinding; ships can still fly through these
+    /// positions.
+    pub fn is_pathable(self, map: &Map, margin: isize) -> bool {
+        (-margin..=margin).all(|dy| {
+            (-margin..=margin)
+                .all(|dx| !map.is_occupied(MapPosition::new(self.x + dx, self.y + dy)))
+        })
+    }
+
+    /// Find the cheapest path to `goal` around the map's obstacles
+    ///
+    /// Returns the positions along the path, starting with this one, and the
+    /// path's total cost.
+    pub fn path_to(self, goal: MapPosition, map: &Map) -> Option<(Vec<MapPosition>, isize)> {
+        self.path_with_clearance(goal, map, 0)
+    }
+
+    /// Find the cheapest path to `goal` that keeps `margin` cells away from
+    /// obstacles where it can
+    ///
+    /// Steps into positions that aren't pathable with the margin cost extra,
+    /// so tight spots are still passable when there's no other way.
+    pub fn path_with_clearance(
+        self,
+        goal: MapPosition,
+        map: &Map,
+        margin: isize,
+    ) -> Option<(Vec<MapPosition>, isize)> {
+        self.path_avoiding(goal, map, margin, &DangerMap::default())
+    }
+
+    /// Find the cheapest path to `goal` that keeps `margin` cells away from
+    /// obstacles, and away from the enemies in `danger`, where it can
+    ///
+    /// Dangerous cells, like the map's cells to avoid, only cost extra, so the
+    /// path still goes through them when going around would cost more.
+    pub fn path_avoiding(
+        self,
+        goal: MapPosition,
+        map: &Map,
+        margin: isize,
+        danger: &DangerMap,
+    ) -> Option<(Vec<MapPosition>, isize)> {
+        astar(
+            &self,
+            |&p| {
+                p.adjacent_positions(map).map(move |next| {
+                    let penalty = if next.is_pathable(map, margin) {
+                        0
+                    } else {
+                        CLEARANCE_COST
+                    };
+                    let cost = p.step_cost(next) + penalty + map.terrain_cost(next);
+                    (next, cost + danger.cost(next))
+                })
+            },
+            |p| p.octile_distance(goal),
+            |p| *p == goal,
+        )
+    }
+
+    /// The neighboring positions that are on the map and aren't obstacles,
+    /// in the order of `NEIGHBOR_OFFSETS`
+    pub fn adjacent_positions(self, map: &Map) -> impl Iterator<Item = MapPosition> + '_ {
+        UnoccupiedMapPositionIter::new(self, map)
     }
 }
 
@@ -70,56 +556,383 @@ impl From<MapPosition> for (isize, isize) {
     }
 }
 
-impl From<MapPosition> for Position {
-    fn from(pos: MapPosition) -> Position {
-        Position::new(
-            (pos.x * 64) as f32 + 32.0 - BOUNDARY_X,
-            (pos.y * 64) as f32 + 32.0 - BOUNDARY_Y,
-        )
-    }
-}
-
-struct UnoccupiedMapPositionIter {
+/// The unoccupied neighbors of a position; see `MapPosition::adjacent_positions`
+struct UnoccupiedMapPositionIter<'m> {
+    map: &'m Map,
     origin: MapPosition,
-    x: isize,
-    y: isize,
+    /// Index into `NEIGHBOR_OFFSETS` of the next neighbor to check
+    next: usize,
 }
 
-impl UnoccupiedMapPositionIter {
-    pub fn new(origin: MapPosition) -> UnoccupiedMapPositionIter {
+impl<'m> UnoccupiedMapPositionIter<'m> {
+    pub fn new(origin: MapPosition, map: &'m Map) -> UnoccupiedMapPositionIter<'m> {
         UnoccupiedMapPositionIter {
+            map,
             origin,
-            x: origin.x - 1,
-            y: origin.y - 1,
+            next: 0,
         }
     }
 }
 
-impl Iterator for UnoccupiedMapPositionIter {
+impl<'m> Iterator for UnoccupiedMapPositionIter<'m> {
     type Item = MapPosition;
 
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            let x = self.x;
-            let y = self.y;
-
-            self.x += 1;
-
-            if !(x == self.origin.x && y == self.origin.y) {
-                if x <= self.origin.x + 1 {
-                    if y <= self.origin.y + 1 {
-                        let pos = MapPosition::new(x, y);
-                        if !pos.is_occupied() {
-                            return Some(pos);
-                        }
-                    } else {
-                        return None;
-                    }
-                } else {
-                    self.x = self.origin.x - 1;
-                    self.y += 1;
-                }
+        while let Some(&(dx, dy)) = NEIGHBOR_OFFSETS.get(self.next) {
+            self.next += 1;
+            let pos = MapPosition::new(self.origin.x + dx, self.origin.y + dy);
+            if self.map.contains(pos) && !self.map.is_occupied(pos) {
+                return Some(pos);
             }
         }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DangerMap, Map, MapPosition, Position, Terrain, BOUNDARY_X, BOUNDARY_Y};
+
+    /// A map without any obstacles
+    fn open_map(width: usize, height: usize) -> Map {
+        let row = vec!["0"; width].join(" ");
+        Map::parse(&vec![row; height].join("\n")).expect("valid map")
+    }
+
+    #[test]
+    fn diagonal_path_beats_staircase() {
+        let map = open_map(8, 8);
+        let start = MapPosition::new(0, 0);
+        let goal = MapPosition::new(5, 5);
+        let (path, cost) = start.path_to(goal, &map).expect("a path");
+
+        // Alternate right and down steps to reach the same goal
+        let staircase: Vec<MapPosition> = (0..=10)
+            .map(|i| MapPosition::new((i + 1) / 2, i / 2))
+            .collect();
+        let staircase_cost: isize = staircase
+            .windows(2)
+            .map(|step| step[0].step_cost(step[1]))
+            .sum();
+
+        assert_eq!(path.len(), 6);
+        assert_eq!(cost, 5 * 14);
+        assert_eq!(staircase_cost, 10 * 10);
+        assert!(cost < staircase_cost);
+    }
+
+    #[test]
+    fn octile_distance_is_open_path_cost() {
+        let map = open_map(8, 8);
+        let start = MapPosition::new(1, 2);
+        let goal = MapPosition::new(7, 4);
+        let (_, cost) = start.path_to(goal, &map).expect("a path");
+        assert_eq!(cost, start.octile_distance(goal));
+    }
+
+    #[test]
+    fn obstacle_between_cached() {
+        let map = Map::parse("0 0 0 0\n0 0 1 0\n0 0 0 0").expect("valid map");
+        let from = MapPosition::new(0, 1);
+        let to = MapPosition::new(3, 1);
+
+        assert_eq!(
+            from.obstacle_between(to, &map),
+            Some(MapPosition::new(2, 1))
+        );
+        // Second check comes from the cache
+        assert_eq!(
+            from.obstacle_between(to, &map),
+            Some(MapPosition::new(2, 1))
+        );
+        assert_eq!(map.line_of_sight.lock().unwrap().len(), 1);
+
+        let below = MapPosition::new(3, 2);
+        assert_eq!(MapPosition::new(0, 2).obstacle_between(below, &map), None);
+    }
+
+    #[test]
+    fn smooth_path_through_corridor() {
+        // A corridor with a bend around a wall
+        let map = Map::parse(
+            "0 0 0 0 0 0 0 0\n\
+             0 0 0 0 0 0 0 0\n\
+             1 1 1 1 1 0 0 0\n\
+             0 0 0 0 0 0 0 0\n\
+             0 0 0 0 0 0 0 0",
+        )
+        .expect("valid map");
+        let start = MapPosition::new(0, 0);
+        let goal = MapPosition::new(0, 4);
+        let (path, _) = start.path_to(goal, &map).expect("a path");
+        let smoothed = map.smooth_path(&path);
+
+        assert!(smoothed.len() < path.len());
+        assert_eq!(smoothed.first(), Some(&start));
+        assert_eq!(smoothed.last(), Some(&goal));
+        for leg in smoothed.windows(2) {
+            assert_eq!(leg[0].obstacle_between(leg[1], &map), None);
+        }
+    }
+
+    #[test]
+    fn smooth_path_open_sky() {
+        let map = open_map(8, 8);
+        let path: Vec<MapPosition> = (0..8).map(|x| MapPosition::new(x, x / 2)).collect();
+        assert_eq!(
+            map.smooth_path(&path),
+            vec![MapPosition::new(0, 0), MapPosition::new(7, 3)]
+        );
+    }
+
+    #[test]
+    fn path_keeps_clear_of_obstacles() {
+        let map = Map::parse(
+            "0 0 0 0 0 0 0 0 0\n\
+             0 0 0 0 0 0 0 0 0\n\
+             0 0 0 0 0 0 0 0 0\n\
+             0 0 0 0 1 0 0 0 0\n\
+             0 0 0 0 0 0 0 0 0\n\
+             0 0 0 0 0 0 0 0 0\n\
+             0 0 0 0 0 0 0 0 0",
+        )
+        .expect("valid map");
+        let start = MapPosition::new(1, 3);
+        let goal = MapPosition::new(7, 3);
+
+        let (hugging, _) = start.path_to(goal, &map).expect("a path");
+        assert!(hugging.iter().any(|p| !p.is_pathable(&map, 1)));
+
+        let (clear, _) = start.path_with_clearance(goal, &map, 1).expect("a path");
+        assert!(clear.iter().all(|p| p.is_pathable(&map, 1)));
+    }
+
+    #[test]
+    fn path_squeezes_through_when_it_must() {
+        // The only way through is right next to the walls
+        let map = Map::parse(
+            "0 0 1 0 0\n\
+             0 0 0 0 0\n\
+             0 0 1 0 0",
+        )
+        .expect("valid map");
+        let start = MapPosition::new(0, 1);
+        let goal = MapPosition::new(4, 1);
+        assert!(start.path_with_clearance(goal, &map, 1).is_some());
+    }
+
+    #[test]
+    fn path_prefers_open_sky_to_cells_to_avoid() {
+        // A zone to avoid in the middle, with open sky around the bottom
+        let map = Map::parse(
+            "0 0 0 2 0 0 0\n\
+             0 0 0 2 0 0 0\n\
+             0 0 0 2 0 0 0\n\
+             0 0 0 0 0 0 0",
+        )
+        .expect("valid map");
+        let start = MapPosition::new(0, 0);
+        let goal = MapPosition::new(6, 0);
+        assert_eq!(map.terrain(MapPosition::new(3, 0)), Terrain::Avoid);
+        assert!(!map.is_occupied(MapPosition::new(3, 0)));
+
+        let (path, _) = start.path_to(goal, &map).expect("a path");
+        assert!(path.iter().all(|&p| map.terrain(p) == Terrain::Passable));
+        for leg in map.smooth_path(&path).windows(2) {
+            assert_eq!(map.max_terrain_between(leg[0], leg[1]), 0);
+        }
+
+        // Walled off below, the only way is through
+        let map = Map::parse(
+            "0 0 0 2 0 0 0\n\
+             0 0 0 2 0 0 0\n\
+             0 0 0 2 0 0 0\n\
+             0 0 0 1 0 0 0",
+        )
+        .expect("valid map");
+        let (path, _) = start.path_to(goal, &map).expect("a path");
+        assert!(path.iter().any(|&p| map.terrain(p) == Terrain::Avoid));
+        assert_eq!(path.last(), Some(&goal));
+    }
+
+    #[test]
+    fn parse_rejects_unknown_terrain() {
+        assert!(Map::parse("0 1 2\n2 1 0").is_ok());
+        let err = Map::parse("0 1 2\n0 3 0").expect_err("invalid map");
+        assert!(err.starts_with("line 2:"), "{}", err);
+    }
+
+    #[test]
+    fn distance_world_on_builtin_map() {
+        let map = Map::builtin();
+        let origin = MapPosition::new(100, 100);
+
+        // Built-in cells are 64 world units on a side
+        assert_eq!(
+            origin.distance_world(MapPosition::new(101, 100), &map),
+            64.0
+        );
+        assert_eq!(origin.distance_world(MapPosition::new(100, 99), &map), 64.0);
+        let diagonal = origin.distance_world(MapPosition::new(101, 101), &map);
+        assert!((diagonal - 90.51).abs() < 0.01);
+        assert_eq!(origin.distance(MapPosition::new(101, 101)), 2);
+    }
+
+    #[test]
+    fn adjacent_positions_at_map_edge() {
+        let map = open_map(4, 3);
+        let corner: Vec<MapPosition> = MapPosition::new(0, 0).adjacent_positions(&map).collect();
+        assert_eq!(
+            corner,
+            vec![
+                MapPosition::new(1, 0),
+                MapPosition::new(0, 1),
+                MapPosition::new(1, 1),
+            ]
+        );
+
+        let edge: Vec<MapPosition> = MapPosition::new(3, 1).adjacent_positions(&map).collect();
+        assert_eq!(
+            edge,
+            vec![
+                MapPosition::new(2, 0),
+                MapPosition::new(3, 0),
+                MapPosition::new(2, 1),
+                MapPosition::new(2, 2),
+                MapPosition::new(3, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn adjacent_positions_skip_obstacles() {
+        let map = Map::parse(
+            "0 0 0\n\
+             0 0 1\n\
+             0 1 0",
+        )
+        .expect("valid map");
+        let around: Vec<MapPosition> = MapPosition::new(1, 1).adjacent_positions(&map).collect();
+        assert_eq!(
+            around,
+            vec![
+                MapPosition::new(0, 0),
+                MapPosition::new(1, 0),
+                MapPosition::new(2, 0),
+                MapPosition::new(0, 1),
+                MapPosition::new(0, 2),
+                MapPosition::new(2, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn position_round_trip_within_a_cell() {
+        let map = Map::builtin();
+        let positions = [
+            (0.0, 0.0),
+            (-1.0, -1.0),
+            (1234.5, -4321.0),
+            (-16000.0, 8000.0),
+            (16383.0, -8191.0),
+        ];
+        for &(x, y) in positions.iter() {
+            let cell = map.map_position(Position::new(x, y));
+            let center = map.world_position(cell);
+            // The center of the cell is within half a cell of the position
+            assert!(
+                (center.x.inner() - x).abs() <= 32.0,
+                "x {} went to {:?}",
+                x,
+                cell
+            );
+            assert!(
+                (center.y.inner() - y).abs() <= 32.0,
+                "y {} went to {:?}",
+                y,
+                cell
+            );
+        }
+    }
+
+    #[test]
+    fn positions_off_the_world_clamp_to_the_edge() {
+        let map = Map::builtin();
+        assert_eq!(
+            map.map_position(Position::new(-BOUNDARY_X, -BOUNDARY_Y)),
+            MapPosition::new(0, 0)
+        );
+        assert_eq!(
+            map.map_position(Position::new(-20000.0, -10000.0)),
+            MapPosition::new(0, 0)
+        );
+        assert_eq!(
+            map.map_position(Position::new(BOUNDARY_X, BOUNDARY_Y)),
+            MapPosition::new(511, 255)
+        );
+        assert_eq!(
+            map.map_position(Position::new(20000.0, 10000.0)),
+            MapPosition::new(511, 255)
+        );
+        // Just past the left edge is the first column, not one mirrored
+        // back into the map
+        assert_eq!(
+            map.map_position(Position::new(-BOUNDARY_X - 100.0, 0.0)).x,
+            0
+        );
+    }
+
+    #[test]
+    fn edges_are_occupied() {
+        let map = open_map(4, 3);
+        for &(x, y) in [(-1, 0), (0, -1), (4, 0), (0, 3), (-1, -1), (4, 3)].iter() {
+            assert!(map.is_occupied(MapPosition::new(x, y)), "({}, {})", x, y);
+            assert!(!map.contains(MapPosition::new(x, y)));
+        }
+        for &(x, y) in [(0, 0), (3, 0), (0, 2), (3, 2)].iter() {
+            assert!(!map.is_occupied(MapPosition::new(x, y)), "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn far_off_positions_are_not_mirrored() {
+        let map = Map::builtin();
+        // Reflecting across the boundary would land 3000 units, or 46
+        // cells, inside the map
+        let left = map.map_position(Position::new(-BOUNDARY_X - 3000.0, 100.0));
+        assert_eq!(left.x, 0);
+        assert_ne!(
+            left,
+            map.map_position(Position::new(-BOUNDARY_X + 3000.0, 100.0))
+        );
+
+        let top = map.map_position(Position::new(100.0, -BOUNDARY_Y - 3000.0));
+        assert_eq!(top.y, 0);
+        assert_eq!(top.x, map.map_position(Position::new(100.0, 0.0)).x);
+    }
+
+    #[test]
+    fn path_goes_around_enemies() {
+        let map = open_map(16, 12);
+        let start = MapPosition::new(0, 6);
+        let goal = MapPosition::new(15, 6);
+        let enemy = MapPosition::new(8, 6);
+
+        let (direct, _) = start.path_to(goal, &map).expect("a path");
+        assert!(direct.contains(&enemy));
+
+        let danger = DangerMap::new(&map, vec![map.world_position(enemy)]);
+        assert!(danger.cost(enemy) > danger.cost(MapPosition::new(8, 8)));
+        assert_eq!(danger.cost(MapPosition::new(8, 10)), 0);
+        let (cautious, _) = start.path_avoiding(goal, &map, 0, &danger).expect("a path");
+        assert!(!cautious.contains(&enemy));
+        assert_eq!(cautious.last(), Some(&goal));
+
+        // Smoothing doesn't cut the corner back through the enemy
+        let smoothed = map.smooth_path_avoiding(&cautious, &danger);
+        assert!(smoothed.len() < cautious.len());
+        for leg in smoothed.windows(2) {
+            assert!(danger.max_between(leg[0], leg[1]) < danger.cost(enemy));
+        }
     }
 }
@@ -3,21 +3,219 @@
 //! Once spawned, a wingman can only be shutdown by setting
 //! an atomic flag that's provided on startup.
 //!
-//! Right now, the wingman simply follows and shoots a player.
-//! It's really dumb...
+//! Right now, the wingman simply follows and shoots a player,
+//! respawning when it's shot down. It's really dumb... A defending
+//! wingman instead stays near its player and shoots enemies that
+//! come close, and a protecting wingman goes after whichever enemy
+//! is the biggest threat to its player.
+//!
+//! Kills and other notable events are reported back through a `Reporter`.
+//! What a wingman does with its player is up to the `Behavior` picked for
+//! its orders, in the `behavior` module.
+
+mod behavior;
 
-use airmash_client::{Client, ClientBase};
+use airmash_client::{ClientBase, ClientEvent};
 use airmash_protocol as protocol;
 
-use pathfinding::prelude::astar;
-use std::sync::{atomic, Arc};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{atomic, mpsc, Arc, Mutex};
 use std::time;
 use url::Url;
 
-use crate::types::MapPosition;
+use self::behavior::{Behavior, Contact, WingContext};
+use crate::commands;
+use crate::net;
+use crate::types::{DangerMap, Map, MapPosition};
 
+/// Wingmen open fire on enemies closer than this, unless their request
+/// asked for another range
 const MIN_FIRE_DIST: f32 = 500.0;
 
+/// Only fire when our heading is within this angle (in radians) of the
+/// target, so we don't waste energy on shots that can't hit
+const MAX_FIRE_ANGLE: f32 = std::f32::consts::PI / 9.0;
+
+/// Wingmen swing the heading they steer for at most this many degrees a
+/// second, about as fast as the nimblest plane turns, so a goal that hops
+/// about from tick to tick doesn't leave their nose twitching
+const MAX_TURN_RATE: f32 = 240.0;
+
+/// A wingman that runs too low on energy to shoot holds fire until it's
+/// back up to this much, out of a full tank of 1, or enough for a shot if
+/// that's more, so it fires in bursts instead of dry-firing
+const MIN_FIRE_ENERGY: f32 = 0.5;
+
+/// Teammates within this distance of the line between a wingman
+/// and its target are considered to be in the firing line
+const FIRING_LINE_WIDTH: f32 = 100.0;
+
+/// How long to sit idle after dying before asking to respawn
+const RESPAWN_DELAY: time::Duration = time::Duration::from_secs(2);
+
+/// A wingman that can't see its target right after logging in looks again
+/// this many times, this far apart, while the player list catches up
+const TARGET_LOOKUP_ATTEMPTS: u32 = 10;
+const TARGET_LOOKUP_WAIT: time::Duration = time::Duration::from_millis(300);
+
+/// A wingman that dies more than `MAX_DEATHS` times within
+/// `DEATH_WINDOW` gives up
+const MAX_DEATHS: usize = 5;
+const DEATH_WINDOW: time::Duration = time::Duration::from_secs(60);
+
+/// A wingman whose health drops below `RETREAT_HEALTH` breaks off
+/// for up to `RETREAT_TIME`, or until its health is back above
+/// `RECOVERED_HEALTH`. One that runs out of time without recovering
+/// fights on for at least `RETREAT_COOLDOWN` before retreating again.
+const RETREAT_HEALTH: f32 = 0.3;
+const RECOVERED_HEALTH: f32 = 0.8;
+const RETREAT_TIME: time::Duration = time::Duration::from_secs(4);
+const RETREAT_COOLDOWN: time::Duration = time::Duration::from_secs(6);
+
+/// Predator wingmen boost while their target is farther away than this
+const BOOST_DIST: f32 = 1200.0;
+
+/// Goliath wingmen repel enemies and missiles within this distance
+const REPEL_RANGE: f32 = 225.0;
+
+/// Wingmen only pathfind around obstacles closer than this; farther
+/// obstacles are fine to head towards
+const PATHFINDING_DIST: f32 = 1024.0;
+
+/// A wingman that can't find a path to its target for this long gives up
+const UNREACHABLE_TIME: time::Duration = time::Duration::from_secs(10);
+
+/// A wingman that hasn't moved `STUCK_DIST` world units in `STUCK_TIME`
+/// while away from its slot is stuck, probably wedged against a mountain
+const STUCK_DIST: f32 = 50.0;
+const STUCK_TIME: time::Duration = time::Duration::from_secs(3);
+/// How long a stuck wingman flies for open sky before resuming the chase
+const UNSTICK_TIME: time::Duration = time::Duration::from_millis(1500);
+/// A wingman that gets stuck this many times without getting anywhere in
+/// between gives up
+const MAX_UNSTICKS: u32 = 3;
+/// A stuck wingman pushes away from obstacles within this many map cells
+const ESCAPE_PROBE: isize = 2;
+/// A stuck wingman picks the clearest of this many evenly spaced headings,
+/// aiming for a point this far away in world units
+const ESCAPE_HEADINGS: usize = 16;
+const ESCAPE_DIST: f32 = 400.0;
+
+/// Cautious wingmen rebuild their map of nearby enemies this often,
+/// rather than every tick
+const DANGER_UPDATE_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+/// A target that stays dead for this long has probably started spectating,
+/// so the wingman gives up on it. Respawning takes much less time.
+const TARGET_DEAD_GRACE: time::Duration = time::Duration::from_secs(15);
+
+/// Seconds a wingman waits for a target that left the game to rejoin,
+/// unless configured. Players on flaky connections are often back within
+/// a few seconds.
+pub const DEFAULT_TARGET_GRACE: u64 = 20;
+
+/// Wingmen farther than this from their target, in world units, break off
+/// the chase once they've been that far for `LEASH_TIME` without gaining
+/// on it, unless configured. It's half the width of the map, and all of
+/// its height.
+pub const DEFAULT_MAX_CHASE_DISTANCE: f32 = 16000.0;
+const LEASH_TIME: time::Duration = time::Duration::from_secs(5);
+/// A wingman this much closer than when it got too far is catching up, so
+/// it starts the wait over
+const LEASH_PROGRESS: f32 = 500.0;
+
+/// Defending wingmen engage enemies within this distance of the player
+/// they protect
+const DEFEND_RANGE: f32 = 600.0;
+
+/// Patrolling wingmen engage enemies within this distance of their point
+const PATROL_RANGE: f32 = 800.0;
+/// Patrolling wingmen circle their point at this radius, this many radians
+/// a second, so they're a moving target while they wait
+const PATROL_RADIUS: f32 = 300.0;
+const PATROL_ORBIT_SPEED: f32 = 0.5;
+
+/// Protecting wingmen hunt enemies within this distance of the player
+/// they protect, unless configured
+pub const DEFAULT_THREAT_RADIUS: f32 = 1000.0;
+/// Protecting wingmen pick the biggest threat again this often, so they
+/// don't chase one enemy while another closes in
+const THREAT_RESELECT_TIME: time::Duration = time::Duration::from_secs(3);
+/// Enemies in the same band of this width, by distance from the protected
+/// player, are equally near, and the one approaching faster is the bigger
+/// threat
+const THREAT_TIE_DIST: f32 = 100.0;
+
+/// Dodging wingmen evade missiles within this distance that are flying
+/// within `DODGE_CONE` radians of straight at them
+const DODGE_RANGE: f32 = 500.0;
+const DODGE_CONE: f32 = std::f32::consts::PI / 12.0;
+/// How far to the side a dodging wingman aims to get out of a missile's path
+const DODGE_DIST: f32 = 400.0;
+
+/// The server cuts names off at this many characters
+const MAX_NAME_LEN: usize = 20;
+/// Room kept at the end of a wingman's name for its number, like `-12`
+const NAME_NUMBER_LEN: usize = 4;
+
+/// Wingmen steer away from other planes closer than this
+const SEPARATION_DIST: f32 = 150.0;
+/// How far a wingman shifts the point it flies towards to get clear of a
+/// plane right on top of it
+const SEPARATION_PUSH: f32 = 300.0;
+
+/// Codes for each upgrade in the upgrade command
+const UPGRADE_SPEED: u8 = 1;
+const UPGRADE_DEFENSE: u8 = 2;
+const UPGRADE_ENERGY: u8 = 3;
+const UPGRADE_MISSILE: u8 = 4;
+/// Upgrades a wingman spends its points on, most wanted first
+const UPGRADE_PRIORITY: [u8; 4] = [
+    UPGRADE_MISSILE,
+    UPGRADE_SPEED,
+    UPGRADE_DEFENSE,
+    UPGRADE_ENERGY,
+];
+/// The server won't upgrade anything past this level
+const MAX_UPGRADE_LEVEL: u8 = 5;
+
+/// Wingmen steer for where their target will be this far ahead, so
+/// they cut corners instead of trailing behind
+const INTERCEPT_LEAD: time::Duration = time::Duration::from_millis(600);
+/// A target moving slower than this, in world units per second, is
+/// steered for where it is
+const MIN_LEAD_SPEED: f32 = 40.0;
+/// A target that seems to move faster than this, in world units per
+/// second, has respawned or teleported, so its old position is forgotten
+const MAX_TARGET_SPEED: f32 = 2000.0;
+/// Weight of the newest measurement in the smoothed velocity estimate
+const VELOCITY_SMOOTHING: f32 = 0.5;
+
+/// A follower whose leader has been out of the game this long takes over
+/// the lead, unless another follower already has
+const LEADER_PROMOTE_TIME: time::Duration = time::Duration::from_secs(5);
+
+/// Wingmen fly to evenly spaced slots on a circle of this radius
+/// around their target, so they don't stack on one spot
+const FORMATION_RADIUS: f32 = 200.0;
+/// Number of slots on the formation circle
+const FORMATION_SLOTS: usize = 8;
+
+/// Idle wingmen only go after powerups this close, so they're never far
+/// off when their target is back
+const POWERUP_RANGE: f32 = 2000.0;
+/// A powerup this close has been picked up, by us or someone else, even
+/// if the server hasn't said so
+const POWERUP_PICKUP_DIST: f32 = 50.0;
+
+/// Milliseconds between wingmen logging in, unless configured
+pub const DEFAULT_SPAWN_STAGGER: u64 = 300;
+
+/// Fewest milliseconds between steering updates, unless configured
+pub const DEFAULT_TICK_MIN: u64 = 10;
+/// Most milliseconds between steering updates, unless configured
+pub const DEFAULT_TICK_MAX: u64 = 1000;
+
 /// Flag used to shutdown a wingman's event loop
 #[derive(Clone)]
 pub struct Flag {
@@ -33,7 +231,11 @@ impl Default for Flag {
 }
 
 impl Flag {
-    fn read(&self) -> bool {
+    /// True once the wingman should shut down, or has shut down
+    ///
+    /// A wingman drops its copy of the flag as it exits, so this
+    /// also goes high when a wingman gives up on its own.
+    pub fn read(&self) -> bool {
         self.inner.load(atomic::Ordering::SeqCst)
     }
 }
@@ -44,139 +246,2071 @@ impl Drop for Flag {
     }
 }
 
+/// How long a wingman waits between steering updates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tick {
+    /// Twice the round trip time to the server, kept within `min` and
+    /// `max`, so updates don't outpace what the server sends us
+    Ping {
+        min: time::Duration,
+        max: time::Duration,
+    },
+    /// The same interval regardless of ping
+    Fixed(time::Duration),
+}
+
+impl Default for Tick {
+    fn default() -> Self {
+        Tick::Ping {
+            min: time::Duration::from_millis(DEFAULT_TICK_MIN),
+            max: time::Duration::from_millis(DEFAULT_TICK_MAX),
+        }
+    }
+}
+
+impl Tick {
+    /// Scale with ping between `min` and `max` milliseconds, or an error if
+    /// `min` is above `max`
+    pub fn ping(min: u64, max: u64) -> Result<Self, String> {
+        if min <= max {
+            Ok(Tick::Ping {
+                min: time::Duration::from_millis(min),
+                max: time::Duration::from_millis(max),
+            })
+        } else {
+            Err(format!(
+                "invalid tick range: the minimum of {} ms is above the maximum of {} ms",
+                min, max
+            ))
+        }
+    }
+
+    /// Wait `interval` milliseconds regardless of ping, or an error if it's
+    /// 0, which would steer as fast as the wingman can
+    pub fn fixed(interval: u64) -> Result<Self, String> {
+        if interval > 0 {
+            Ok(Tick::Fixed(time::Duration::from_millis(interval)))
+        } else {
+            Err("invalid fixed tick: must be at least 1 ms".to_owned())
+        }
+    }
+
+    /// The time to wait with a round trip time of `ping` milliseconds
+    pub fn interval(self, ping: u16) -> time::Duration {
+        match self {
+            Tick::Ping { min, max } => time::Duration::from_millis(u64::from(ping) * 2)
+                .max(min)
+                .min(max),
+            Tick::Fixed(interval) => interval,
+        }
+    }
+}
+
+/// Spaces out wingman logins across every server, so a burst of requests
+/// doesn't open all its connections at once
+///
+/// Clones share the same schedule. Each wingman reserves the next free
+/// login time, `stagger` after the one before.
+#[derive(Debug, Clone, Default)]
+pub struct SpawnSchedule {
+    /// When the next wingman may log in, if anyone's waiting
+    next: Arc<Mutex<Option<time::Instant>>>,
+    /// Time between logins
+    stagger: time::Duration,
+}
+
+impl SpawnSchedule {
+    /// Create a schedule that lets a wingman log in every `stagger`
+    pub fn new(stagger: time::Duration) -> Self {
+        SpawnSchedule {
+            next: Arc::new(Mutex::new(None)),
+            stagger,
+        }
+    }
+
+    /// Reserve the next login time, and return how long from `now` that is
+    fn reserve(&self, now: time::Instant) -> time::Duration {
+        let mut next = match self.next.lock() {
+            Ok(next) => next,
+            Err(_) => return time::Duration::from_secs(0),
+        };
+        let start = next.map_or(now, |next| next.max(now));
+        *next = Some(start + self.stagger);
+        start - now
+    }
+}
+
+/// The wingman that the rest of a player's wing flies in formation with,
+/// when wings follow a leader
+///
+/// Clones share the same lead. Ground control appoints the first leader,
+/// and a follower takes over if the leader leaves the game. With nobody
+/// appointed, every wingman pursues the target on its own.
+#[derive(Debug, Clone, Default)]
+pub struct Lead {
+    /// Login name of the leader, if there is one
+    leader: Arc<Mutex<Option<String>>>,
+}
+
+impl Lead {
+    /// Make the wingman logging in as `name` the leader
+    pub fn appoint(&self, name: String) {
+        if let Ok(mut leader) = self.leader.lock() {
+            *leader = Some(name);
+        }
+    }
+
+    /// Name of the current leader, if there is one
+    fn leader(&self) -> Option<String> {
+        self.leader.lock().ok().and_then(|leader| leader.clone())
+    }
+
+    /// Take over the lead from `gone` as `name`, returning `false` if
+    /// another wingman already has
+    fn promote(&self, gone: &str, name: &str) -> bool {
+        let mut leader = match self.leader.lock() {
+            Ok(leader) => leader,
+            Err(_) => return false,
+        };
+        if leader.as_ref().map(String::as_str) != Some(gone) {
+            return false;
+        }
+        *leader = Some(name.to_owned());
+        true
+    }
+}
+
+/// Something that happened to a wingman
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    /// The wingman connected and logged in
+    Connected,
+    /// The wingman couldn't connect or log in, and gave up
+    Failed,
+    /// The wingman shot down the named player
+    Killed { victim: String },
+    /// The wingman couldn't find a way to its target, and gave up
+    Unreachable,
+    /// The wingman's target left the game
+    TargetLost,
+    /// The wingman logged in but never saw its target in the game
+    TargetNotFound,
+}
+
+/// An event reported by a wingman
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// The player who requested the wingman
+    pub owner: protocol::Player,
+    /// Name of the wingman's target
+    pub target: String,
+    /// What happened
+    pub kind: EventKind,
+}
+
+/// Reports a wingman's events to the server that spawned it
+///
+/// The reporter is dropped when the wingman exits, so the channel
+/// closes once the server's own sender and every wingman are gone.
+pub struct Reporter {
+    owner: protocol::Player,
+    target: String,
+    sender: mpsc::Sender<Event>,
+}
+
+impl Reporter {
+    /// Create a reporter for a wingman requested by `owner` to attack `target`
+    pub fn new(owner: protocol::Player, target: String, sender: mpsc::Sender<Event>) -> Self {
+        Reporter {
+            owner,
+            target,
+            sender,
+        }
+    }
+
+    /// Report later events against a new target
+    fn retarget(&mut self, target: String) {
+        self.target = target;
+    }
+
+    fn report(&self, kind: EventKind) {
+        let event = Event {
+            owner: self.owner,
+            target: self.target.clone(),
+            kind,
+        };
+        if self.sender.send(event).is_err() {
+            log::debug!("wingman event dropped; server has gone away");
+        }
+    }
+}
+
+/// A change of orders for a wingman in the air, sent by ground control
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Order {
+    /// Chase the named player instead
+    Retarget(String),
+    /// Keep the same target, but treat them according to the new behavior
+    Behave(commands::Behavior),
+}
+
+/// The players an attacking wingman takes on in turn, moving on to the next
+/// whenever its target dies, leaves or gets away
+///
+/// A rotation of one player or none never moves on.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rotation {
+    /// Names of the players, in the order they were asked for
+    names: Vec<String>,
+}
+
+impl Rotation {
+    /// Create a rotation through the named players
+    pub fn new(names: Vec<String>) -> Self {
+        Rotation { names }
+    }
+
+    /// The first player after `current` in the rotation who's `available`,
+    /// wrapping around to the start
+    ///
+    /// `current` itself is never picked, so there's no next player when
+    /// nobody else is available.
+    fn after<F>(&self, current: &str, available: F) -> Option<&str>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let start = self
+            .names
+            .iter()
+            .position(|name| name == current)
+            .map_or(0, |idx| idx + 1);
+        let len = self.names.len();
+        (0..len)
+            .map(|offset| &self.names[(start + offset) % len])
+            .map(String::as_str)
+            .find(|&name| name != current && available(name))
+    }
+}
+
+/// Options that control how spawned wingmen behave
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// True to fire regardless of who's in the way, else false to
+    /// hold fire when the target or a player in the firing line is
+    /// a teammate
+    pub friendly_fire: bool,
+    /// Collision map used for pathfinding
+    pub map: Arc<Map>,
+    /// True to break off and retreat when badly damaged, else false
+    /// to keep attacking no matter what
+    pub retreat: bool,
+    /// Pathfinding keeps this many map cells away from obstacles
+    /// where it can
+    pub clearance: isize,
+    /// Settings to log in with, shared with ground control
+    pub login: net::LoginOptions,
+    /// True to spend upgrade points as they're earned, else false
+    pub upgrades: bool,
+    /// True to swerve out of the way of incoming missiles, else false
+    /// to keep pressing the attack
+    pub dodge: bool,
+    /// True to path around enemy planes on the way to the target, else
+    /// false to fly straight through them
+    pub cautious: bool,
+    /// How long to wait between steering updates
+    pub tick: Tick,
+    /// Protecting wingmen hunt enemies within this distance of their player
+    pub threat_radius: f32,
+    /// When wingmen may log in, shared by every server
+    pub spawn_schedule: SpawnSchedule,
+    /// Wingmen give up on targets farther away than this, or chase them
+    /// anywhere if 0
+    pub max_chase_distance: f32,
+    /// How long a wingman waits for a target that left to rejoin, and
+    /// ground control keeps the wings of a player who left
+    pub target_grace: time::Duration,
+    /// How long ground control and its wingmen wait to hear from the server
+    /// before taking the connection to be dead, or forever if 0
+    pub stale_after: time::Duration,
+    /// Wingmen log in with names starting with this, followed by their
+    /// target's name and number
+    pub name_prefix: String,
+    /// True to have the first of a player's wingmen lead the others in
+    /// formation, else false for every wingman to find its own way
+    pub leader_follow: bool,
+    /// True to go after nearby upgrades and powerups while waiting for the
+    /// target, else false to hold position
+    pub grab_powerups: bool,
+}
+
+/// Energy a shot from `plane` costs, out of a full tank of 1
+fn shot_energy(plane: protocol::PlaneType) -> f32 {
+    match plane {
+        protocol::PlaneType::Predator => 0.6,
+        protocol::PlaneType::Goliath => 0.9,
+        protocol::PlaneType::Mohawk => 0.3,
+        protocol::PlaneType::Tornado => 0.5,
+        protocol::PlaneType::Prowler => 0.75,
+    }
+}
+
+/// Whether a wingman has the energy to keep shooting
+///
+/// A wingman shoots until it can't afford another shot, then holds fire
+/// until it's back up to `MIN_FIRE_ENERGY`.
+#[derive(Default)]
+struct Trigger {
+    /// True while we wait for energy to build back up
+    empty: bool,
+}
+
+impl Trigger {
+    /// True if a shot costing `cost` is worth firing with `energy` left
+    fn ready(&mut self, energy: f32, cost: f32) -> bool {
+        if self.empty {
+            self.empty = energy < cost.max(MIN_FIRE_ENERGY);
+        } else {
+            self.empty = energy < cost;
+        }
+        !self.empty
+    }
+}
+
+/// Recent deaths of a wingman
+///
+/// Used to stop a wingman that keeps getting shot down from
+/// respawning forever.
+#[derive(Default)]
+struct Deaths {
+    times: VecDeque<time::Instant>,
+}
+
+impl Deaths {
+    /// Record a death, returning `false` if the wingman has died
+    /// too many times recently and should give up
+    fn record(&mut self, now: time::Instant) -> bool {
+        while self
+            .times
+            .front()
+            .map(|&time| now - time > DEATH_WINDOW)
+            .unwrap_or(false)
+        {
+            self.times.pop_front();
+        }
+        self.times.push_back(now);
+        self.times.len() <= MAX_DEATHS
+    }
+}
+
+/// When a wingman breaks off to recover its health
+#[derive(Default)]
+struct Retreat {
+    /// When we started retreating, if we're retreating
+    since: Option<time::Instant>,
+    /// When we may retreat again, after a retreat that didn't recover
+    resting_until: Option<time::Instant>,
+}
+
+impl Retreat {
+    /// Record our `health` at `now`, returning true if we should be
+    /// retreating
+    fn update(&mut self, health: f32, now: time::Instant) -> bool {
+        match self.since {
+            Some(start) if now - start < RETREAT_TIME && health < RECOVERED_HEALTH => return true,
+            // Ran out of time without recovering; going straight back to
+            // retreating would keep us out of the fight for good
+            Some(start) if now - start >= RETREAT_TIME => {
+                self.resting_until = Some(now + RETREAT_COOLDOWN)
+            }
+            _ => (),
+        }
+        self.since = None;
+
+        let rested = self.resting_until.map(|until| now >= until).unwrap_or(true);
+        if health < RETREAT_HEALTH && rested {
+            log::debug!("wingman retreating with health {}", health);
+            self.since = Some(now);
+        }
+        self.since.is_some()
+    }
+
+    /// Stop retreating, like after respawning
+    fn reset(&mut self) {
+        *self = Retreat::default();
+    }
+}
+
+/// A smoothed estimate of a player's velocity, from their position each
+/// time we look
+#[derive(Default)]
+struct VelocityEstimate {
+    /// Where the player was when we last looked, and when that was
+    last: Option<(protocol::Position, time::Instant)>,
+    /// Velocity in world units per second
+    velocity: (f32, f32),
+}
+
+impl VelocityEstimate {
+    /// Record the player's position at `now`
+    ///
+    /// Positions only change when the server sends an update, so an
+    /// unchanged position is skipped rather than taken to mean the player
+    /// stopped.
+    fn update(&mut self, pos: protocol::Position, now: time::Instant) {
+        if let Some((last_pos, last_time)) = self.last {
+            if last_pos.x.inner() == pos.x.inner() && last_pos.y.inner() == pos.y.inner() {
+                return;
+            }
+            let elapsed = now - last_time;
+            let secs = elapsed.as_secs() as f32 + elapsed.subsec_millis() as f32 / 1000.0;
+            if secs <= 0.0 {
+                return;
+            }
+            let vx = (pos.x.inner() - last_pos.x.inner()) / secs;
+            let vy = (pos.y.inner() - last_pos.y.inner()) / secs;
+            if (vx * vx + vy * vy).sqrt() > MAX_TARGET_SPEED {
+                self.velocity = (0.0, 0.0);
+            } else {
+                let (old_x, old_y) = self.velocity;
+                self.velocity = (
+                    old_x + VELOCITY_SMOOTHING * (vx - old_x),
+                    old_y + VELOCITY_SMOOTHING * (vy - old_y),
+                );
+            }
+        }
+        self.last = Some((pos, now));
+    }
+
+    /// Forget everything, like when the player dies
+    fn reset(&mut self) {
+        *self = VelocityEstimate::default();
+    }
+
+    /// Where the player at `pos` will be `ahead` from now, if they keep going;
+    /// just `pos` if they're barely moving
+    fn project(&self, pos: protocol::Position, ahead: time::Duration) -> protocol::Position {
+        let (vx, vy) = self.velocity;
+        if (vx * vx + vy * vy).sqrt() < MIN_LEAD_SPEED {
+            return pos;
+        }
+        let secs = ahead.as_secs() as f32 + ahead.subsec_millis() as f32 / 1000.0;
+        protocol::Position::new(pos.x.inner() + vx * secs, pos.y.inner() + vy * secs)
+    }
+}
+
+/// Where a wingman has been lately, to notice when it's stuck
+#[derive(Default)]
+struct StuckDetector {
+    /// Recent positions, oldest first, and when we were there
+    samples: VecDeque<(protocol::Position, time::Instant)>,
+}
+
+impl StuckDetector {
+    /// Record our position at `now`
+    fn record(&mut self, pos: protocol::Position, now: time::Instant) {
+        self.samples.push_back((pos, now));
+        // Keep one sample from at least `STUCK_TIME` ago, so we know how
+        // far we've come over the whole window
+        while self.samples.len() > 1 && now - self.samples[1].1 >= STUCK_TIME {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Forget where we've been, like after respawning
+    fn reset(&mut self) {
+        self.samples.clear();
+    }
+
+    /// True if we've been watching for at least `STUCK_TIME`
+    fn is_full(&self, now: time::Instant) -> bool {
+        self.samples
+            .front()
+            .map_or(false, |&(_, at)| now - at >= STUCK_TIME)
+    }
+
+    /// True if we've stayed within `STUCK_DIST` of where we are now for
+    /// the last `STUCK_TIME`
+    fn is_stuck(&self, now: time::Instant) -> bool {
+        let latest = match self.samples.back() {
+            Some(&(pos, _)) => pos,
+            None => return false,
+        };
+        self.is_full(now)
+            && self
+                .samples
+                .iter()
+                .all(|&(pos, _)| (pos - latest).length().inner() < STUCK_DIST)
+    }
+}
+
+/// How long a wingman has been too far from its target without catching
+/// up, to break off a chase across the map
+#[derive(Default)]
+struct Leash {
+    /// When we got too far away, and how far that was, if we are
+    strained_since: Option<(time::Instant, f32)>,
+}
+
+impl Leash {
+    /// Record our distance `dist` from the target at `now`, returning true if
+    /// we've been farther than `max` for `LEASH_TIME` without gaining on the
+    /// target and should give up
+    ///
+    /// A `max` of 0 never gives up.
+    fn pull(&mut self, dist: f32, max: f32, now: time::Instant) -> bool {
+        if max <= 0.0 || dist <= max {
+            self.strained_since = None;
+            return false;
+        }
+        match self.strained_since {
+            Some((_, from)) if dist > from - LEASH_PROGRESS => (),
+            // Just got too far, or catching up: start the wait over
+            _ => self.strained_since = Some((now, dist)),
+        }
+        let (since, _) = self.strained_since.unwrap();
+        now - since >= LEASH_TIME
+    }
+
+    /// Forget how long we've been too far, like after respawning
+    fn reset(&mut self) {
+        self.strained_since = None;
+    }
+}
+
+/// The position of formation `slot` around `target`
+///
+/// Slots alternate sides of the circle so that the first few wingmen
+/// are spread out, even when there are only two or three of them.
+fn formation_position(target: protocol::Position, slot: usize) -> protocol::Position {
+    let step = std::f32::consts::PI * 2.0 / FORMATION_SLOTS as f32;
+    let index = slot % FORMATION_SLOTS;
+    // 0, 4, 2, 6, 1, 5, 3, 7 for eight slots
+    let spread =
+        (index % 2) * FORMATION_SLOTS / 2 + (index / 2 % 2) * FORMATION_SLOTS / 4 + index / 4;
+    let angle = spread as f32 * step;
+    protocol::Position::new(
+        target.x.inner() + FORMATION_RADIUS * angle.cos(),
+        target.y.inner() + FORMATION_RADIUS * angle.sin(),
+    )
+}
+
+/// Where the wingman in formation `slot` should be, `orbiting` into its
+/// patrol around `point`
+///
+/// The wingmen keep their formation, spread out to `PATROL_RADIUS`, and
+/// turn it slowly around the point.
+fn patrol_position(
+    point: protocol::Position,
+    slot: usize,
+    orbiting: time::Duration,
+) -> protocol::Position {
+    let slot_pos = formation_position(protocol::Position::new(0.0, 0.0), slot);
+    let (x, y) = (
+        slot_pos.x.inner() * PATROL_RADIUS / FORMATION_RADIUS,
+        slot_pos.y.inner() * PATROL_RADIUS / FORMATION_RADIUS,
+    );
+    let secs = orbiting.as_secs() as f32 + orbiting.subsec_nanos() as f32 * 1e-9;
+    let angle = secs * PATROL_ORBIT_SPEED;
+    let (sin, cos) = angle.sin_cos();
+    protocol::Position::new(
+        point.x.inner() + x * cos - y * sin,
+        point.y.inner() + x * sin + y * cos,
+    )
+}
+
+/// The start of the login name of every wingman assigned to `target`
+///
+/// The stem is cut short if needed, leaving room for the wingman's number
+/// within the server's name length limit.
+fn wing_name_stem(prefix: &str, target: &str) -> String {
+    format!("{}{}", prefix, target)
+        .chars()
+        .take(MAX_NAME_LEN - NAME_NUMBER_LEN)
+        .collect()
+}
+
+/// The login name of wingman `number` assigned to `target`, like
+/// `WING-target-1` with the prefix `WING-`
+pub fn wing_name(prefix: &str, target: &str, number: usize) -> String {
+    format!("{}-{}", wing_name_stem(prefix, target), number)
+}
+
+/// The player to move on to from `target`, if we're `attacking` and anyone
+/// else in the rotation is alive in the game
+fn next_in_rotation(
+    rotation: &Rotation,
+    target: &str,
+    attacking: bool,
+    client: &ClientBase,
+) -> Option<String> {
+    if !attacking {
+        return None;
+    }
+    let alive = |name: &str| {
+        client
+            .world
+            .names
+            .get(name)
+            .and_then(|id| client.world.players.get(id))
+            .map_or(false, |player| {
+                player.status == protocol::PlayerStatus::Alive
+            })
+    };
+    rotation.after(target, alive).map(str::to_owned)
+}
+
+/// A snapshot of ourselves and everyone in the game, for our `Behavior`
+fn contacts(client: &ClientBase) -> (Contact, Vec<Contact>) {
+    let me = client.world.get_me();
+    let me = Contact {
+        id: me.id,
+        name: me.name.clone(),
+        team: me.team,
+        plane: me.plane,
+        pos: me.pos,
+        alive: me.status == protocol::PlayerStatus::Alive,
+    };
+    let players = client
+        .world
+        .players
+        .iter()
+        .map(|(&id, player)| Contact {
+            id,
+            name: player.name.clone(),
+            team: player.team,
+            plane: player.plane,
+            pos: player.pos,
+            alive: player.status == protocol::PlayerStatus::Alive,
+        })
+        .collect();
+    (me, players)
+}
+
+/// Where the live enemies we can see are, other than `target`, which we're
+/// flying to anyway
+fn enemy_positions(client: &ClientBase, target: u16) -> Vec<protocol::Position> {
+    let me = client.world.get_me();
+    client
+        .world
+        .players
+        .iter()
+        .filter(|&(&id, player)| {
+            id != target
+                && id != me.id
+                && player.team != me.team
+                && player.status == protocol::PlayerStatus::Alive
+        })
+        .map(|(_, player)| player.pos)
+        .collect()
+}
+
+/// How far to shift our heading to keep clear of nearby planes
+///
+/// Every live plane within `SEPARATION_DIST` pushes us away, harder the
+/// closer it is. That includes our sibling wingmen, so they spread out
+/// even when the target stops moving. The `target` itself doesn't count,
+/// since we want to stay near it.
+fn separation(client: &ClientBase, target: u16) -> (f32, f32) {
+    let me = client.world.get_me();
+    client
+        .world
+        .players
+        .iter()
+        .filter(|(&id, player)| {
+            id != me.id && id != target && player.status == protocol::PlayerStatus::Alive
+        })
+        .fold((0.0, 0.0), |(x, y), (_, player)| {
+            let (dx, dy) = (
+                me.pos.x.inner() - player.pos.x.inner(),
+                me.pos.y.inner() - player.pos.y.inner(),
+            );
+            let dist = (dx * dx + dy * dy).sqrt();
+            if dist >= SEPARATION_DIST || dist == 0.0 {
+                return (x, y);
+            }
+            let push = SEPARATION_PUSH * (SEPARATION_DIST - dist) / SEPARATION_DIST / dist;
+            (x + dx * push, y + dy * push)
+        })
+}
+
+/// The next upgrade to apply, given our current upgrade `levels` for speed,
+/// defense, energy and missiles, or `None` if everything is maxed out
+fn next_upgrade(levels: [u8; 4]) -> Option<u8> {
+    UPGRADE_PRIORITY
+        .iter()
+        .cloned()
+        .find(|&code| levels[usize::from(code - 1)] < MAX_UPGRADE_LEVEL)
+}
+
+/// A point away from `target`, on the far side of `me`
+fn retreat_position(me: protocol::Position, target: protocol::Position) -> protocol::Position {
+    protocol::Position::new(
+        2.0 * me.x.inner() - target.x.inner(),
+        2.0 * me.y.inner() - target.y.inner(),
+    )
+}
+
+/// A point to fly for to get away from the obstacles we're stuck against at
+/// `me`, facing `rot`
+///
+/// Every occupied cell near us pushes us away, and we take the heading
+/// closest to that push with a clear line of flight. With nothing nearby to
+/// push us, we back out the way we came.
+fn escape_position(map: &Map, me: protocol::Position, rot: f32) -> protocol::Position {
+    let here = map.map_position(me);
+    let (mut away_x, mut away_y) = (0.0, 0.0);
+    for dy in -ESCAPE_PROBE..=ESCAPE_PROBE {
+        for dx in -ESCAPE_PROBE..=ESCAPE_PROBE {
+            if (dx, dy) != (0, 0) && map.is_occupied(MapPosition::new(here.x + dx, here.y + dy)) {
+                let len = ((dx * dx + dy * dy) as f32).sqrt();
+                away_x -= dx as f32 / len;
+                away_y -= dy as f32 / len;
+            }
+        }
+    }
+    if away_x == 0.0 && away_y == 0.0 {
+        // A rotation of zero points up the map
+        away_x = -rot.sin();
+        away_y = rot.cos();
+    }
+
+    let heading = |angle: f32| (angle.cos(), angle.sin());
+    let point = |(x, y): (f32, f32)| {
+        protocol::Position::new(
+            me.x.inner() + x * ESCAPE_DIST,
+            me.y.inner() + y * ESCAPE_DIST,
+        )
+    };
+    let clearest = (0..ESCAPE_HEADINGS)
+        .map(|i| heading(2.0 * std::f32::consts::PI * i as f32 / ESCAPE_HEADINGS as f32))
+        .filter(|&dir| {
+            map.obstacle_between(here, map.map_position(point(dir)))
+                .is_none()
+        })
+        .max_by(|a, b| {
+            let score = |(x, y): (f32, f32)| x * away_x + y * away_y;
+            score(*a)
+                .partial_cmp(&score(*b))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+    match clearest {
+        Some(dir) => point(dir),
+        None => {
+            let len = (away_x * away_x + away_y * away_y).sqrt();
+            point((away_x / len, away_y / len))
+        }
+    }
+}
+
+/// The angle in radians between our heading and the direction from `me`
+/// to `target`
+///
+/// A rotation of zero points up the map (negative y) and increases
+/// clockwise.
+fn aim_error(me: protocol::Position, rot: f32, target: protocol::Position) -> f32 {
+    let bearing = (target.x.inner() - me.x.inner()).atan2(me.y.inner() - target.y.inner());
+    let pi = std::f32::consts::PI;
+    let diff = (bearing - rot) % (2.0 * pi);
+    if diff > pi {
+        2.0 * pi - diff
+    } else if diff < -pi {
+        2.0 * pi + diff
+    } else {
+        diff.abs()
+    }
+}
+
+/// The signed angle in radians to turn from heading `from` to heading `to`
+/// the short way round, positive clockwise
+fn turn_between(from: f32, to: f32) -> f32 {
+    let pi = std::f32::consts::PI;
+    let diff = (to - from) % (2.0 * pi);
+    if diff > pi {
+        diff - 2.0 * pi
+    } else if diff < -pi {
+        diff + 2.0 * pi
+    } else {
+        diff
+    }
+}
+
+/// Smooths the heading a wingman steers for
+///
+/// Without it, a wingman points straight at a goal that can jump between
+/// map cells each tick, and its nose jitters back and forth.
+#[derive(Default)]
+struct Heading {
+    /// The heading we last steered for, in radians, and when
+    last: Option<(f32, time::Instant)>,
+}
+
+impl Heading {
+    /// A point to point at from `me` to head for `goal`
+    ///
+    /// The heading turns from the last one towards `goal` by no more than
+    /// `MAX_TURN_RATE` allows for the time since. The point is as far away
+    /// as `goal`, so it's `goal` itself once we've turned to face it.
+    fn steer(
+        &mut self,
+        me: protocol::Position,
+        goal: protocol::Position,
+        now: time::Instant,
+    ) -> protocol::Position {
+        let (dx, dy) = (goal.x.inner() - me.x.inner(), goal.y.inner() - me.y.inner());
+        let dist = (dx * dx + dy * dy).sqrt();
+        if dist == 0.0 {
+            return goal;
+        }
+
+        // A rotation of zero points up the map and increases clockwise
+        let bearing = dx.atan2(-dy);
+        let heading = match self.last {
+            Some((last, at)) if now > at => {
+                let elapsed = now - at;
+                let secs = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9;
+                let max_turn = MAX_TURN_RATE.to_radians() * secs;
+                let turn = turn_between(last, bearing);
+                last + turn.max(-max_turn).min(max_turn)
+            }
+            Some((last, _)) => last,
+            None => bearing,
+        };
+        self.last = Some((turn_between(0.0, heading), now));
+        protocol::Position::new(
+            me.x.inner() + heading.sin() * dist,
+            me.y.inner() - heading.cos() * dist,
+        )
+    }
+}
+
+/// Upgrades and powerups lying around the map, as far as the server has
+/// told us
+#[derive(Debug, Default)]
+struct Powerups {
+    /// Where each one is, by mob ID
+    spawned: HashMap<u16, protocol::Position>,
+}
+
+impl Powerups {
+    /// Note powerups appearing and being picked up or expiring
+    fn update(&mut self, packet: &protocol::ServerPacket) {
+        match packet {
+            protocol::ServerPacket::MobUpdateStationary(mob) if !is_missile(mob.ty) => {
+                self.spawned.insert(mob.id.0, mob.pos);
+            }
+            protocol::ServerPacket::MobDespawn(mob) => {
+                self.spawned.remove(&mob.id.0);
+            }
+            _ => (),
+        }
+    }
+
+    /// Where to head from `me` for the nearest powerup within
+    /// `POWERUP_RANGE` that we can reach, if there is one
+    ///
+    /// That's the powerup itself, or a waypoint on the way around mountains
+    /// and cells to avoid, planned like the way to a target.
+    fn waypoint(
+        &mut self,
+        me: protocol::Position,
+        map: &Map,
+        clearance: isize,
+    ) -> Option<protocol::Position> {
+        let dist = |pos: protocol::Position| (pos - me).length().inner();
+        self.spawned
+            .retain(|_, &mut pos| dist(pos) > POWERUP_PICKUP_DIST);
+        let mut nearby: Vec<_> = self
+            .spawned
+            .values()
+            .cloned()
+            .filter(|&pos| dist(pos) <= POWERUP_RANGE)
+            .collect();
+        nearby.sort_by(|a, b| {
+            dist(*a)
+                .partial_cmp(&dist(*b))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let src = map.map_position(me);
+        let danger = DangerMap::default();
+        nearby.into_iter().find_map(|goal| {
+            let dst = map.map_position(goal);
+            if dst.is_occupied(map) {
+                return None;
+            }
+            match plan_route(map, src, dst, clearance, &danger) {
+                Route::Direct => Some(goal),
+                Route::Via(p) => Some(map.world_position(p)),
+                Route::Unreachable => None,
+            }
+        })
+    }
+}
+
+/// How a wingman gets from one map cell to another
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Route {
+    /// Straight there
+    Direct,
+    /// By way of this cell, the furthest waypoint we can see on the path
+    Via(MapPosition),
+    /// There's no way there
+    Unreachable,
+}
+
+/// Plan the way from `src` to `dst`, keeping `clearance` cells from
+/// obstacles where we can
+///
+/// Pathfinding only kicks in when something on the straight line is near:
+/// a mountain, a cell to avoid, or enemies in `danger` when we're cautious.
+/// Anything further off, we can just head in its direction for now.
+fn plan_route(
+    map: &Map,
+    src: MapPosition,
+    dst: MapPosition,
+    clearance: isize,
+    danger: &DangerMap,
+) -> Route {
+    let blocked = src
+        .obstacle_between(dst, map)
+        .or_else(|| map.avoid_between(src, dst))
+        .or_else(|| danger.danger_between(src, dst));
+    match blocked {
+        Some(cell) if cell.distance_world(src, map) < PATHFINDING_DIST => {
+            match src.path_avoiding(dst, map, clearance, danger) {
+                Some((positions, _)) => map
+                    .smooth_path_avoiding(&positions, danger)
+                    .get(1)
+                    .map_or(Route::Direct, |&p| Route::Via(p)),
+                None => Route::Unreachable,
+            }
+        }
+        _ => Route::Direct,
+    }
+}
+
+/// True if the mob is a missile, as opposed to an upgrade or powerup
+fn is_missile(ty: protocol::MobType) -> bool {
+    match ty {
+        protocol::MobType::Upgrade | protocol::MobType::Shield | protocol::MobType::Inferno => {
+            false
+        }
+        _ => true,
+    }
+}
+
+/// True if the mob is a missile within `REPEL_RANGE` of `me` and flying
+/// towards us
+///
+/// Missiles we fire ourselves fly away from us, so they don't count.
+fn missile_incoming(me: protocol::Position, mob: &protocol::server::MobUpdate) -> bool {
+    if !is_missile(mob.ty) || (me - mob.pos).length().inner() > REPEL_RANGE {
+        return false;
+    }
+    let (dx, dy) = (
+        me.x.inner() - mob.pos.x.inner(),
+        me.y.inner() - mob.pos.y.inner(),
+    );
+    dx * mob.speed.x.inner() + dy * mob.speed.y.inner() > 0.0
+}
+
+/// The distance to the mob if it's a missile on a collision course with `me`,
+/// else `None`
+///
+/// A missile is on a collision course if it's within `DODGE_RANGE`, and
+/// flying within `DODGE_CONE` of straight at us.
+fn missile_threat(me: protocol::Position, mob: &protocol::server::MobUpdate) -> Option<f32> {
+    if !is_missile(mob.ty) {
+        return None;
+    }
+    let (dx, dy) = (
+        me.x.inner() - mob.pos.x.inner(),
+        me.y.inner() - mob.pos.y.inner(),
+    );
+    let (vx, vy) = (mob.speed.x.inner(), mob.speed.y.inner());
+    let dist = (dx * dx + dy * dy).sqrt();
+    let speed = (vx * vx + vy * vy).sqrt();
+    if dist > DODGE_RANGE || dist == 0.0 || speed == 0.0 {
+        return None;
+    }
+    let closing = (dx * vx + dy * vy) / (dist * speed);
+    if closing >= DODGE_CONE.cos() {
+        Some(dist)
+    } else {
+        None
+    }
+}
+
+/// A point to the side of the path of a missile at `missile` flying with
+/// `speed`, on the same side as `me`
+fn dodge_position(
+    me: protocol::Position,
+    missile: protocol::Position,
+    speed: protocol::Velocity,
+) -> protocol::Position {
+    let (vx, vy) = (speed.x.inner(), speed.y.inner());
+    let speed = (vx * vx + vy * vy).sqrt().max(std::f32::EPSILON);
+    let (mut px, mut py) = (-vy / speed, vx / speed);
+    let (dx, dy) = (
+        me.x.inner() - missile.x.inner(),
+        me.y.inner() - missile.y.inner(),
+    );
+    if px * dx + py * dy < 0.0 {
+        px = -px;
+        py = -py;
+    }
+    protocol::Position::new(
+        me.x.inner() + px * DODGE_DIST,
+        me.y.inner() + py * DODGE_DIST,
+    )
+}
+
+/// The number used to select a plane in the respawn command
+fn plane_code(plane: protocol::PlaneType) -> u8 {
+    match plane {
+        protocol::PlaneType::Predator => 1,
+        protocol::PlaneType::Goliath => 2,
+        protocol::PlaneType::Mohawk => 3,
+        protocol::PlaneType::Tornado => 4,
+        protocol::PlaneType::Prowler => 5,
+    }
+}
+
 pub struct Wingman;
 
 impl Wingman {
-    /// Spawn a wingman that connects to the associated URL and follows the target
+    /// Spawn a wingman that connects to the associated URL, logs in as `login_name`,
+    /// and follows the target
+    ///
+    /// When the shutdown flag goes high, the wingman shuts down. Events are sent
+    /// to `events`. The wingman flies to formation `slot` around the target, in
+    /// `ship` if one is given, and attacks or defends the target according to
+    /// `behavior`, taking on each player in `rotation` in turn while it
+    /// attacks. It opens fire on enemies within `range`, or `MIN_FIRE_DIST`
+    /// if `None`. Orders received on `control` switch the wingman to a new
+    /// target or behavior without reconnecting. Unless it's the leader, it flies in
+    /// formation with the wingman leading its wing, while there is one.
     ///
-    /// When the shutdown flag goes high, the wingman shuts down.
+    /// The wingman waits its turn on the options' spawn schedule before
+    /// connecting, and quietly exits if it's called off while waiting.
     ///
     /// We need to use the name of a target, not an ID, because the IDs for players
     /// seem to vary across clients.
-    pub async fn spawn(url: Url, target: String, shutdown: Flag) {
-        let mut client = match await!(Client::new_insecure(url)) {
+    pub async fn spawn(
+        url: Url,
+        target: String,
+        login_name: String,
+        slot: usize,
+        ship: Option<protocol::PlaneType>,
+        behavior: commands::Behavior,
+        rotation: Rotation,
+        range: Option<u16>,
+        shutdown: Flag,
+        options: Options,
+        events: Reporter,
+        control: mpsc::Receiver<Order>,
+        lead: Lead,
+    ) {
+        let delay = options.spawn_schedule.reserve(time::Instant::now());
+        if delay > time::Duration::from_secs(0) {
+            await!(net::sleep(delay));
+        }
+        if shutdown.read() {
+            log::debug!("wingman for {} called off before logging in", target);
+            return;
+        }
+
+        let mut client = match await!(net::open_within(url, options.login)) {
             Err(err) => {
                 log::error!("error connection wingman client {}", err);
+                events.report(EventKind::Failed);
                 return;
             }
             Ok(client) => client,
         };
 
-        if let Err(err) = await!(client.send(protocol::client::Login {
-            flag: "UN".to_owned(),
-            name: target.clone(),
-            session: "none".to_owned(),
-            horizon_x: 3000,
-            horizon_y: 3000,
-            protocol: 5,
-        })) {
+        if let Err(err) = await!(client.send(options.login.login(login_name))) {
             log::error!("error logging in wingman {}", err);
+            events.report(EventKind::Failed);
             return;
         }
 
-        if let Err(err) = await!(client.wait_for_login()) {
+        if let Err(err) = await!(net::wait_for_login(&mut client, options.login)) {
             log::error!("error waiting for wingman login {}", err);
+            events.report(EventKind::Failed);
             return;
         }
+        events.report(EventKind::Connected);
 
-        let id = match client.world.names.get(&target) {
-            Some(x) => *x,
-            None => {
+        // Switch ships by respawning in the one we want
+        if let Some(ship) = ship {
+            if let Err(err) = await!(client.send(protocol::client::Command {
+                com: "respawn".to_owned(),
+                data: plane_code(ship).to_string(),
+            })) {
+                log::error!("error selecting wingman ship {}", err);
+                return;
+            }
+        }
+
+        let id = match await!(Self::find_target(&mut client, target.clone())) {
+            Ok(Some(id)) => id,
+            Ok(None) => {
                 log::error!("no player with name {} in game", target);
+                events.report(EventKind::TargetNotFound);
+                return;
+            }
+            Err(err) => {
+                log::error!("error waiting for wingman target {}", err);
+                events.report(EventKind::TargetNotFound);
                 return;
             }
         };
 
-        warn_on_err!(await!(Self::follow(client, id, shutdown)));
+        warn_on_err!(await!(Self::follow(
+            client,
+            id,
+            target.clone(),
+            slot,
+            behavior,
+            rotation,
+            range,
+            shutdown,
+            options,
+            events,
+            control,
+            lead
+        )));
         log::debug!("shutting down wingmen on {}", target);
     }
 
+    /// The ID of the player named `target`, once they show up
+    ///
+    /// Just after logging in, the player list may not have caught up with
+    /// the server, so keep reading from it for a while before giving up.
+    async fn find_target(
+        client: &mut ClientBase,
+        target: String,
+    ) -> airmash_client::ClientResult<Option<u16>> {
+        for attempt in 0..TARGET_LOOKUP_ATTEMPTS {
+            if let Some(&id) = client.world.names.get(&target) {
+                return Ok(Some(id));
+            }
+            log::debug!(
+                "wingman target {} not in game yet (attempt {})",
+                target,
+                attempt + 1
+            );
+
+            let until = time::Instant::now() + TARGET_LOOKUP_WAIT;
+            loop {
+                let now = time::Instant::now();
+                if now >= until || client.world.names.contains_key(&target) {
+                    break;
+                }
+                match await!(net::timeout(client.next(), until - now)) {
+                    Some(next) => {
+                        if next?.is_none() {
+                            return Ok(None);
+                        }
+                    }
+                    None => break,
+                }
+            }
+        }
+        Ok(client.world.names.get(&target).cloned())
+    }
+
     async fn follow(
         mut client: ClientBase,
-        player: u16,
+        mut player: u16,
+        mut target: String,
+        slot: usize,
+        orders: commands::Behavior,
+        mut rotation: Rotation,
+        range: Option<u16>,
         shutdown: Flag,
+        options: Options,
+        mut events: Reporter,
+        control: mpsc::Receiver<Order>,
+        lead: Lead,
     ) -> airmash_client::ClientResult<()> {
         let mut pos;
         let mut prev = time::Instant::now();
+        let mut deaths = Deaths::default();
+        let mut retreat = Retreat::default();
+        // True while we hold the special key to boost a predator
+        let mut boosting = false;
+        // True if a missile is headed our way since we last checked
+        let mut incoming = false;
+        // The position, speed and distance of the nearest missile on a
+        // collision course with us since we last steered
+        let mut threat: Option<(protocol::Position, protocol::Velocity, f32)> = None;
+        // When we last failed to find a path to the target, if we're failing
+        let mut unreachable_since: Option<time::Instant> = None;
+        // When the target died, if it's dead
+        let mut target_dead_since: Option<time::Instant> = None;
+        // When the target left the game, if it's gone
+        let mut target_gone_since: Option<time::Instant> = None;
+        // How fast the target is moving, to steer for where it's going
+        let mut target_velocity = VelocityEstimate::default();
+        // Extra pathfinding cost near enemies, if we're cautious, and when
+        // it was last rebuilt
+        let mut danger = DangerMap::default();
+        let mut danger_updated: Option<time::Instant> = None;
+        // Where we've been lately, when we started getting unstuck and where
+        // we're headed to do it, if we are, and how many times we've been
+        // stuck since we last got anywhere
+        let mut stuck = StuckDetector::default();
+        let mut unsticking: Option<(time::Instant, protocol::Position)> = None;
+        let mut unsticks: u32 = 0;
+        // How long we've been too far from the target
+        let mut leash = Leash::default();
+        // When our wing's leader left the game, if it's gone
+        let mut leader_gone_since: Option<time::Instant> = None;
+        // Unspent upgrade points, and our speed, defense, energy and missile levels
+        let mut upgrade_points: u16 = 0;
+        let mut upgrade_levels = [0u8; 4];
+        // True while we wait for the server to confirm an upgrade, or to
+        // update our points if it refused
+        let mut upgrading = false;
+        // Whether we've the energy to shoot
+        let mut trigger = Trigger::default();
+        // The heading we steer for, turning smoothly towards our goal
+        let mut heading = Heading::default();
+        // Powerups to go after while we wait for the target, if we do
+        let mut powerups = Powerups::default();
+        let fire_dist = range.map(f32::from).unwrap_or(MIN_FIRE_DIST);
+        let map = options.map.clone();
+        // Where to fly and what to shoot, and the point we're guarding,
+        // if we're patrolling
+        let mut behavior = behavior::for_orders(orders, time::Instant::now());
+        let mut post = behavior.post();
+        // Only attacking wingmen move on through their rotation, to the
+        // player picked when the target dies, leaves or gets away
+        let mut attacking = orders == commands::Behavior::Attack;
+        let mut rotated: Option<String> = None;
+        let name = client.world.get_me().name.clone();
+        // A server that goes quiet has dropped us without closing the
+        // connection, so we give up rather than wait forever
+        let mut watchdog = net::Watchdog::new(options.stale_after, time::Instant::now());
         await!(client.press_key(protocol::KeyCode::Up))?;
-        while let Some(_) = await!(client.next())? {
+        loop {
+            let now = time::Instant::now();
+            let next = match watchdog.remaining(now) {
+                Some(_) if watchdog.is_stale(now) => None,
+                Some(remaining) => await!(net::timeout(client.next(), remaining)),
+                None => Some(await!(client.next())),
+            };
+            let event = match next {
+                Some(next) => match next? {
+                    Some(event) => event,
+                    None => break,
+                },
+                None => {
+                    log::warn!(
+                        "wingman {} heard nothing in {}s; taking the connection to be dead",
+                        name,
+                        watchdog.limit().as_secs()
+                    );
+                    break;
+                }
+            };
+            if let ClientEvent::Packet(_) = event {
+                watchdog.heard(time::Instant::now());
+            }
             if shutdown.read() {
                 break;
             }
 
-            if let Some(p) = client.world.players.get(&player) {
-                pos = p.pos;
-            } else {
+            // Switch targets or behavior in place, rather than reconnecting.
+            // A target picked by ground control replaces our rotation.
+            let mut retarget = rotated.take();
+            while let Ok(order) = control.try_recv() {
+                match order {
+                    Order::Retarget(name) => {
+                        rotation = Rotation::default();
+                        retarget = Some(name);
+                    }
+                    Order::Behave(orders) => {
+                        log::debug!("wingman switching to {:?}", orders);
+                        behavior = behavior::for_orders(orders, time::Instant::now());
+                        post = behavior.post();
+                        attacking = orders == commands::Behavior::Attack;
+                    }
+                }
+            }
+            if let Some(name) = retarget {
+                match client.world.names.get(&name) {
+                    Some(&id) => {
+                        log::debug!("wingman retargeting to {}", name);
+                        player = id;
+                        target = name.clone();
+                        events.retarget(name);
+                        target_velocity.reset();
+                        target_dead_since = None;
+                        target_gone_since = None;
+                        unreachable_since = None;
+                        stuck.reset();
+                        leash.reset();
+                    }
+                    None => log::warn!("wingman can't retarget to {}; not in game", name),
+                }
+            }
+
+            if let ClientEvent::Packet(protocol::ServerPacket::PlayerKill(ref kill)) = event {
+                let me = protocol::Player(client.world.get_me().id);
+                if kill.killer == Some(me) {
+                    if let Some(victim) = client.world.players.get(&kill.id.0) {
+                        events.report(EventKind::Killed {
+                            victim: victim.name.clone(),
+                        });
+                    }
+                }
+            }
+
+            if let ClientEvent::Packet(ref packet) = event {
+                if options.grab_powerups {
+                    powerups.update(packet);
+                }
+            }
+
+            if let ClientEvent::Packet(protocol::ServerPacket::MobUpdate(ref mob)) = event {
+                incoming |= missile_incoming(client.world.get_me().pos, mob);
+                if options.dodge {
+                    if let Some(dist) = missile_threat(client.world.get_me().pos, mob) {
+                        if threat.map_or(true, |(_, _, nearest)| dist < nearest) {
+                            threat = Some((mob.pos, mob.speed, dist));
+                        }
+                    }
+                }
+            }
+
+            match event {
+                ClientEvent::Packet(protocol::ServerPacket::ScoreUpdate(ref score))
+                    if score.id.0 == client.world.get_me().id =>
+                {
+                    upgrade_points = score.upgrades;
+                    upgrading = false;
+                }
+                ClientEvent::Packet(protocol::ServerPacket::PlayerUpgrade(ref upgrade)) => {
+                    upgrade_points = upgrade.upgrades;
+                    upgrade_levels = [
+                        upgrade.speed,
+                        upgrade.defense,
+                        upgrade.energy,
+                        upgrade.missile,
+                    ];
+                    upgrading = false;
+                }
+                _ => (),
+            }
+
+            // Only spend points the server says we've earned, one at a time
+            if options.upgrades && upgrade_points > 0 && !upgrading {
+                if let Some(code) = next_upgrade(upgrade_levels) {
+                    await!(client.send(protocol::client::Command {
+                        com: "upgrade".to_owned(),
+                        data: code.to_string(),
+                    }))?;
+                    upgrading = true;
+                }
+            }
+
+            if client.world.get_me().status == protocol::PlayerStatus::Dead {
+                if !deaths.record(time::Instant::now()) {
+                    log::info!("wingman shot down too many times; giving up");
+                    break;
+                }
+
+                // The server wants us idle for a moment before we respawn
+                await!(client.release_key(protocol::KeyCode::Fire))?;
+                await!(client.release_key(protocol::KeyCode::Up))?;
+                await!(client.release_key(protocol::KeyCode::Special))?;
+                boosting = false;
+                incoming = false;
+                threat = None;
+                stuck.reset();
+                leash.reset();
+                unsticking = None;
+                await!(client.wait(RESPAWN_DELAY))?;
+                if shutdown.read() {
+                    break;
+                }
+
+                // Some game modes respawn us on their own
+                if client.world.get_me().status == protocol::PlayerStatus::Dead {
+                    let plane = plane_code(client.world.get_me().plane);
+                    await!(client.send(protocol::client::Command {
+                        com: "respawn".to_owned(),
+                        data: plane.to_string(),
+                    }))?;
+                    await!(client.wait(RESPAWN_DELAY))?;
+                }
+
+                await!(client.press_key(protocol::KeyCode::Up))?;
+                prev = time::Instant::now();
+                retreat.reset();
+                continue;
+            }
+
+            // A target that drops out and rejoins comes back with a new ID,
+            // so look for it by name until it's been gone too long
+            if !client.world.players.contains_key(&player) {
+                if let Some(&id) = client.world.names.get(&target) {
+                    log::debug!("wingman target {} is back", target);
+                    player = id;
+                }
+            }
+            // Patrolling wingmen guard their point whatever their player does
+            let found = match post {
+                Some(point) => Some((point, protocol::PlayerStatus::Alive)),
+                None => client.world.players.get(&player).map(|p| (p.pos, p.status)),
+            };
+            let (target_pos, target_status) = match found {
+                Some(found) => found,
+                None => {
+                    if let Some(next) = next_in_rotation(&rotation, &target, attacking, &client) {
+                        log::info!("wingman target {} left; moving on to {}", target, next);
+                        rotated = Some(next);
+                        continue;
+                    }
+                    let since = *target_gone_since.get_or_insert_with(time::Instant::now);
+                    if since.elapsed() > options.target_grace {
+                        log::info!("wingman target {} left; giving up", target);
+                        events.report(EventKind::TargetLost);
+                        break;
+                    }
+
+                    // Hold position until the target is back, unless
+                    // there's a powerup to pick up in the meantime
+                    target_velocity.reset();
+                    stuck.reset();
+                    leash.reset();
+                    await!(client.release_key(protocol::KeyCode::Fire))?;
+                    await!(client.release_key(protocol::KeyCode::Special))?;
+                    boosting = false;
+                    let me = client.world.get_me().pos;
+                    let grab = if options.grab_powerups {
+                        powerups.waypoint(me, &map, options.clearance)
+                    } else {
+                        None
+                    };
+                    match grab {
+                        Some(goal) => {
+                            await!(client.press_key(protocol::KeyCode::Up))?;
+                            let steer = heading.steer(me, goal, time::Instant::now());
+                            await!(client.point_at(steer))?;
+                        }
+                        None => await!(client.release_key(protocol::KeyCode::Up))?,
+                    }
+                    await!(client.wait(options.tick.interval(client.world.ping)))?;
+                    continue;
+                }
+            };
+            target_gone_since = None;
+
+            // Other players see a spectator as dead, so wait out a respawn
+            // before giving up on the target
+            if target_status == protocol::PlayerStatus::Dead {
+                if let Some(next) = next_in_rotation(&rotation, &target, attacking, &client) {
+                    log::debug!("wingman target {} is down; moving on to {}", target, next);
+                    rotated = Some(next);
+                    continue;
+                }
+                target_velocity.reset();
+                stuck.reset();
+                leash.reset();
+                let since = *target_dead_since.get_or_insert_with(time::Instant::now);
+                if since.elapsed() > TARGET_DEAD_GRACE {
+                    log::info!("wingman target is spectating; giving up");
+                    await!(client.release_key(protocol::KeyCode::Fire))?;
+                    await!(client.release_key(protocol::KeyCode::Special))?;
+                    events.report(EventKind::TargetLost);
+                    break;
+                }
+
+                await!(client.release_key(protocol::KeyCode::Fire))?;
+                if options.grab_powerups {
+                    let me = client.world.get_me().pos;
+                    if let Some(goal) = powerups.waypoint(me, &map, options.clearance) {
+                        await!(client.press_key(protocol::KeyCode::Up))?;
+                        let steer = heading.steer(me, goal, time::Instant::now());
+                        await!(client.point_at(steer))?;
+                    }
+                }
+                await!(client.wait(options.tick.interval(client.world.ping)))?;
+                continue;
+            }
+            target_dead_since = None;
+            target_velocity.update(target_pos, time::Instant::now());
+
+            // Don't follow the target across the whole map. A patrol point
+            // doesn't move, so there's no chase to break off.
+            let target_dist = (target_pos - client.world.get_me().pos).length().inner();
+            if post.is_none()
+                && leash.pull(
+                    target_dist,
+                    options.max_chase_distance,
+                    time::Instant::now(),
+                )
+            {
+                if let Some(next) = next_in_rotation(&rotation, &target, attacking, &client) {
+                    log::info!("wingman target {} got away; moving on to {}", target, next);
+                    rotated = Some(next);
+                    continue;
+                }
+                log::info!("wingman target {} is too far away; giving up", target);
+                events.report(EventKind::TargetLost);
                 break;
             }
 
-            // Fire when close to the target.
-            let mut fire = if (pos - client.world.get_me().pos).length().inner() < MIN_FIRE_DIST {
-                true
-            } else {
-                false
+            let health = client.world.get_me().health;
+            if options.retreat && retreat.update(health, time::Instant::now()) {
+                stuck.reset();
+                // Hold fire and fly directly away from the target
+                let away = retreat_position(client.world.get_me().pos, target_pos);
+                await!(client.release_key(protocol::KeyCode::Fire))?;
+                let steer = heading.steer(client.world.get_me().pos, away, time::Instant::now());
+                await!(client.point_at(steer))?;
+                if time::Instant::now() - prev > time::Duration::from_millis(500) {
+                    await!(client.press_key(protocol::KeyCode::Up))?;
+                    prev = time::Instant::now();
+                }
+
+                await!(client.wait(options.tick.interval(client.world.ping)))?;
+                continue;
+            }
+            // Our orders pick where to fly and what to shoot, given where
+            // the target is headed. Pathfinding and obstacle avoidance below
+            // work from the position they pick.
+            let action = {
+                let (me, players) = contacts(&client);
+                behavior.step(&WingContext {
+                    me: &me,
+                    players: &players,
+                    player,
+                    target: target_pos,
+                    target_lead: target_velocity.project(target_pos, INTERCEPT_LEAD),
+                    slot,
+                    fire_dist,
+                    options: &options,
+                    now: time::Instant::now(),
+                })
+            };
+            pos = action.dest;
+
+            // Followers hold their place in formation around the leader,
+            // and leave finding the way to it. While the leader is dead,
+            // they pursue the target on their own.
+            let leader = lead.leader().filter(|leader| *leader != name);
+            let following = match leader {
+                Some(leader) => {
+                    let found = client
+                        .world
+                        .names
+                        .get(&leader)
+                        .and_then(|id| client.world.players.get(id));
+                    match found {
+                        Some(p) => {
+                            leader_gone_since = None;
+                            Some(p)
+                                .filter(|p| p.status == protocol::PlayerStatus::Alive)
+                                .map(|p| p.pos)
+                        }
+                        None => {
+                            let since = *leader_gone_since.get_or_insert_with(time::Instant::now);
+                            if since.elapsed() > LEADER_PROMOTE_TIME && lead.promote(&leader, &name)
+                            {
+                                log::debug!(
+                                    "wingman {} taking over the lead from {}",
+                                    name,
+                                    leader
+                                );
+                            }
+                            None
+                        }
+                    }
+                }
+                None => None,
             };
+            if let Some(leader_pos) = following {
+                pos = formation_position(leader_pos, slot);
+            }
+
+            // Notice when we're wedged somewhere short of our slot, and fly
+            // for open sky before resuming the chase
+            let now = time::Instant::now();
+            let me = client.world.get_me();
+            stuck.record(me.pos, now);
+            if unsticking.is_none()
+                && stuck.is_stuck(now)
+                && (pos - me.pos).length().inner() > FORMATION_RADIUS
+            {
+                unsticks += 1;
+                if unsticks > MAX_UNSTICKS {
+                    log::info!("wingman stuck too many times; giving up");
+                    events.report(EventKind::Unreachable);
+                    break;
+                }
+                log::debug!("wingman stuck; trying to get free");
+                unsticking = Some((now, escape_position(&map, me.pos, me.rot.inner())));
+                stuck.reset();
+            } else if stuck.is_full(now) && !stuck.is_stuck(now) {
+                unsticks = 0;
+            }
+
+            if let Some((start, escape)) = unsticking {
+                if start.elapsed() < UNSTICK_TIME {
+                    await!(client.release_key(protocol::KeyCode::Fire))?;
+                    let me = client.world.get_me().pos;
+                    let steer = heading.steer(me, escape, time::Instant::now());
+                    await!(client.point_at(steer))?;
+                    if time::Instant::now() - prev > time::Duration::from_millis(500) {
+                        await!(client.press_key(protocol::KeyCode::Up))?;
+                        prev = time::Instant::now();
+                    }
+                    // Predators boost clear; the boost is let go below once
+                    // we're back on the chase
+                    if client.world.get_me().plane == protocol::PlaneType::Predator && !boosting {
+                        await!(client.press_key(protocol::KeyCode::Special))?;
+                        boosting = true;
+                    }
+                    await!(client.wait(options.tick.interval(client.world.ping)))?;
+                    continue;
+                }
+                unsticking = None;
+                stuck.reset();
+            }
+
+            // Fire at whatever our orders picked once it's in range, unless
+            // something below says otherwise
+            let mut fire = action.fire;
+            let aim_pos = action.aim.unwrap_or(pos);
 
             if time::Instant::now() - prev > time::Duration::from_millis(500) {
                 await!(client.press_key(protocol::KeyCode::Up))?;
                 prev = time::Instant::now();
             }
 
-            let src_map_pos: MapPosition = client.world.get_me().pos.into();
-            let mut dst_map_pos: MapPosition = pos.into();
+            let src_map_pos = map.map_position(client.world.get_me().pos);
+            let mut dst_map_pos = map.map_position(pos);
             let mut pathfinding_enabled = true;
+            let mut reachable = true;
 
             // astar will search the entire map if the destination is occupied so pick
             // a free adjacent position.
-            if dst_map_pos.is_occupied() {
-                if let Some(p) = dst_map_pos.adjacent_positions().next() {
+            if dst_map_pos.is_occupied(&map) {
+                if let Some(p) = dst_map_pos.adjacent_positions(&map).next() {
                     dst_map_pos = p;
                 } else {
                     // Couldn't find an unoccupied position on the map, so disable
                     // pathfinding so the cpu doesn't spike.
                     pathfinding_enabled = false;
+                    reachable = false;
                 }
             }
 
+            if options.cautious
+                && danger_updated.map_or(true, |at| at.elapsed() > DANGER_UPDATE_INTERVAL)
+            {
+                danger = DangerMap::new(&map, enemy_positions(&client, player));
+                danger_updated = Some(time::Instant::now());
+            }
+
             if pathfinding_enabled {
-                // Only use pathfinding if there's an obstacle (mountain) between us and
-                // the target.
-                if let Some(ob_map_pos) = src_map_pos.obstacle_between(dst_map_pos) {
+                if src_map_pos.obstacle_between(dst_map_pos, &map).is_some() {
                     // Don't fire if don't have line-of-sight.
                     fire = false;
+                }
 
-                    // Make sure the obstacle is near, otherwise we can just head in its
-                    // direction.
-                    // Distance is in map units (1 = 64 world units), so this is taking us within
-                    // 960 of the obstacle.
-                    if ob_map_pos.distance(src_map_pos) < 16 {
-                        let path_positions = astar(
-                            &src_map_pos,
-                            |p| p.adjacent_positions().into_iter().map(|pp| (pp, 1)),
-                            |p| p.distance(dst_map_pos),
-                            |p| p.x == dst_map_pos.x && p.y == dst_map_pos.y,
-                        );
-                        if let Some((positions, _)) = path_positions {
-                            if let Some(p) = positions.get(1) {
-                                pos = (*p).into();
-                            }
-                        }
+                // Followers leave pathfinding to their leader
+                if following.is_none() {
+                    match plan_route(&map, src_map_pos, dst_map_pos, options.clearance, &danger) {
+                        Route::Direct => (),
+                        Route::Via(p) => pos = map.world_position(p),
+                        Route::Unreachable => reachable = false,
                     }
                 }
             }
 
-            await!(client.point_at(pos))?;
+            if reachable {
+                unreachable_since = None;
+            } else {
+                let since = *unreachable_since.get_or_insert_with(time::Instant::now);
+                if since.elapsed() > UNREACHABLE_TIME {
+                    log::info!("wingman can't reach its target; giving up");
+                    events.report(EventKind::Unreachable);
+                    break;
+                }
+            }
+
+            // Keep clear of other planes, our siblings included
+            let (push_x, push_y) = separation(&client, player);
+            pos = protocol::Position::new(pos.x.inner() + push_x, pos.y.inner() + push_y);
 
-            if fire {
+            // Getting out of a missile's way comes before the attack
+            if let Some((missile, speed, _)) = threat.take() {
+                pos = dodge_position(client.world.get_me().pos, missile, speed);
+                fire = false;
+            }
+
+            // Use our ship's special ability when our orders call for it,
+            // and repel missiles headed our way
+            match client.world.get_me().plane {
+                protocol::PlaneType::Predator => {
+                    let boost = action.special;
+                    if boost && !boosting {
+                        await!(client.press_key(protocol::KeyCode::Special))?;
+                    } else if !boost && boosting {
+                        await!(client.release_key(protocol::KeyCode::Special))?;
+                    }
+                    boosting = boost;
+                }
+                protocol::PlaneType::Goliath => {
+                    if incoming || action.special {
+                        await!(client.press_key(protocol::KeyCode::Special))?;
+                        await!(client.release_key(protocol::KeyCode::Special))?;
+                    }
+                }
+                _ => (),
+            }
+            incoming = false;
+
+            // Aim at the enemy itself, not our formation slot
+            let goal = if fire { aim_pos } else { pos };
+            let steer = heading.steer(client.world.get_me().pos, goal, time::Instant::now());
+            await!(client.point_at(steer))?;
+
+            // Keep turning towards the enemy, but hold fire until aimed, and
+            // while we're out of energy. Aim is judged by the way we're
+            // actually facing, not the heading we're steering for.
+            let me = client.world.get_me();
+            let loaded = trigger.ready(me.energy, shot_energy(me.plane));
+            if fire && loaded && aim_error(me.pos, me.rot.inner(), aim_pos) <= MAX_FIRE_ANGLE {
                 await!(client.press_key(protocol::KeyCode::Fire))?;
             } else {
                 await!(client.release_key(protocol::KeyCode::Fire))?;
             }
 
-            let delay_time = u64::from((client.world.ping * 2).min(1000).max(10));
-            await!(client.wait(time::Duration::from_millis(delay_time)))?;
+            await!(client.wait(options.tick.interval(client.world.ping)))?;
         }
 
         await!(client.release_key(protocol::KeyCode::Up))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Positions a tick apart, starting at `start`, moving `step` world units
+    /// to the right each tick
+    fn track(
+        start: time::Instant,
+        step: f32,
+        ticks: u32,
+    ) -> Vec<(protocol::Position, time::Instant)> {
+        (0..ticks)
+            .map(|i| {
+                (
+                    protocol::Position::new(1000.0 + step * i as f32, 500.0),
+                    start + time::Duration::from_millis(100) * i,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stuck_after_staying_put() {
+        let start = time::Instant::now();
+        let mut stuck = StuckDetector::default();
+        // Jittering back and forth on the same spot
+        for (i, (pos, at)) in track(start, 0.0, 40).into_iter().enumerate() {
+            let jitter = if i % 2 == 0 { 2.0 } else { -2.0 };
+            stuck.record(
+                protocol::Position::new(pos.x.inner() + jitter, pos.y.inner()),
+                at,
+            );
+            let elapsed = at - start;
+            assert_eq!(
+                stuck.is_stuck(at),
+                elapsed >= STUCK_TIME,
+                "at {:?}",
+                elapsed
+            );
+        }
+
+        stuck.reset();
+        assert!(!stuck.is_stuck(start + time::Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn not_stuck_while_moving() {
+        let start = time::Instant::now();
+        let mut stuck = StuckDetector::default();
+        for (pos, at) in track(start, 10.0, 60) {
+            stuck.record(pos, at);
+            assert!(!stuck.is_stuck(at));
+        }
+        assert!(stuck.is_full(start + time::Duration::from_secs(6)));
+    }
+
+    #[test]
+    fn stuck_after_stopping() {
+        let start = time::Instant::now();
+        let mut stuck = StuckDetector::default();
+        let moving = track(start, 10.0, 30);
+        let (last, stopped_at) = *moving.last().unwrap();
+        for &(pos, at) in &moving {
+            stuck.record(pos, at);
+        }
+        assert!(!stuck.is_stuck(stopped_at));
+
+        // Samples from before we stopped fall out of the window
+        let later = stopped_at + STUCK_TIME + time::Duration::from_millis(100);
+        stuck.record(last, stopped_at + time::Duration::from_secs(1));
+        stuck.record(last, later);
+        assert!(stuck.is_stuck(later));
+    }
+
+    #[test]
+    fn spawn_schedule_staggers_logins() {
+        let schedule = SpawnSchedule::new(time::Duration::from_millis(300));
+        let shared = schedule.clone();
+        let now = time::Instant::now();
+        assert_eq!(schedule.reserve(now), time::Duration::from_millis(0));
+        assert_eq!(shared.reserve(now), time::Duration::from_millis(300));
+        assert_eq!(
+            schedule.reserve(now + time::Duration::from_millis(100)),
+            time::Duration::from_millis(500)
+        );
+
+        // Once the schedule has caught up, logins go straight through
+        let later = now + time::Duration::from_secs(5);
+        assert_eq!(shared.reserve(later), time::Duration::from_millis(0));
+    }
+
+    #[test]
+    fn leash_breaks_after_staying_too_far() {
+        let start = time::Instant::now();
+        let secs = |secs| start + time::Duration::from_secs(secs);
+        let mut leash = Leash::default();
+
+        assert!(!leash.pull(9000.0, 8000.0, start));
+        assert!(!leash.pull(9000.0, 8000.0, secs(4)));
+        // Coming back in range starts the wait over
+        assert!(!leash.pull(7000.0, 8000.0, secs(5)));
+        assert!(!leash.pull(9000.0, 8000.0, secs(6)));
+        assert!(!leash.pull(9000.0, 8000.0, secs(10)));
+        assert!(leash.pull(9000.0, 8000.0, secs(11)));
+    }
+
+    #[test]
+    fn leash_holds_while_catching_up() {
+        let start = time::Instant::now();
+        let secs = |secs| start + time::Duration::from_secs(secs);
+        let mut leash = Leash::default();
+
+        assert!(!leash.pull(12000.0, 8000.0, start));
+        assert!(!leash.pull(11000.0, 8000.0, secs(4)));
+        assert!(!leash.pull(10000.0, 8000.0, secs(8)));
+        // Small wobbles aren't catching up
+        assert!(!leash.pull(10200.0, 8000.0, secs(10)));
+        assert!(!leash.pull(9800.0, 8000.0, secs(12)));
+        assert!(leash.pull(9800.0, 8000.0, secs(13)));
+    }
+
+    #[test]
+    fn retreat_rests_after_running_out_of_time() {
+        let start = time::Instant::now();
+        let secs = |secs| start + time::Duration::from_secs(secs);
+        let mut retreat = Retreat::default();
+
+        assert!(!retreat.update(0.5, start));
+        assert!(retreat.update(0.2, secs(1)));
+        assert!(retreat.update(0.4, secs(4)));
+        // Still hurt when time runs out: fight on for a while first
+        assert!(!retreat.update(0.2, secs(5)));
+        assert!(!retreat.update(0.2, secs(10)));
+        assert!(retreat.update(0.2, secs(11)));
+    }
+
+    #[test]
+    fn retreat_ends_on_recovery() {
+        let start = time::Instant::now();
+        let secs = |secs| start + time::Duration::from_secs(secs);
+        let mut retreat = Retreat::default();
+
+        assert!(retreat.update(0.2, start));
+        assert!(!retreat.update(0.9, secs(2)));
+        // Recovering doesn't need a rest before the next retreat
+        assert!(retreat.update(0.2, secs(3)));
+    }
+
+    #[test]
+    fn leash_of_zero_never_breaks() {
+        let start = time::Instant::now();
+        let mut leash = Leash::default();
+        assert!(!leash.pull(30000.0, 0.0, start));
+        assert!(!leash.pull(30000.0, 0.0, start + time::Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn trigger_waits_for_energy_after_running_dry() {
+        let mut trigger = Trigger::default();
+        assert!(trigger.ready(1.0, 0.3));
+        assert!(trigger.ready(0.3, 0.3));
+        // Out of energy: hold fire until it's built back up
+        assert!(!trigger.ready(0.2, 0.3));
+        assert!(!trigger.ready(0.4, 0.3));
+        assert!(trigger.ready(MIN_FIRE_ENERGY, 0.3));
+
+        // Ships with costly shots wait for enough for a shot
+        let mut trigger = Trigger::default();
+        assert!(!trigger.ready(0.5, 0.9));
+        assert!(!trigger.ready(0.8, 0.9));
+        assert!(trigger.ready(0.9, 0.9));
+    }
+
+    #[test]
+    fn lead_passes_to_one_follower() {
+        let lead = Lead::default();
+        let follower = lead.clone();
+        assert_eq!(follower.leader(), None);
+
+        lead.appoint("WING-xplay-1".to_owned());
+        assert_eq!(follower.leader(), Some("WING-xplay-1".to_owned()));
+
+        // Only the first follower to notice the leader gone takes over
+        assert!(follower.promote("WING-xplay-1", "WING-xplay-3"));
+        assert!(!lead.promote("WING-xplay-1", "WING-xplay-2"));
+        assert_eq!(lead.leader(), Some("WING-xplay-3".to_owned()));
+    }
+
+    #[test]
+    fn heading_turns_no_faster_than_the_cap() {
+        let start = time::Instant::now();
+        let me = protocol::Position::new(0.0, 0.0);
+        let up = protocol::Position::new(0.0, -500.0);
+        let down = protocol::Position::new(0.0, 500.0);
+        let mut heading = Heading::default();
+        assert_eq!(heading.steer(me, up, start), up);
+
+        // A tenth of a second only turns us a tenth of the cap
+        let later = start + time::Duration::from_millis(100);
+        let steer = heading.steer(me, down, later);
+        let turned = turn_between(0.0, steer.x.inner().atan2(-steer.y.inner()));
+        assert!((turned.abs() - (MAX_TURN_RATE / 10.0).to_radians()).abs() < 1e-3);
+        assert!(((steer - me).length().inner() - 500.0).abs() < 0.1);
+
+        // Given long enough, we face the goal
+        let steer = heading.steer(me, down, later + time::Duration::from_secs(2));
+        assert!((steer - down).length().inner() < 0.1);
+    }
+
+    #[test]
+    fn patrol_circles_the_point() {
+        let point = protocol::Position::new(-2000.0, 1500.0);
+        let dist = |pos: protocol::Position| (pos - point).length().inner();
+        let start = patrol_position(point, 0, time::Duration::from_secs(0));
+        let later = patrol_position(point, 0, time::Duration::from_secs(2));
+        assert!((dist(start) - PATROL_RADIUS).abs() < 0.1);
+        assert!((dist(later) - PATROL_RADIUS).abs() < 0.1);
+        assert!((later - start).length().inner() > 100.0);
+
+        // Wingmen keep their places in formation as they go round
+        let other = patrol_position(point, 1, time::Duration::from_secs(2));
+        assert!((other - later).length().inner() > PATROL_RADIUS);
+    }
+
+    #[test]
+    fn rotation_moves_on_to_the_next_available_player() {
+        let rotation = Rotation::new(vec![
+            "alice".to_owned(),
+            "bob".to_owned(),
+            "carol".to_owned(),
+        ]);
+        let anyone = |_: &str| true;
+        assert_eq!(rotation.after("alice", anyone), Some("bob"));
+        assert_eq!(rotation.after("carol", anyone), Some("alice"));
+        // Someone outside the rotation hands over to its start
+        assert_eq!(rotation.after("dave", anyone), Some("alice"));
+
+        assert_eq!(rotation.after("alice", |name| name != "bob"), Some("carol"));
+        assert_eq!(rotation.after("alice", |name| name == "alice"), None);
+        assert_eq!(
+            Rotation::new(vec!["alice".to_owned()]).after("alice", anyone),
+            None
+        );
+        assert_eq!(Rotation::default().after("alice", anyone), None);
+    }
+
+    #[test]
+    fn idle_wingmen_head_for_the_nearest_reachable_powerup() {
+        // Each cell is 8192 units square; the right half is mountains
+        let map = Map::parse("0 0 1 1\n0 0 1 1").unwrap();
+        let me = protocol::Position::new(-1000.0, -4000.0);
+        let powerup = |id, ty, x: f32| {
+            protocol::ServerPacket::MobUpdateStationary(protocol::server::MobUpdateStationary {
+                id: protocol::Mob(id),
+                ty,
+                pos: protocol::Position::new(x, -4000.0),
+            })
+        };
+        let mut powerups = Powerups::default();
+        assert_eq!(powerups.waypoint(me, &map, 0), None);
+
+        // Closer, but in the mountains
+        powerups.update(&powerup(1, protocol::MobType::Shield, 500.0));
+        powerups.update(&powerup(2, protocol::MobType::Upgrade, -2800.0));
+        // Too far to leave the target for
+        powerups.update(&powerup(3, protocol::MobType::Inferno, -4000.0));
+        // Missiles aren't powerups
+        powerups.update(&powerup(4, protocol::MobType::PredatorMissile, -1100.0));
+        assert_eq!(
+            powerups.waypoint(me, &map, 0),
+            Some(protocol::Position::new(-2800.0, -4000.0))
+        );
+
+        powerups.update(&protocol::ServerPacket::MobDespawn(
+            protocol::server::MobDespawn {
+                id: protocol::Mob(2),
+                ty: protocol::DespawnType::Collided,
+            },
+        ));
+        assert_eq!(powerups.waypoint(me, &map, 0), None);
+    }
+
+    #[test]
+    fn routes_go_around_cells_to_avoid() {
+        // Cells are 512 units square, with a short wall of cells to avoid
+        let rows: Vec<String> = (0..32)
+            .map(|y| {
+                (0..64)
+                    .map(|x| {
+                        if x == 10 && y >= 5 && y < 15 {
+                            "2"
+                        } else {
+                            "0"
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+        let map = Map::parse(&rows.join("\n")).unwrap();
+        let danger = DangerMap::default();
+        let (src, dst) = (MapPosition::new(9, 10), MapPosition::new(12, 10));
+
+        match plan_route(&map, src, dst, 0, &danger) {
+            Route::Via(waypoint) => assert_eq!(map.avoid_between(src, waypoint), None),
+            other => panic!("flew straight through the cells to avoid: {:?}", other),
+        }
+        // Nothing in the way
+        assert_eq!(
+            plan_route(&map, src, MapPosition::new(9, 20), 0, &danger),
+            Route::Direct
+        );
+    }
+}
@@ -0,0 +1,594 @@
+//! What a wingman does on each tick, according to its orders
+//!
+//! `Wingman::follow` looks after everything wingmen do alike: staying
+//! connected, respawning, retreating, finding a way around obstacles and
+//! getting out of the way of missiles. On each tick it hands a `WingContext`
+//! to the wingman's `Behavior`, which decides where to fly and what to
+//! shoot, and applies the `WingAction` it gets back.
+//!
+//! The context is a snapshot of the game rather than the client itself, so
+//! behaviors can be tested without a connection.
+
+use airmash_protocol as protocol;
+
+use std::collections::HashMap;
+use std::time;
+
+use super::{
+    formation_position, patrol_position, wing_name_stem, Options, BOOST_DIST, DEFEND_RANGE,
+    FIRING_LINE_WIDTH, PATROL_RANGE, REPEL_RANGE, THREAT_RESELECT_TIME, THREAT_TIE_DIST,
+};
+use crate::commands;
+
+/// A player in the game, as a wingman saw it this tick
+#[derive(Debug, Clone)]
+pub struct Contact {
+    pub id: u16,
+    pub name: String,
+    pub team: protocol::Team,
+    pub plane: protocol::PlaneType,
+    pub pos: protocol::Position,
+    pub alive: bool,
+}
+
+/// What a wingman's behavior gets to see on each tick
+pub struct WingContext<'a> {
+    /// The wingman itself
+    pub me: &'a Contact,
+    /// Everyone in the game, the wingman and its player included
+    pub players: &'a [Contact],
+    /// ID of the player the wingman is assigned to
+    pub player: u16,
+    /// Where the player is, or the point the wingman guards if it has one
+    pub target: protocol::Position,
+    /// Where the target is headed, to intercept it rather than chase it
+    pub target_lead: protocol::Position,
+    /// The wingman's place in its formation
+    pub slot: usize,
+    /// Enemies closer than this can be fired on
+    pub fire_dist: f32,
+    pub options: &'a Options,
+    pub now: time::Instant,
+}
+
+impl<'a> WingContext<'a> {
+    /// The player with ID `id`, if they're in the game
+    pub fn find(&self, id: u16) -> Option<&'a Contact> {
+        self.players.iter().find(|player| player.id == id)
+    }
+
+    /// The live enemies of the wingman and its player, other than fellow
+    /// wingmen assigned to the same player
+    ///
+    /// There are none once the player has left the game.
+    fn enemies(&self) -> impl Iterator<Item = &'a Contact> {
+        let me = self.me;
+        let protected = self.find(self.player);
+        let siblings = protected.map(|p| wing_name_stem(&self.options.name_prefix, &p.name));
+        self.players
+            .iter()
+            .filter(move |player| match (protected, &siblings) {
+                (Some(protected), Some(siblings)) => {
+                    player.team != me.team
+                        && player.team != protected.team
+                        && player.alive
+                        && !player.name.starts_with(siblings.as_str())
+                }
+                _ => false,
+            })
+    }
+}
+
+/// What a wingman's behavior wants done this tick
+#[derive(Debug, Clone, PartialEq)]
+pub struct WingAction {
+    /// Where to fly
+    pub dest: protocol::Position,
+    /// Where to aim, if there's an enemy to shoot at
+    pub aim: Option<protocol::Position>,
+    /// True to open fire once we're aimed and have the energy
+    pub fire: bool,
+    /// True to use our ship's special ability
+    pub special: bool,
+}
+
+/// How a wingman picks where to fly and what to shoot
+pub trait Behavior {
+    /// Decide what to do this tick
+    fn step(&mut self, ctx: &WingContext) -> WingAction;
+
+    /// The point the wingman guards whatever its player does, if any
+    ///
+    /// A wingman with a post flies around it instead of its player, and
+    /// never gives up for being too far from the player.
+    fn post(&self) -> Option<protocol::Position> {
+        None
+    }
+}
+
+/// The behavior for wingmen with `orders`, starting `now`
+pub fn for_orders(orders: commands::Behavior, now: time::Instant) -> Box<dyn Behavior + Send> {
+    match orders {
+        commands::Behavior::Attack => Box::new(Attack),
+        commands::Behavior::Defend => Box::new(Defend),
+        commands::Behavior::Protect => Box::new(Protect::default()),
+        commands::Behavior::Patrol { x, y } => Box::new(Patrol {
+            point: protocol::Position::new(x as f32, y as f32),
+            started: now,
+        }),
+    }
+}
+
+/// Chase the player and shoot them down
+pub struct Attack;
+
+impl Behavior for Attack {
+    fn step(&mut self, ctx: &WingContext) -> WingAction {
+        let dest = formation_position(ctx.target_lead, ctx.slot);
+        engage(ctx, dest, Some((ctx.player, ctx.target)))
+    }
+}
+
+/// Hold a place in formation around the player, and shoot whoever
+/// threatens them
+pub struct Defend;
+
+impl Behavior for Defend {
+    fn step(&mut self, ctx: &WingContext) -> WingAction {
+        let dest = formation_position(ctx.target_lead, ctx.slot);
+        engage(ctx, dest, nearest_threat(ctx, DEFEND_RANGE))
+    }
+}
+
+/// Leave the player to chase down whichever enemy is the biggest threat
+/// to them, if there is one
+#[derive(Default)]
+pub struct Protect {
+    threats: ThreatTracker,
+}
+
+impl Behavior for Protect {
+    fn step(&mut self, ctx: &WingContext) -> WingAction {
+        let hunted = self.threats.update(ctx);
+        let dest = match hunted {
+            Some((_, threat_pos)) => formation_position(threat_pos, ctx.slot),
+            None => formation_position(ctx.target_lead, ctx.slot),
+        };
+        engage(ctx, dest, hunted)
+    }
+}
+
+/// Circle a point on the map and shoot whoever comes near it
+pub struct Patrol {
+    point: protocol::Position,
+    /// When we started patrolling, to keep the formation turning
+    started: time::Instant,
+}
+
+impl Behavior for Patrol {
+    fn step(&mut self, ctx: &WingContext) -> WingAction {
+        let orbiting = if ctx.now > self.started {
+            ctx.now - self.started
+        } else {
+            time::Duration::from_secs(0)
+        };
+        let dest = patrol_position(self.point, ctx.slot, orbiting);
+        engage(ctx, dest, nearest_threat(ctx, PATROL_RANGE))
+    }
+
+    fn post(&self) -> Option<protocol::Position> {
+        Some(self.point)
+    }
+}
+
+/// Fly to `dest`, and fire on `enemy`, given by ID and position, once
+/// it's in range and no teammate is in the way
+fn engage(
+    ctx: &WingContext,
+    dest: protocol::Position,
+    enemy: Option<(u16, protocol::Position)>,
+) -> WingAction {
+    let fire = match enemy {
+        Some((id, enemy_pos)) => {
+            (enemy_pos - ctx.me.pos).length().inner() < ctx.fire_dist
+                && (ctx.options.friendly_fire || !friendly_in_line(ctx, id))
+        }
+        None => false,
+    };
+    WingAction {
+        dest,
+        aim: enemy.map(|(_, enemy_pos)| enemy_pos),
+        fire,
+        special: wants_special(ctx),
+    }
+}
+
+/// True if our ship's special ability would help right now
+///
+/// Predators boost to catch up with a far away target, and goliaths repel
+/// enemies that get close.
+fn wants_special(ctx: &WingContext) -> bool {
+    match ctx.me.plane {
+        protocol::PlaneType::Predator => (ctx.target - ctx.me.pos).length().inner() > BOOST_DIST,
+        protocol::PlaneType::Goliath => enemy_in_repel_range(ctx),
+        _ => false,
+    }
+}
+
+/// True if `point` is within `FIRING_LINE_WIDTH` of the line segment
+/// from `from` to `to`, not counting the ends of the segment
+fn in_firing_line(
+    from: protocol::Position,
+    to: protocol::Position,
+    point: protocol::Position,
+) -> bool {
+    let (dx, dy) = (to.x.inner() - from.x.inner(), to.y.inner() - from.y.inner());
+    let (px, py) = (
+        point.x.inner() - from.x.inner(),
+        point.y.inner() - from.y.inner(),
+    );
+    let len_sq = dx * dx + dy * dy;
+    if len_sq <= 0.0 {
+        return false;
+    }
+
+    // Projection of the point along the segment
+    let t = (px * dx + py * dy) / len_sq;
+    if t <= 0.0 || t >= 1.0 {
+        return false;
+    }
+
+    let (ox, oy) = (px - t * dx, py - t * dy);
+    (ox * ox + oy * oy).sqrt() < FIRING_LINE_WIDTH
+}
+
+/// True if shooting at the target would hit a teammate
+///
+/// That's either because the target is on our team, or because a live
+/// teammate is between us and the target. Team membership comes from the
+/// world's view of our own player, not the name we logged in with.
+fn friendly_in_line(ctx: &WingContext, target: u16) -> bool {
+    let me = ctx.me;
+    let target_player = match ctx.find(target) {
+        Some(player) => player,
+        None => return false,
+    };
+
+    if target_player.team == me.team {
+        return true;
+    }
+
+    ctx.players
+        .iter()
+        .filter(|player| player.id != target && player.team == me.team && player.alive)
+        .any(|player| in_firing_line(me.pos, target_player.pos, player.pos))
+}
+
+/// The nearest live enemy of our player within `range` of the target,
+/// with its position
+fn nearest_threat(ctx: &WingContext, range: f32) -> Option<(u16, protocol::Position)> {
+    ctx.enemies()
+        .map(|player| {
+            (
+                player.id,
+                player.pos,
+                (player.pos - ctx.target).length().inner(),
+            )
+        })
+        .filter(|&(_, _, dist)| dist < range)
+        .min_by(|a, b| a.2.partial_cmp(&b.2).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(id, pos, _)| (id, pos))
+}
+
+/// True if a live enemy is within `REPEL_RANGE` of us
+fn enemy_in_repel_range(ctx: &WingContext) -> bool {
+    let me = ctx.me;
+    ctx.players.iter().any(|player| {
+        player.team != me.team
+            && player.alive
+            && (player.pos - me.pos).length().inner() < REPEL_RANGE
+    })
+}
+
+/// The biggest threat among enemies near a protected player
+///
+/// Each candidate is an enemy's ID, its distance from the protected player,
+/// and how fast it's approaching them, in world units per second. Distances
+/// are split into bands `THREAT_TIE_DIST` wide, counting out from the
+/// protected player. The biggest threat is in the nearest band; enemies in
+/// the same band count as equally near and the faster approaching one wins.
+/// Any remaining tie goes to the lowest ID.
+///
+/// Enemies close together but either side of a band's edge aren't tied:
+/// the nearer one wins however fast the other approaches. Comparing each
+/// pair's gap instead wouldn't give a consistent order.
+fn biggest_threat<I>(candidates: I) -> Option<u16>
+where
+    I: IntoIterator<Item = (u16, f32, f32)>,
+{
+    candidates
+        .into_iter()
+        .min_by(|a, b| {
+            let band = |dist: f32| (dist / THREAT_TIE_DIST) as u32;
+            band(a.1)
+                .cmp(&band(b.1))
+                .then_with(|| b.2.partial_cmp(&a.2).unwrap_or(std::cmp::Ordering::Equal))
+                .then_with(|| a.0.cmp(&b.0))
+        })
+        .map(|(id, _, _)| id)
+}
+
+/// How far an enemy was from the protected player when that last changed,
+/// and how fast it was approaching
+#[derive(Clone, Copy)]
+struct Approach {
+    dist: f32,
+    at: time::Instant,
+    /// World units per second; negative when moving away
+    speed: f32,
+}
+
+/// Picks and keeps track of the enemy a protecting wingman is hunting
+#[derive(Default)]
+struct ThreatTracker {
+    /// The enemy we're hunting, if any
+    hunted: Option<u16>,
+    /// When we last picked an enemy to hunt
+    chosen_at: Option<time::Instant>,
+    /// How each enemy within range is approaching the protected player
+    approaches: HashMap<u16, Approach>,
+}
+
+impl ThreatTracker {
+    /// Update the enemies near our player, and return the one to hunt and
+    /// where it is, if any
+    ///
+    /// The biggest threat is picked again every `THREAT_RESELECT_TIME`, or
+    /// straight away if the hunted enemy dies or leaves the radius.
+    fn update(&mut self, ctx: &WingContext) -> Option<(u16, protocol::Position)> {
+        let now = ctx.now;
+        let protected = match ctx.find(ctx.player) {
+            Some(protected) => protected,
+            None => {
+                self.hunted = None;
+                return None;
+            }
+        };
+
+        let mut approaches = HashMap::new();
+        let mut candidates = Vec::new();
+        for player in ctx.enemies() {
+            let dist = (player.pos - protected.pos).length().inner();
+            if dist > ctx.options.threat_radius {
+                continue;
+            }
+            // Positions only change when the server sends an update, so
+            // keep the last speed until the distance changes
+            let approach = match self.approaches.get(&player.id) {
+                Some(last) if last.dist == dist => *last,
+                Some(last) if now > last.at => {
+                    let elapsed = now - last.at;
+                    let secs = elapsed.as_secs() as f32 + elapsed.subsec_millis() as f32 / 1000.0;
+                    Approach {
+                        dist,
+                        at: now,
+                        speed: if secs > 0.0 {
+                            (last.dist - dist) / secs
+                        } else {
+                            last.speed
+                        },
+                    }
+                }
+                _ => Approach {
+                    dist,
+                    at: now,
+                    speed: 0.0,
+                },
+            };
+            approaches.insert(player.id, approach);
+            candidates.push((player.id, dist, approach.speed));
+        }
+        self.approaches = approaches;
+
+        let hunted_nearby = self
+            .hunted
+            .map_or(false, |id| self.approaches.contains_key(&id));
+        let due = self
+            .chosen_at
+            .map_or(true, |at| now - at >= THREAT_RESELECT_TIME);
+        if !hunted_nearby || due {
+            let threat = biggest_threat(candidates);
+            if threat != self.hunted {
+                log::debug!("protecting wingman now hunting {:?}", threat);
+            }
+            self.hunted = threat;
+            self.chosen_at = Some(now);
+        }
+
+        let id = self.hunted?;
+        ctx.find(id).map(|player| (id, player.pos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contact(id: u16, name: &str, team: u16, x: f32, y: f32) -> Contact {
+        Contact {
+            id,
+            name: name.to_owned(),
+            team: protocol::Team(team),
+            plane: protocol::PlaneType::Mohawk,
+            pos: protocol::Position::new(x, y),
+            alive: true,
+        }
+    }
+
+    /// Step `behavior` once as wingman 1, assigned to player 2, with
+    /// everyone in `players`
+    fn step(
+        behavior: &mut dyn Behavior,
+        players: &[Contact],
+        options: &Options,
+        now: time::Instant,
+    ) -> WingAction {
+        let me = players.iter().find(|player| player.id == 1).unwrap();
+        let target = behavior
+            .post()
+            .unwrap_or_else(|| players.iter().find(|player| player.id == 2).unwrap().pos);
+        behavior.step(&WingContext {
+            me,
+            players,
+            player: 2,
+            target,
+            target_lead: target,
+            slot: 0,
+            fire_dist: 500.0,
+            options,
+            now,
+        })
+    }
+
+    fn options() -> Options {
+        Options {
+            name_prefix: "WING-".to_owned(),
+            threat_radius: 1000.0,
+            ..Options::default()
+        }
+    }
+
+    #[test]
+    fn attack_fires_on_a_target_in_range() {
+        let options = options();
+        let now = time::Instant::now();
+        let mut players = vec![
+            contact(1, "WING-xplay-1", 1, 0.0, 0.0),
+            contact(2, "xplay", 2, 300.0, 0.0),
+        ];
+        let action = step(&mut Attack, &players, &options, now);
+        assert!(action.fire);
+        assert_eq!(action.aim, Some(players[1].pos));
+        assert_eq!(action.dest, formation_position(players[1].pos, 0));
+
+        players[1].pos = protocol::Position::new(3000.0, 0.0);
+        assert!(!step(&mut Attack, &players, &options, now).fire);
+    }
+
+    #[test]
+    fn attack_holds_fire_with_a_teammate_in_the_way() {
+        let mut options = options();
+        let now = time::Instant::now();
+        let players = vec![
+            contact(1, "WING-xplay-1", 1, 0.0, 0.0),
+            contact(2, "xplay", 2, 400.0, 0.0),
+            contact(3, "mate", 1, 200.0, 20.0),
+        ];
+        assert!(!step(&mut Attack, &players, &options, now).fire);
+
+        options.friendly_fire = true;
+        assert!(step(&mut Attack, &players, &options, now).fire);
+    }
+
+    #[test]
+    fn defend_engages_the_nearest_enemy_but_not_siblings() {
+        let options = options();
+        let now = time::Instant::now();
+        let players = vec![
+            contact(1, "WING-xplay-1", 3, 0.0, 0.0),
+            contact(2, "xplay", 2, 100.0, 0.0),
+            contact(3, "WING-xplay-2", 4, 150.0, 0.0),
+            contact(4, "foe", 5, 400.0, 0.0),
+            contact(5, "far", 5, 2000.0, 0.0),
+        ];
+        let action = step(&mut Defend, &players, &options, now);
+        assert_eq!(action.aim, Some(players[3].pos));
+        assert!(action.fire);
+        assert_eq!(action.dest, formation_position(players[1].pos, 0));
+    }
+
+    #[test]
+    fn protect_chases_the_threat() {
+        let options = options();
+        let now = time::Instant::now();
+        let players = vec![
+            contact(1, "WING-xplay-1", 3, 0.0, 0.0),
+            contact(2, "xplay", 2, 100.0, 0.0),
+            contact(4, "foe", 5, 900.0, 0.0),
+        ];
+        let mut protect = Protect::default();
+        let action = step(&mut protect, &players, &options, now);
+        assert_eq!(action.dest, formation_position(players[2].pos, 0));
+        assert_eq!(action.aim, Some(players[2].pos));
+        assert!(!action.fire);
+
+        // Nobody near the player, so we stay with them
+        let action = step(&mut protect, &players[..2], &options, now);
+        assert_eq!(action.dest, formation_position(players[1].pos, 0));
+        assert_eq!(action.aim, None);
+    }
+
+    #[test]
+    fn patrol_guards_its_point() {
+        let options = options();
+        let now = time::Instant::now();
+        let players = vec![
+            contact(1, "WING-xplay-1", 3, 5000.0, 5000.0),
+            contact(2, "xplay", 2, -5000.0, 0.0),
+            contact(4, "foe", 5, 5200.0, 5000.0),
+        ];
+        let mut patrol = for_orders(commands::Behavior::Patrol { x: 5000, y: 5000 }, now);
+        let point = protocol::Position::new(5000.0, 5000.0);
+        assert_eq!(patrol.post(), Some(point));
+
+        let action = step(&mut *patrol, &players, &options, now);
+        assert_eq!(
+            action.dest,
+            patrol_position(point, 0, time::Duration::from_secs(0))
+        );
+        assert_eq!(action.aim, Some(players[2].pos));
+        assert!(action.fire);
+        assert_eq!(for_orders(commands::Behavior::Attack, now).post(), None);
+    }
+
+    #[test]
+    fn biggest_threat_is_nearest() {
+        assert_eq!(biggest_threat(vec![]), None);
+        assert_eq!(
+            biggest_threat(vec![(1, 800.0, 0.0), (2, 300.0, -50.0), (3, 550.0, 400.0)]),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn biggest_threat_tie_goes_to_faster_approach() {
+        // Both are in the same band of distances
+        assert_eq!(
+            biggest_threat(vec![(1, 310.0, 20.0), (2, 360.0, 250.0)]),
+            Some(2)
+        );
+        // Moving away is no threat at all
+        assert_eq!(
+            biggest_threat(vec![(1, 310.0, -100.0), (2, 360.0, 0.0)]),
+            Some(2)
+        );
+        assert_eq!(
+            biggest_threat(vec![(7, 310.0, 100.0), (4, 360.0, 100.0)]),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn biggest_threat_bands_have_edges() {
+        // Only 20 apart, but either side of the edge at 400
+        assert_eq!(
+            biggest_threat(vec![(1, 390.0, 0.0), (2, 410.0, 500.0)]),
+            Some(1)
+        );
+        // 90 apart within a band
+        assert_eq!(
+            biggest_threat(vec![(1, 405.0, 0.0), (2, 495.0, 500.0)]),
+            Some(2)
+        );
+    }
+}